@@ -0,0 +1,99 @@
+//! A crate-local stand-in for std's unstable `Allocator` trait, so [`Arc`](crate::arc::Arc) and
+//! the channels built on it can take an allocator on stable Rust.
+
+use core::alloc::Layout;
+use core::fmt;
+use core::ptr::{self, NonNull};
+#[cfg(feature = "std")]
+use std::alloc::{GlobalAlloc, System};
+
+/// The allocator could not hand out a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.write_str("memory allocation failed",)
+    }
+}
+
+impl core::error::Error for AllocError {}
+
+/// Hands out blocks of memory and takes them back.
+///
+/// # Safety
+///
+/// A block returned by `allocate` must stay valid, and must not be handed out again, until it is
+/// passed to `deallocate`, even if the allocator is moved in the meantime (an `Arc` keeps its
+/// allocator inside the block it got from it).
+pub unsafe trait Allocator {
+    /// Allocates a block that fits `layout`. Zero-sized layouts are allowed.
+    fn allocate(&self, layout: Layout,) -> Result<NonNull<u8,>, AllocError,>;
+
+    /// Gives a block back.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `allocate` on this allocator, with the same `layout`, and must not
+    /// have been deallocated already.
+    unsafe fn deallocate(&self, ptr: NonNull<u8,>, layout: Layout,);
+}
+
+/// The global allocator, which `Box` and `Vec` use. The default wherever an allocator is taken.
+#[derive(Clone, Copy, Debug, Default,)]
+pub struct Global;
+
+/// A well-aligned, non-null pointer for zero-sized blocks, which never touch the allocator.
+fn dangling(layout: Layout,) -> NonNull<u8,> {
+    // SAFETY: the alignment is never zero.
+    unsafe { NonNull::new_unchecked(ptr::without_provenance_mut(layout.align(),),) }
+}
+
+// SAFETY: the blocks come straight from the global allocator.
+unsafe impl Allocator for Global {
+    fn allocate(&self, layout: Layout,) -> Result<NonNull<u8,>, AllocError,> {
+        if layout.size() == 0 {
+            return Ok(dangling(layout,),);
+        }
+        // SAFETY: the layout isn't zero-sized.
+        NonNull::new(unsafe { alloc::alloc::alloc(layout,) },).ok_or(AllocError,)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8,>, layout: Layout,) {
+        if layout.size() != 0 {
+            // SAFETY: allocated by `allocate` with this layout (guaranteed by the caller).
+            unsafe { alloc::alloc::dealloc(ptr.as_ptr(), layout,) }
+        }
+    }
+}
+
+// SAFETY: the blocks come straight from the system allocator.
+#[cfg(feature = "std")]
+unsafe impl Allocator for System {
+    fn allocate(&self, layout: Layout,) -> Result<NonNull<u8,>, AllocError,> {
+        if layout.size() == 0 {
+            return Ok(dangling(layout,),);
+        }
+        // SAFETY: the layout isn't zero-sized.
+        NonNull::new(unsafe { GlobalAlloc::alloc(self, layout,) },).ok_or(AllocError,)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8,>, layout: Layout,) {
+        if layout.size() != 0 {
+            // SAFETY: allocated by `allocate` with this layout (guaranteed by the caller).
+            unsafe { GlobalAlloc::dealloc(self, ptr.as_ptr(), layout,) }
+        }
+    }
+}
+
+// SAFETY: forwards to `A`, whose blocks outlive the reference.
+unsafe impl<A: Allocator + ?Sized,> Allocator for &A {
+    fn allocate(&self, layout: Layout,) -> Result<NonNull<u8,>, AllocError,> {
+        (**self).allocate(layout,)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8,>, layout: Layout,) {
+        // SAFETY: forwarded from our caller.
+        unsafe { (**self).deallocate(ptr, layout,) }
+    }
+}
@@ -1,38 +1,280 @@
-use std::cell::UnsafeCell;
-use std::mem::ManuallyDrop;
-use std::ops::Deref;
-use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::sync::atomic::fence;
-use std::{ptr::NonNull, sync::atomic::AtomicUsize};
-
-struct ArcData<T,> {
+//! A reference counted pointer with weak references, like `std::sync::Arc`.
+//!
+//! `T` may be unsized: `Arc<str>` and `Arc<[T]>` are built with `From` and `FromIterator`, and
+//! an `Arc<T>` is turned into an `Arc<dyn Trait>` with [`unsize_arc!`](crate::unsize_arc), since
+//! `CoerceUnsized` isn't available to us.
+//!
+//! The allocation comes from an [`Allocator`], [`Global`] unless one is passed to
+//! [`Arc::new_in`]. It is kept next to the counts and used again to free the allocation once the
+//! last `Weak` is gone.
+
+use crate::allocator::{Allocator, Global};
+use crate::sync::atomic::{AtomicUsize, fence};
+use crate::sync::cell::UnsafeCell;
+use crate::sync::{abort, spin_loop};
+use alloc::alloc::{Layout, dealloc, handle_alloc_error};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::mem::{self, ManuallyDrop, MaybeUninit};
+use core::ops::Deref;
+use core::ptr::{self, NonNull, copy_nonoverlapping};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+// repr(C), so the layout of an unsized ArcData can be computed by hand, see ArcData::layout.
+#[repr(C)]
+struct ArcData<T: ?Sized, A,> {
     ref_count: AtomicUsize,
     weak_count: AtomicUsize,
+    alloc: A,
     data: UnsafeCell<ManuallyDrop<T,>,>,
 }
 
-#[derive(Debug,)]
-pub struct Arc<T,> {
-    ptr: NonNull<ArcData<T,>,>,
+impl<T: ?Sized, A,> ArcData<T, A,> {
+    /// The layout of an `ArcData` holding a value with layout `value`, and the offset of the
+    /// value: the two counts and the allocator followed by the cell, padded to its alignment,
+    /// which is what the compiler computes for `repr(C)`.
+    fn layout(value: Layout,) -> (Layout, usize,) {
+        let (cell, offset,) = UnsafeCell::<ManuallyDrop<T,>,>::layout_for(value,);
+        let layout = Layout::new::<[AtomicUsize; 2],>()
+            .extend(Layout::new::<A,>(),)
+            .and_then(|(header, _,)| header.extend(cell,),);
+        match layout {
+            Ok((layout, cell_offset,),) => (layout.pad_to_align(), cell_offset + offset,),
+            Err(_,) => panic!("Arc allocation too large"),
+        }
+    }
+
+    /// Moves `data` into a block from its own allocator.
+    fn boxed(data: Self,) -> NonNull<Self,>
+    where
+        Self: Sized,
+        A: Allocator,
+    {
+        let layout = Layout::new::<Self,>();
+        let Ok(mem,) = data.alloc.allocate(layout,) else {
+            handle_alloc_error(layout,);
+        };
+        let ptr = mem.cast::<Self>();
+        // SAFETY: the block fits an ArcData and nobody else has it.
+        unsafe { ptr.write(data,) };
+        ptr
+    }
 }
 
-// SAFETY: not unsafe, we have to ensure that the weak can be Send if it is Sync
-unsafe impl<T: Send + Sync,> Send for Arc<T,> {}
+/// `meta` with its address replaced by `addr`, keeping the slice length or vtable. This is
+/// `ptr::from_raw_parts`, which isn't stable yet.
+fn with_metadata_of<T: ?Sized, A,>(addr: *mut u8, meta: *const T,) -> *mut ArcData<T, A,> {
+    let mut ptr = meta as *mut ArcData<T, A,>;
+    // SAFETY: the address is the first word of every pointer, thin or fat, and only that word is
+    // overwritten. This is how std implemented `set_ptr_value` before pointer metadata existed.
+    unsafe { (&raw mut ptr).cast::<*mut u8>().write(addr,) };
+    ptr
+}
+
+pub struct Arc<T: ?Sized, A: Allocator = Global,> {
+    ptr: NonNull<ArcData<T, A,>,>,
+}
+
+// SAFETY: not unsafe, we have to ensure that the weak can be Send if it is Sync. Whichever
+// thread drops the last Weak uses the allocator.
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send + Sync,> Send for Arc<T, A,> {}
 // SAFETY: not unsafe, we have to ensure that the weak can be Sync if it is Send
-unsafe impl<T: Send + Sync,> Sync for Arc<T,> {}
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send + Sync,> Sync for Arc<T, A,> {}
 
 impl<T,> Arc<T,> {
     pub fn new(data: T,) -> Arc<T,> {
+        Arc::new_in(data, Global,)
+    }
+
+    /// Creates an `Arc` of a value that holds a `Weak` to itself. `f` gets that `Weak`, which
+    /// can't be upgraded until `new_cyclic` returns, on any thread.
+    pub fn new_cyclic(f: impl FnOnce(&Weak<T,>,) -> T,) -> Arc<T,> {
+        Arc::new_cyclic_in(f, Global,)
+    }
+}
+
+impl<T, A: Allocator,> Arc<T, A,> {
+    /// Like [`Arc::new`], with the allocation coming from `alloc`.
+    pub fn new_in(data: T, alloc: A,) -> Arc<T, A,> {
         Arc {
-            ptr: NonNull::from(Box::leak(Box::new(ArcData {
+            ptr: ArcData::boxed(ArcData {
                 ref_count: AtomicUsize::new(1,),
                 weak_count: AtomicUsize::new(1,),
+                alloc,
                 data: UnsafeCell::new(ManuallyDrop::new(data,),),
-            },),),),
+            },),
         }
     }
 
-    fn data(&self,) -> &ArcData<T,> {
+    /// Like [`Arc::new_cyclic`], with the allocation coming from `alloc`.
+    pub fn new_cyclic_in(f: impl FnOnce(&Weak<T, A,>,) -> T, alloc: A,) -> Arc<T, A,> {
+        // No Arc yet, the weak count is the Weak handed to `f`.
+        let ptr = ArcData::boxed(ArcData {
+            ref_count: AtomicUsize::new(0,),
+            weak_count: AtomicUsize::new(1,),
+            alloc,
+            data: UnsafeCell::new(ManuallyDrop::new(MaybeUninit::<T,>::uninit(),),),
+        },)
+        .cast::<ArcData<T, A,>>();
+        let weak = Weak { ptr, };
+        let data = f(&weak,);
+        // SAFETY: the ArcData is alive, `weak` holds it. Nobody can read the data while the
+        // ref_count is 0, and MaybeUninit<T> has the layout of T.
+        let inner = unsafe { ptr.as_ref() };
+        // SAFETY: as above.
+        unsafe { inner.data.write_ptr().write(ManuallyDrop::new(data,),) };
+        // Release pairs with the Acquire in Weak::upgrade: a clone of `weak` may already be on
+        // another thread, waiting for the data.
+        inner.ref_count.store(1, Release,);
+        // The Weak's count becomes the one all the Arcs share.
+        mem::forget(weak,);
+        Arc { ptr, }
+    }
+
+    /// Returns the value if this is the only `Arc`, otherwise the `Arc` itself. Weak pointers
+    /// don't count, they can no longer upgrade afterwards.
+    pub fn try_unwrap(arc: Self,) -> Result<T, Self,> {
+        // Acquire pairs with the Release decrement in Drop, like the fence there.
+        if arc.data().ref_count.compare_exchange(1, 0, Acquire, Relaxed,).is_err() {
+            return Err(arc,);
+        }
+        let arc = ManuallyDrop::new(arc,);
+        // SAFETY: the count went from 1 to 0, nobody else can get to the data, and there is no
+        // Arc left to drop it.
+        let data = unsafe { ManuallyDrop::take(&mut *arc.data().data.write_ptr(),) };
+        drop(Weak { ptr: arc.ptr, },);
+        Ok(data,)
+    }
+
+    /// Drops the `Arc`, returning the value if it was the last one. Unlike `try_unwrap`, when
+    /// several threads do this with clones of the same `Arc`, exactly one of them gets the value.
+    pub fn into_inner(arc: Self,) -> Option<T,> {
+        let arc = ManuallyDrop::new(arc,);
+        // The same as Drop, but the last one takes the data instead of dropping it.
+        if arc.data().ref_count.fetch_sub(1, Release,) != 1 {
+            return None;
+        }
+        fence(Acquire,);
+        // SAFETY: this was the last Arc, see Drop.
+        let data = unsafe { ManuallyDrop::take(&mut *arc.data().data.write_ptr(),) };
+        drop(Weak { ptr: arc.ptr, },);
+        Some(data,)
+    }
+
+    /// Turns the `Arc` into one of an unsized type, like `Arc<[T; N]>` into `Arc<[T]>` or
+    /// `Arc<T>` into `Arc<dyn Trait>`, without touching the counts. [`unsize_arc!`](crate::unsize_arc)
+    /// does this safely.
+    ///
+    /// # Safety
+    ///
+    /// `f` must return its argument unsized to `U`: the same address, with the length or vtable
+    /// of `T` as a `U`. An unsizing coercion (`|p| -> *const U { p }`) does exactly that.
+    pub unsafe fn unsize<U: ?Sized,>(
+        arc: Self,
+        f: impl FnOnce(*const T,) -> *const U,
+    ) -> Arc<U, A,> {
+        let arc = ManuallyDrop::new(arc,);
+        let ptr = arc.ptr.as_ptr();
+        // SAFETY: `ptr` points to a live ArcData.
+        let data = unsafe { UnsafeCell::raw_get(&raw const (*ptr).data,) }.cast_const().cast::<T>();
+        // SAFETY: `f` keeps the address (guaranteed by the caller), and with the metadata of a
+        // `U` the data sits at the same offset, since the alignment is the one of `T`. Stepping
+        // back from the data to the start of the allocation keeps its provenance.
+        unsafe {
+            let offset = data.byte_offset_from(ptr,);
+            let ptr = f(data,).byte_offset(-offset,) as *mut ArcData<U, A,>;
+            Arc { ptr: NonNull::new_unchecked(ptr,), }
+        }
+    }
+}
+
+impl<T: Clone, A: Allocator,> Arc<T, A,> {
+    /// Returns the value if this is the only `Arc`, otherwise a clone of it.
+    pub fn unwrap_or_clone(arc: Self,) -> T {
+        Arc::try_unwrap(arc,).unwrap_or_else(|arc| T::clone(&arc,),)
+    }
+}
+
+impl<T: Clone, A: Allocator + Clone,> Arc<T, A,> {
+    /// A mutable reference to the value, cloning it into a new allocation first if other `Arc`s
+    /// share it. Weak pointers are disassociated instead: they stay with the old allocation and
+    /// can no longer upgrade.
+    pub fn make_mut(arc: &mut Self,) -> &mut T {
+        // Acquire pairs with the Release decrement of the other Arcs, like in try_unwrap. While
+        // the count is 0 no Weak can upgrade.
+        if arc.data().ref_count.compare_exchange(1, 0, Acquire, Relaxed,).is_err() {
+            *arc = Arc::new_in(T::clone(arc,), arc.data().alloc.clone(),);
+        } else if arc.data().weak_count.load(Relaxed,) != 1 {
+            // SAFETY: the count is 0, nobody else can get to the data.
+            let data = unsafe { ManuallyDrop::take(&mut *arc.data().data.write_ptr(),) };
+            let alloc = arc.data().alloc.clone();
+            let old = ManuallyDrop::new(mem::replace(arc, Arc::new_in(data, alloc,),),);
+            // The old Arc gives up its share of the weak count, the Weaks free the allocation.
+            drop(Weak { ptr: old.ptr, },);
+        } else {
+            // No other Arc and no Weak, and only we could make new ones.
+            arc.data().ref_count.store(1, Release,);
+        }
+        // SAFETY: the Arc is unique now.
+        unsafe { &mut *arc.data().data.write_ptr() }
+    }
+}
+
+impl<T: ?Sized,> Arc<T,> {
+    /// Takes back an `Arc` given up by [`Arc::into_raw`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Arc::<T>::into_raw`, and each such pointer may only be turned back
+    /// into an `Arc` once.
+    pub unsafe fn from_raw(ptr: *const T,) -> Self {
+        // SAFETY: forwarded from our caller.
+        unsafe { Arc::from_raw_in(ptr,) }
+    }
+}
+
+impl<T: ?Sized, A: Allocator,> Arc<T, A,> {
+    /// Allocates an `ArcData` for a value with layout `value`, with both counts at 1 and the
+    /// value left uninitialised. `meta` only supplies the slice length or vtable.
+    fn allocate(value: Layout, meta: *const T, alloc: A,) -> NonNull<ArcData<T, A,>,> {
+        let (layout, _,) = ArcData::<T, A,>::layout(value,);
+        let Ok(mem,) = alloc.allocate(layout,) else {
+            handle_alloc_error(layout,);
+        };
+        let ptr = with_metadata_of::<T, A,>(mem.as_ptr(), meta,);
+        // SAFETY: the allocation is big enough for the whole ArcData and nobody else has it.
+        unsafe {
+            (&raw mut (*ptr).ref_count).write(AtomicUsize::new(1,),);
+            (&raw mut (*ptr).weak_count).write(AtomicUsize::new(1,),);
+            (&raw mut (*ptr).alloc).write(alloc,);
+            UnsafeCell::init_header(&raw mut (*ptr).data,);
+            NonNull::new_unchecked(ptr,)
+        }
+    }
+
+    /// Moves the value at `src`, with layout `value`, into a new `Arc`.
+    ///
+    /// # Safety
+    ///
+    /// `src` must point to a valid value, which the caller must not use or drop afterwards.
+    unsafe fn copy_from(src: *const T, value: Layout, alloc: A,) -> Arc<T, A,> {
+        let ptr = Self::allocate(value, src, alloc,);
+        // SAFETY: the value is valid for reads (guaranteed by the caller), and the new
+        // allocation has room for it.
+        unsafe {
+            let data = UnsafeCell::raw_get(&raw const (*ptr.as_ptr()).data,);
+            copy_nonoverlapping(src.cast::<u8>(), data.cast::<u8>(), value.size(),);
+            debug_assert_eq!(Layout::for_value(ptr.as_ref()), ArcData::<T, A,>::layout(value).0);
+        }
+        Arc { ptr, }
+    }
+
+    fn data(&self,) -> &ArcData<T, A,> {
         // SAFETY: the pointer will always have valid ArcData<T> as long as the Arc object exists
         // (see new() and drop() impl). When dropping the last Arc, it also drops the ArcData.
         unsafe { self.ptr.as_ref() }
@@ -55,14 +297,14 @@ impl<T,> Arc<T,> {
         // the data.
         fence(Acquire,);
         // SAFETY: if there is an Arc, there is guarenteed to be data.
-        unsafe { Some(&mut *arc.data().data.get(),) }
+        unsafe { Some(&mut *arc.data().data.write_ptr(),) }
     }
 
-    pub fn downgrade(arc: &Self,) -> Weak<T,> {
+    pub fn downgrade(arc: &Self,) -> Weak<T, A,> {
         let mut n = arc.data().weak_count.load(Relaxed,);
         loop {
             if n == usize::MAX {
-                std::hint::spin_loop();
+                spin_loop();
                 n = arc.data().weak_count.load(Relaxed,);
                 continue;
             }
@@ -77,28 +319,85 @@ impl<T,> Arc<T,> {
             return Weak { ptr: arc.ptr, };
         }
     }
+
+    /// The allocator the `Arc` was allocated with.
+    pub fn allocator(arc: &Self,) -> &A {
+        &arc.data().alloc
+    }
+
+    /// The number of `Arc`s sharing the value. Other threads may change it at any time.
+    pub fn strong_count(arc: &Self,) -> usize {
+        arc.data().ref_count.load(Relaxed,)
+    }
+
+    /// The number of `Weak`s pointing to the value. Other threads may change it at any time.
+    pub fn weak_count(arc: &Self,) -> usize {
+        match arc.data().weak_count.load(Relaxed,) {
+            // Locked by get_mut, which only happens while there are no Weaks.
+            usize::MAX => 0,
+            // One for all the Arcs together.
+            n => n - 1,
+        }
+    }
+
+    /// Whether both `Arc`s point to the same allocation (not whether the values are equal).
+    pub fn ptr_eq(a: &Self, b: &Self,) -> bool {
+        ptr::addr_eq(a.ptr.as_ptr(), b.ptr.as_ptr(),)
+    }
+
+    /// A pointer to the value, valid as long as any `Arc` to it is alive.
+    pub fn as_ptr(arc: &Self,) -> *const T {
+        // SAFETY: the ArcData is alive as long as `arc` is.
+        unsafe { UnsafeCell::raw_get(&raw const (*arc.ptr.as_ptr()).data,) as *const T }
+    }
+
+    /// Gives up the `Arc` without decrementing the count, returning a pointer to the value. Use
+    /// [`Arc::from_raw`] to get the `Arc` back, or the value leaks.
+    pub fn into_raw(arc: Self,) -> *const T {
+        let ptr = Arc::as_ptr(&arc,);
+        mem::forget(arc,);
+        ptr
+    }
+
+    /// [`Arc::from_raw`] for an `Arc` with another allocator. Unlike std's, it doesn't take the
+    /// allocator, it is found next to the value.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Arc::<T, A>::into_raw`, and each such pointer may only be turned
+    /// back into an `Arc` once.
+    pub unsafe fn from_raw_in(ptr: *const T,) -> Self {
+        // SAFETY: the Arc given up by into_raw keeps the value alive.
+        let value = Layout::for_value(unsafe { &*ptr },);
+        let (_, offset,) = ArcData::<T, A,>::layout(value,);
+        // SAFETY: the value sits `offset` bytes into its ArcData, stepping back stays inside the
+        // allocation and keeps its provenance.
+        unsafe {
+            Arc { ptr: NonNull::new_unchecked(ptr.byte_sub(offset,) as *mut ArcData<T, A,>,), }
+        }
+    }
 }
 
-impl<T,> Deref for Arc<T,> {
+impl<T: ?Sized, A: Allocator,> Deref for Arc<T, A,> {
     type Target = T;
 
     fn deref(&self,) -> &T {
         // SAFETY: if there is an Active Arc, there is data
-        unsafe { &*self.data().data.get() }
+        unsafe { &*self.data().data.read_ptr() }
     }
 }
 
-impl<T,> Clone for Arc<T,> {
+impl<T: ?Sized, A: Allocator,> Clone for Arc<T, A,> {
     fn clone(&self,) -> Self {
         // NOTE: There should be a cleaner way to handle usize overflows. (no its not doing usize::MAX - 1)
         if self.data().ref_count.fetch_add(1, Relaxed,) > usize::MAX / 2 {
-            std::process::abort();
+            abort();
         }
         Arc { ptr: self.ptr, }
     }
 }
 
-impl<T,> Drop for Arc<T,> {
+impl<T: ?Sized, A: Allocator,> Drop for Arc<T, A,> {
     fn drop(&mut self,) {
         // TODO: add memory ordering.
         if self.data().ref_count.fetch_sub(1, Release,) == 1 {
@@ -109,64 +408,493 @@ impl<T,> Drop for Arc<T,> {
             fence(Acquire,);
             // SAFETY: see comment in Arc::data()
             unsafe {
-                ManuallyDrop::drop(&mut *self.data().data.get(),);
+                ManuallyDrop::drop(&mut *self.data().data.write_ptr(),);
             }
             drop(Weak { ptr: self.ptr, },);
         }
     }
 }
 
-pub struct Weak<T,> {
-    ptr: NonNull<ArcData<T,>,>,
+/// A pointer that doesn't keep the value alive, only its allocation. [`Weak::new`] makes one
+/// without an allocation, its address is `usize::MAX`.
+pub struct Weak<T: ?Sized, A: Allocator = Global,> {
+    ptr: NonNull<ArcData<T, A,>,>,
 }
 
 // SAFETY: not unsafe, we have to ensure that the weak can be Send if it is Sync
-unsafe impl<T: Send + Sync,> Send for Weak<T,> {}
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send + Sync,> Send for Weak<T, A,> {}
 // SAFETY: not unsafe, we have to ensure that the weak can be Sync if it is Send
-unsafe impl<T: Send + Sync,> Sync for Weak<T,> {}
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send + Sync,> Sync for Weak<T, A,> {}
 
 impl<T,> Weak<T,> {
-    fn data(&self,) -> &ArcData<T,> {
+    /// A `Weak` without an allocation, which never upgrades.
+    pub const fn new() -> Weak<T,> {
+        // SAFETY: usize::MAX isn't null. No ArcData can start there either, the counts alone
+        // take more than one byte.
+        Weak { ptr: unsafe { NonNull::new_unchecked(ptr::without_provenance_mut(usize::MAX,),) }, }
+    }
+}
+
+impl<T: ?Sized, A: Allocator,> Weak<T, A,> {
+    /// The ArcData, unless this is a `Weak::new()`.
+    fn data(&self,) -> Option<&ArcData<T, A,>,> {
+        if self.ptr.as_ptr().cast::<()>().addr() == usize::MAX {
+            return None;
+        }
         // SAFETY: if there is a weak, there is guarenteed to be data.
-        unsafe { self.ptr.as_ref() }
+        Some(unsafe { self.ptr.as_ref() },)
     }
 
-    pub fn upgrade(&self,) -> Option<Arc<T,>,> {
-        let mut n = self.data().ref_count.load(Relaxed,);
+    pub fn upgrade(&self,) -> Option<Arc<T, A,>,> {
+        let data = self.data()?;
+        let mut n = data.ref_count.load(Relaxed,);
         loop {
             if n == 0 {
                 return None;
             }
             assert!(n < usize::MAX);
-            if let Err(e,) =
-                self.data().ref_count.compare_exchange_weak(n, n + 1, Relaxed, Relaxed,)
-            {
+            // Acquire pairs with the Release store in Arc::new_cyclic, which may publish the
+            // data after this Weak was handed out.
+            if let Err(e,) = data.ref_count.compare_exchange_weak(n, n + 1, Acquire, Relaxed,) {
                 n = e;
                 continue;
             }
             return Some(Arc { ptr: self.ptr, },);
         }
     }
+
+    /// The number of `Arc`s sharing the value, 0 once it has been dropped.
+    pub fn strong_count(&self,) -> usize {
+        self.data().map_or(0, |data| data.ref_count.load(Relaxed,),)
+    }
+
+    /// The number of `Weak`s pointing to the value, including this one, or 0 once the value
+    /// has been dropped.
+    pub fn weak_count(&self,) -> usize {
+        let Some(data,) = self.data() else {
+            return 0;
+        };
+        let weak = data.weak_count.load(Acquire,);
+        if data.ref_count.load(Relaxed,) == 0 {
+            0
+        } else {
+            // One for all the Arcs together.
+            weak - 1
+        }
+    }
+
+    /// Whether both `Weak`s point to the same allocation, or were both made by `Weak::new`.
+    pub fn ptr_eq(&self, other: &Self,) -> bool {
+        ptr::addr_eq(self.ptr.as_ptr(), other.ptr.as_ptr(),)
+    }
 }
 
-impl<T,> Clone for Weak<T,> {
+impl<T: ?Sized, A: Allocator,> Clone for Weak<T, A,> {
     fn clone(&self,) -> Self {
-        if self.data().weak_count.fetch_add(1, Relaxed,) > usize::MAX / 2 {
-            std::process::abort();
+        if let Some(data,) = self.data()
+            && data.weak_count.fetch_add(1, Relaxed,) > usize::MAX / 2
+        {
+            abort();
         }
         Weak { ptr: self.ptr, }
     }
 }
 
-impl<T,> Drop for Weak<T,> {
+impl<T,> Default for Weak<T,> {
+    fn default() -> Self {
+        Weak::new()
+    }
+}
+
+impl<T: ?Sized, A: Allocator,> fmt::Debug for Weak<T, A,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        write!(f, "(Weak)")
+    }
+}
+
+impl<T: ?Sized, A: Allocator,> Drop for Weak<T, A,> {
     fn drop(&mut self,) {
-        if self.data().weak_count.fetch_sub(1, Release,) == 1 {
+        let Some(data,) = self.data() else {
+            return;
+        };
+        if data.weak_count.fetch_sub(1, Release,) == 1 {
             fence(Acquire,);
+            let ptr = self.ptr.as_ptr();
             // SAFETY: if there is only one 'weak' (weak + 1 for any amount of Arc's) then there is
-            // guarenteed to be a ptr.
+            // guarenteed to be a ptr. The value has been dropped already, for_value only looks at
+            // its size and alignment. The allocator is moved out before its block is freed.
             unsafe {
-                drop(Box::from_raw(self.ptr.as_ptr(),),);
+                let layout = Layout::for_value(&*ptr,);
+                let alloc = ptr::read(&raw const (*ptr).alloc,);
+                alloc.deallocate(self.ptr.cast(), layout,);
+            }
+        }
+    }
+}
+
+impl<T: ?Sized + PartialEq, A: Allocator,> PartialEq for Arc<T, A,> {
+    fn eq(&self, other: &Self,) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: ?Sized + Eq, A: Allocator,> Eq for Arc<T, A,> {}
+
+impl<T: ?Sized + PartialOrd, A: Allocator,> PartialOrd for Arc<T, A,> {
+    fn partial_cmp(&self, other: &Self,) -> Option<Ordering,> {
+        (**self).partial_cmp(&**other,)
+    }
+}
+
+impl<T: ?Sized + Ord, A: Allocator,> Ord for Arc<T, A,> {
+    fn cmp(&self, other: &Self,) -> Ordering {
+        (**self).cmp(&**other,)
+    }
+}
+
+impl<T: ?Sized + Hash, A: Allocator,> Hash for Arc<T, A,> {
+    fn hash<H: Hasher,>(&self, state: &mut H,) {
+        (**self).hash(state,);
+    }
+}
+
+impl<T: ?Sized + fmt::Display, A: Allocator,> fmt::Display for Arc<T, A,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        fmt::Display::fmt(&**self, f,)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, A: Allocator,> fmt::Debug for Arc<T, A,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f,)
+    }
+}
+
+impl<T: ?Sized, A: Allocator,> fmt::Pointer for Arc<T, A,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        fmt::Pointer::fmt(&Arc::as_ptr(self,), f,)
+    }
+}
+
+impl<T: Default,> Default for Arc<T,> {
+    fn default() -> Self {
+        Arc::new(T::default(),)
+    }
+}
+
+impl<T: ?Sized, A: Allocator,> Borrow<T,> for Arc<T, A,> {
+    fn borrow(&self,) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized, A: Allocator,> AsRef<T,> for Arc<T, A,> {
+    fn as_ref(&self,) -> &T {
+        self
+    }
+}
+
+impl<T,> From<T,> for Arc<T,> {
+    fn from(data: T,) -> Self {
+        Arc::new(data,)
+    }
+}
+
+impl From<&str,> for Arc<str,> {
+    fn from(s: &str,) -> Self {
+        // SAFETY: `str` is `Copy` data, the original stays usable.
+        unsafe { Arc::copy_from(s, Layout::for_value(s,), Global,) }
+    }
+}
+
+impl From<String,> for Arc<str,> {
+    fn from(s: String,) -> Self {
+        Arc::from(s.as_str(),)
+    }
+}
+
+impl<T: Clone,> From<&[T],> for Arc<[T],> {
+    fn from(s: &[T],) -> Self {
+        Arc::from(s.to_vec(),)
+    }
+}
+
+impl<T,> From<Vec<T,>,> for Arc<[T],> {
+    fn from(mut v: Vec<T,>,) -> Self {
+        // SAFETY: the elements are moved into the Arc, and emptying the Vec makes sure it only
+        // frees its buffer.
+        unsafe {
+            let arc = Arc::copy_from(v.as_slice(), Layout::for_value(v.as_slice(),), Global,);
+            v.set_len(0,);
+            arc
+        }
+    }
+}
+
+impl<T: ?Sized,> From<Box<T,>,> for Arc<T,> {
+    fn from(b: Box<T,>,) -> Self {
+        let value = Layout::for_value(&*b,);
+        let raw = Box::into_raw(b,);
+        // SAFETY: the value is moved into the Arc, then the Box's memory is freed without
+        // dropping it. A zero-sized Box never allocated.
+        unsafe {
+            let arc = Arc::copy_from(raw, value, Global,);
+            if value.size() != 0 {
+                dealloc(raw.cast::<u8>(), value,);
+            }
+            arc
+        }
+    }
+}
+
+impl<T,> FromIterator<T,> for Arc<[T],> {
+    fn from_iter<I: IntoIterator<Item = T,>,>(iter: I,) -> Self {
+        Arc::from(iter.into_iter().collect::<Vec<T,>>(),)
+    }
+}
+
+/// Serialises the value, as if there were no `Arc`. Every clone is written out in full, see
+/// [`shared`] to keep them shared.
+#[cfg(feature = "serde")]
+impl<T: ?Sized + serde::Serialize, A: Allocator,> serde::Serialize for Arc<T, A,> {
+    fn serialize<S: serde::Serializer,>(&self, serializer: S,) -> Result<S::Ok, S::Error,> {
+        (**self).serialize(serializer,)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de,>,> serde::Deserialize<'de,> for Arc<T,> {
+    fn deserialize<D: serde::Deserializer<'de,>,>(deserializer: D,) -> Result<Self, D::Error,> {
+        T::deserialize(deserializer,).map(Arc::new,)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de,> serde::Deserialize<'de,> for Arc<str,> {
+    fn deserialize<D: serde::Deserializer<'de,>,>(deserializer: D,) -> Result<Self, D::Error,> {
+        String::deserialize(deserializer,).map(Arc::from,)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de,>,> serde::Deserialize<'de,> for Arc<[T],> {
+    fn deserialize<D: serde::Deserializer<'de,>,>(deserializer: D,) -> Result<Self, D::Error,> {
+        Vec::<T,>::deserialize(deserializer,).map(Arc::from,)
+    }
+}
+
+/// Serialises `Arc`s so that clones stay clones: the first time an allocation is seen it's written
+/// as the variant `Def(id, value)`, after that as `Ref(id)`, and deserialising hands out clones of
+/// the one `Arc` built for `id`. Fields opt in with `#[serde(with = "atomics_locks::arc::shared")]`,
+/// and the ids only mean something within one [`scope`](shared::scope), which both sides must
+/// run in:
+///
+/// ```
+/// use atomics_locks::arc::{Arc, shared};
+///
+/// let a = Arc::new(String::from("shared"));
+/// let (first, again) = shared::scope(|| {
+///     let first = serde_json::to_string(&shared::Ser(&a));
+///     (first, serde_json::to_string(&shared::Ser(&a)))
+/// });
+/// assert_eq!(first.ok().as_deref(), Some(r#"{"Def":[0,"shared"]}"#));
+/// assert_eq!(again.ok().as_deref(), Some(r#"{"Ref":0}"#));
+/// ```
+#[cfg(all(feature = "serde", feature = "std"))]
+pub mod shared {
+    use super::Arc;
+    use core::any::Any;
+    use serde::de::Error as _;
+    use serde::de::{EnumAccess, VariantAccess, Visitor};
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::marker::PhantomData;
+
+    // NOTE: an enum rather than `(id, Option<T>)`, which can't tell a back-reference from the
+    // definition of a value that serialises as `None` itself, like `()`.
+    const VARIANTS: &[&str] = &["Def", "Ref",];
+
+    #[derive(Default,)]
+    struct Tables {
+        depth: usize,
+        // NOTE: allocation address to id, while serialising, with a clone of the `Arc` that keeps
+        // the allocation alive, so its address isn't reused by another one within the scope.
+        ids: HashMap<usize, (u64, Box<dyn Any,>,),>,
+        // NOTE: id to an `Arc<T>` of whatever `T` it was, while deserialising.
+        arcs: HashMap<u64, Box<dyn Any,>,>,
+    }
+
+    std::thread_local! {
+        static TABLES: RefCell<Tables,> = RefCell::default();
+    }
+
+    /// Runs `f` with a fresh table of shared `Arc`s, which is dropped once it returns. Nested
+    /// scopes use the outermost one.
+    pub fn scope<U,>(f: impl FnOnce() -> U,) -> U {
+        struct Exit;
+
+        impl Drop for Exit {
+            fn drop(&mut self,) {
+                TABLES.with_borrow_mut(|t| {
+                    t.depth -= 1;
+                    if t.depth == 0 {
+                        *t = Tables::default();
+                    }
+                },);
+            }
+        }
+
+        TABLES.with_borrow_mut(|t| t.depth += 1,);
+        let _exit = Exit;
+        f()
+    }
+
+    fn in_scope() -> bool {
+        TABLES.with_borrow(|t| t.depth > 0,)
+    }
+
+    /// For `#[serde(with)]`: writes the value the first time this allocation is seen in the scope.
+    pub fn serialize<T: Serialize + 'static, S: Serializer,>(
+        arc: &Arc<T,>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error,> {
+        if !in_scope() {
+            return Err(S::Error::custom("shared Arc serialised outside of arc::shared::scope",),);
+        }
+        let (id, first,) = TABLES.with_borrow_mut(|t| {
+            let next = t.ids.len() as u64;
+            match t.ids.entry(Arc::as_ptr(arc,) as usize,) {
+                std::collections::hash_map::Entry::Occupied(e,) => (e.get().0, false,),
+                std::collections::hash_map::Entry::Vacant(e,) => {
+                    e.insert((next, Box::new(arc.clone(),),),);
+                    (next, true,)
+                }
             }
+        },);
+        // The table isn't borrowed any more: the value may hold shared `Arc`s of its own.
+        if first {
+            serializer.serialize_newtype_variant("Shared", 0, VARIANTS[0], &(id, &**arc,),)
+        } else {
+            serializer.serialize_newtype_variant("Shared", 1, VARIANTS[1], &id,)
         }
     }
+
+    /// For `#[serde(with)]`: builds the `Arc` the first time its id is seen in the scope, and
+    /// clones it after that.
+    pub fn deserialize<'de, T: Deserialize<'de,> + 'static, D: Deserializer<'de,>,>(
+        deserializer: D,
+    ) -> Result<Arc<T,>, D::Error,> {
+        if !in_scope() {
+            return Err(D::Error::custom("shared Arc deserialised outside of arc::shared::scope",),);
+        }
+        let (id, value,) =
+            deserializer.deserialize_enum("Shared", VARIANTS, SharedVisitor::<T,>(PhantomData,),)?;
+        match value {
+            Some(value,) => {
+                let arc = Arc::new(value,);
+                let clone: Box<dyn Any,> = Box::new(arc.clone(),);
+                if TABLES.with_borrow_mut(|t| t.arcs.insert(id, clone,),).is_some() {
+                    return Err(D::Error::custom(format_args!("shared Arc {id} defined twice"),),);
+                }
+                Ok(arc,)
+            }
+            None => TABLES
+                .with_borrow(|t| {
+                    t.arcs.get(&id,).and_then(|a| a.downcast_ref::<Arc<T,>>(),).cloned()
+                },)
+                .ok_or_else(|| {
+                    D::Error::custom(format_args!("shared Arc {id} used before it was defined"),)
+                },),
+        }
+    }
+
+    enum Variant {
+        Def,
+        Ref,
+    }
+
+    impl<'de,> Deserialize<'de,> for Variant {
+        fn deserialize<D: Deserializer<'de,>,>(deserializer: D,) -> Result<Self, D::Error,> {
+            struct VariantVisitor;
+
+            impl Visitor<'_,> for VariantVisitor {
+                type Value = Variant;
+
+                fn expecting(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+                    f.write_str("`Def` or `Ref`",)
+                }
+
+                fn visit_u64<E: serde::de::Error,>(self, v: u64,) -> Result<Variant, E,> {
+                    match v {
+                        0 => Ok(Variant::Def,),
+                        1 => Ok(Variant::Ref,),
+                        _ => Err(E::invalid_value(serde::de::Unexpected::Unsigned(v,), &self,),),
+                    }
+                }
+
+                fn visit_str<E: serde::de::Error,>(self, v: &str,) -> Result<Variant, E,> {
+                    match v {
+                        "Def" => Ok(Variant::Def,),
+                        "Ref" => Ok(Variant::Ref,),
+                        _ => Err(E::unknown_variant(v, VARIANTS,),),
+                    }
+                }
+            }
+
+            deserializer.deserialize_identifier(VariantVisitor,)
+        }
+    }
+
+    /// Reads `Def(id, value)` or `Ref(id)` as the id and, for a `Def`, the value.
+    struct SharedVisitor<T,>(PhantomData<fn() -> T,>,);
+
+    impl<'de, T: Deserialize<'de,>,> Visitor<'de,> for SharedVisitor<T,> {
+        type Value = (u64, Option<T,>,);
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+            f.write_str("a shared Arc",)
+        }
+
+        fn visit_enum<A: EnumAccess<'de,>,>(self, data: A,) -> Result<Self::Value, A::Error,> {
+            match data.variant()? {
+                (Variant::Def, value,) => {
+                    value.newtype_variant::<(u64, T,)>().map(|(id, value,)| (id, Some(value,),),)
+                }
+                (Variant::Ref, id,) => id.newtype_variant().map(|id| (id, None,),),
+            }
+        }
+    }
+
+    /// Serialises a borrowed `Arc` through [`serialize`], where `#[serde(with)]` can't be used.
+    pub struct Ser<'a, T,>(pub &'a Arc<T,>,);
+
+    impl<T: Serialize + 'static,> Serialize for Ser<'_, T,> {
+        fn serialize<S: Serializer,>(&self, serializer: S,) -> Result<S::Ok, S::Error,> {
+            serialize(self.0, serializer,)
+        }
+    }
+
+    /// Deserialises an `Arc` through [`deserialize`], where `#[serde(with)]` can't be used.
+    pub struct De<T,>(pub Arc<T,>,);
+
+    impl<'de, T: Deserialize<'de,> + 'static,> Deserialize<'de,> for De<T,> {
+        fn deserialize<D: Deserializer<'de,>,>(deserializer: D,) -> Result<Self, D::Error,> {
+            deserialize(deserializer,).map(De,)
+        }
+    }
+}
+
+/// Turns an [`Arc<T>`](crate::arc::Arc) into an `Arc` of an unsized type, the way an unsizing
+/// coercion would: `unsize_arc!(arc, dyn Trait)` or `unsize_arc!(arc, [T])`. Anything else
+/// fails to compile.
+#[macro_export]
+macro_rules! unsize_arc {
+    ($arc:expr, $ty:ty $(,)?) => {
+        // SAFETY: the closure's body is coerced to its return type, which only compiles for an
+        // unsizing (or no-op) coercion of its argument.
+        unsafe { $crate::arc::Arc::unsize($arc, |p| -> *const $ty { p },) }
+    };
 }
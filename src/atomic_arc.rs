@@ -0,0 +1,244 @@
+//! An [`Arc`] that can be swapped out atomically, for shared data (like configuration) that is
+//! read all the time and replaced now and then.
+//!
+//! It's lock-free: neither loads nor writers ever wait for each other. The hard part is `load`,
+//! as a writer may swap the pointer out and drop its last count between the reader reading it
+//! and incrementing the count. So the reader first writes the pointer into a debt slot, saying
+//! it owes a count, and then checks it is still current. A writer that swaps a pointer out scans
+//! the slots before letting go of its own count, and pays every debt on that pointer: it
+//! increments the count for the reader and marks the debt paid. The reader then takes back its
+//! debt and increments the count itself, or finds the debt paid and keeps that count.
+//!
+//! The slots are a list owned by the `AtomicArc`. A `load` claims a free one while it runs and
+//! adds one if all are taken, so there are only ever as many as there were loads at once. They
+//! are freed with the `AtomicArc`.
+
+use crate::arc::Arc;
+use crate::sync::atomic::{AtomicBool, AtomicPtr};
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop};
+use std::ptr;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+
+/// Set in a debt that a writer paid, on the pointer it paid with. `Arc` values are at least
+/// word aligned, so the bit is free.
+const PAID: usize = 1;
+
+pub struct AtomicArc<T,> {
+    /// From `Arc::into_raw`. The `AtomicArc` owns one count.
+    ptr: AtomicPtr<T,>,
+    /// The debt slots, a list that only grows.
+    debts: AtomicPtr<Debt<T,>,>,
+    // Only Send and Sync when the Arc is.
+    _marker: PhantomData<Arc<T,>,>,
+}
+
+/// The count a `load` owes: the pointer it is about to take a count of, or null.
+struct Debt<T,> {
+    ptr: AtomicPtr<T,>,
+    /// Claimed by a `load`.
+    in_use: AtomicBool,
+    /// Set before the slot is published, never changed after.
+    next: *mut Debt<T,>,
+}
+
+/// A new count of the `Arc` behind `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must come from `Arc::into_raw`, and a count of it must stay alive meanwhile.
+unsafe fn clone_raw<T,>(ptr: *const T,) -> Arc<T,> {
+    // SAFETY: forwarded from our caller, and the borrowed count isn't dropped.
+    let arc = ManuallyDrop::new(unsafe { Arc::from_raw(ptr,) },);
+    Arc::clone(&arc,)
+}
+
+impl<T,> AtomicArc<T,> {
+    pub fn new(arc: Arc<T,>,) -> Self {
+        let ptr = Arc::into_raw(arc,).cast_mut();
+        debug_assert!(ptr.addr() & PAID == 0);
+        AtomicArc {
+            ptr: AtomicPtr::new(ptr,),
+            debts: AtomicPtr::new(ptr::null_mut(),),
+            _marker: PhantomData,
+        }
+    }
+
+    /// A clone of the current `Arc`.
+    pub fn load(&self,) -> Arc<T,> {
+        let debt = self.claim_debt();
+        let arc = loop {
+            let ptr = self.ptr.load(Acquire,);
+            // SeqCst, like the swap and the scan in `pay_debts`: either the writer's scan sees
+            // our debt, or we see its swap below.
+            debt.ptr.store(ptr, SeqCst,);
+            let now = self.ptr.load(SeqCst,);
+            if now == ptr {
+                // SAFETY: current, so alive, and a writer swapping it out from now on pays our
+                // debt before dropping its count. `now` rather than `ptr`: it may have been
+                // swapped out, freed and the address reused in between.
+                let arc = unsafe { clone_raw(now,) };
+                // If a writer paid the debt as well, that's one count too many.
+                drop(settle(debt, ptr,),);
+                break arc;
+            }
+            // Swapped out before it was protected, but maybe a writer paid the debt anyway.
+            if let Some(arc,) = settle(debt, ptr,) {
+                break arc;
+            }
+        };
+        debt.in_use.store(false, Release,);
+        arc
+    }
+
+    /// Replaces the `Arc`, dropping the old one.
+    pub fn store(&self, arc: Arc<T,>,) {
+        drop(self.swap(arc,),);
+    }
+
+    /// Replaces the `Arc`, returning the old one.
+    pub fn swap(&self, arc: Arc<T,>,) -> Arc<T,> {
+        let new = Arc::into_raw(arc,).cast_mut();
+        debug_assert!(new.addr() & PAID == 0);
+        let old = self.ptr.swap(new, SeqCst,);
+        self.pay_debts(old,);
+        // SAFETY: the pointer came from `Arc::into_raw`, and the count the AtomicArc had of it
+        // is ours now.
+        unsafe { Arc::from_raw(old,) }
+    }
+
+    /// Replaces the `Arc` with `new` if it is still `current` (the same allocation, see
+    /// [`Arc::ptr_eq`]). Returns the old `Arc`, or gives `new` back if it wasn't `current`.
+    ///
+    /// Holding `current` keeps its allocation alive, so it can't have been freed and reused for
+    /// the `Arc` that replaced it in the meantime.
+    pub fn compare_and_swap(&self, current: &Arc<T,>, new: Arc<T,>,) -> Result<Arc<T,>, Arc<T,>,> {
+        let current = Arc::as_ptr(current,).cast_mut();
+        let new_ptr = Arc::as_ptr(&new,).cast_mut();
+        if self.ptr.compare_exchange(current, new_ptr, SeqCst, SeqCst,).is_err() {
+            return Err(new,);
+        }
+        // The AtomicArc owns `new`'s count now.
+        mem::forget(new,);
+        self.pay_debts(current,);
+        // SAFETY: as in `swap`.
+        Ok(unsafe { Arc::from_raw(current,) },)
+    }
+
+    /// Replaces the value with `f` of the current one, retrying with the newer value if another
+    /// writer got in first. Returns the `Arc` that was replaced.
+    pub fn rcu(&self, mut f: impl FnMut(&T,) -> T,) -> Arc<T,> {
+        let mut current = self.load();
+        loop {
+            match self.compare_and_swap(&current, Arc::new(f(&current,),),) {
+                Ok(old,) => return old,
+                Err(_,) => current = self.load(),
+            }
+        }
+    }
+
+    pub fn into_inner(self,) -> Arc<T,> {
+        self.load()
+    }
+
+    /// The debt slots.
+    fn debts(&self,) -> impl Iterator<Item = &Debt<T,>,> {
+        let mut next = self.debts.load(Acquire,).cast_const();
+        std::iter::from_fn(move || {
+            // SAFETY: slots are only freed with the AtomicArc, and `next` only points at
+            // published ones.
+            let debt = unsafe { next.as_ref() }?;
+            next = debt.next;
+            Some(debt,)
+        },)
+    }
+
+    /// A debt slot for a `load`, free or new.
+    fn claim_debt(&self,) -> &Debt<T,> {
+        for debt in self.debts() {
+            if !debt.in_use.load(Relaxed,)
+                && debt.in_use.compare_exchange(false, true, Acquire, Relaxed,).is_ok()
+            {
+                return debt;
+            }
+        }
+        let debt = Box::into_raw(Box::new(Debt {
+            ptr: AtomicPtr::new(ptr::null_mut(),),
+            in_use: AtomicBool::new(true,),
+            next: ptr::null_mut(),
+        },),);
+        let mut head = self.debts.load(Relaxed,);
+        loop {
+            // SAFETY: not published yet, still ours.
+            unsafe { (*debt).next = head };
+            match self.debts.compare_exchange_weak(head, debt, Release, Relaxed,) {
+                Ok(_,) => break,
+                Err(now,) => head = now,
+            }
+        }
+        // SAFETY: published now, and only freed with the AtomicArc.
+        unsafe { &*debt }
+    }
+
+    /// Pays every debt on `old`, just swapped out. Until this returns, the caller keeps the count
+    /// the AtomicArc had of it.
+    fn pay_debts(&self, old: *mut T,) {
+        for debt in self.debts() {
+            if debt.ptr.load(SeqCst,) != old {
+                continue;
+            }
+            // SAFETY: the caller keeps a count of `old`.
+            let count = unsafe { clone_raw(old,) };
+            let paid = old.map_addr(|a| a | PAID,);
+            match debt.ptr.compare_exchange(old, paid, SeqCst, Relaxed,) {
+                // The reader's now, see `settle`.
+                Ok(_,) => mem::forget(count,),
+                // The reader took its debt back first.
+                Err(_,) => drop(count,),
+            }
+        }
+    }
+}
+
+/// Takes back the debt on `ptr`. If a writer paid it already, returns the `Arc` it paid with.
+fn settle<T,>(debt: &Debt<T,>, ptr: *mut T,) -> Option<Arc<T,>,> {
+    // Acquire: the count the writer paid with happens before we use it.
+    let paid = debt.ptr.compare_exchange(ptr, ptr::null_mut(), SeqCst, Acquire,).err()?;
+    debt.ptr.store(ptr::null_mut(), Relaxed,);
+    // SAFETY: only a writer changes our debt, to the pointer it paid with, marked PAID, after
+    // incrementing its count for us.
+    Some(unsafe { Arc::from_raw(paid.map_addr(|a| a & !PAID,),) },)
+}
+
+impl<T,> Drop for AtomicArc<T,> {
+    fn drop(&mut self,) {
+        let mut next = self.debts.load(Relaxed,);
+        while !next.is_null() {
+            // SAFETY: from `Box::into_raw` in `claim_debt`, and with `&mut self` no load is
+            // using it.
+            next = unsafe { Box::from_raw(next,) }.next;
+        }
+        // SAFETY: the pointer came from `Arc::into_raw`, and with `self` gone nobody else can
+        // load it.
+        drop(unsafe { Arc::from_raw(self.ptr.load(Relaxed,),) },);
+    }
+}
+
+impl<T,> From<Arc<T,>,> for AtomicArc<T,> {
+    fn from(arc: Arc<T,>,) -> Self {
+        AtomicArc::new(arc,)
+    }
+}
+
+impl<T: Default,> Default for AtomicArc<T,> {
+    fn default() -> Self {
+        AtomicArc::new(Arc::default(),)
+    }
+}
+
+impl<T: fmt::Debug,> fmt::Debug for AtomicArc<T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_tuple("AtomicArc",).field(&self.load(),).finish()
+    }
+}
@@ -0,0 +1,167 @@
+//! A reusable barrier for a fixed number of threads, on a futex.
+//!
+//! Unlike `std::sync::Barrier` it can time out and it can break: a participant that panics while
+//! holding a [`BreakOnPanic`] guard, or a wait that times out, breaks the barrier, and every wait
+//! from then on returns [`BarrierError::Broken`] instead of waiting for threads that will never
+//! come. A broken barrier stays broken.
+
+use crate::futex::{self, WaitResult};
+use crate::sync::atomic::AtomicU32;
+use core::fmt;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+use std::time::{Duration, Instant};
+
+const BROKEN: u32 = 1;
+// NOTE: the generation counts in steps of two, so it can wrap around without touching BROKEN.
+const NEXT_GENERATION: u32 = 2;
+
+pub struct Barrier {
+    n: u32,
+    arrived: AtomicU32,
+    // NOTE: the futex word: the generation, with the BROKEN bit. Waiters sleep until it changes.
+    generation: AtomicU32,
+}
+
+/// Returned by a successful [`Barrier::wait`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub struct BarrierWaitResult {
+    is_leader: bool,
+}
+
+impl BarrierWaitResult {
+    /// Whether this thread was the last to arrive. Exactly one per generation is.
+    pub fn is_leader(&self,) -> bool {
+        self.is_leader
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub enum BarrierError {
+    /// A participant panicked or timed out, the barrier won't open again.
+    Broken,
+    /// This wait timed out, which broke the barrier for everyone else.
+    TimedOut,
+}
+
+impl fmt::Display for BarrierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.write_str(match self {
+            BarrierError::Broken => "barrier is broken",
+            BarrierError::TimedOut => "barrier wait timed out",
+        },)
+    }
+}
+
+impl core::error::Error for BarrierError {}
+
+impl Barrier {
+    /// A barrier that opens once `n` threads wait on it. `0` behaves like `1`.
+    pub const fn new(n: u32,) -> Self {
+        Self {
+            n: if n == 0 { 1 } else { n },
+            arrived: AtomicU32::new(0,),
+            generation: AtomicU32::new(0,),
+        }
+    }
+
+    /// Blocks until all `n` threads have arrived.
+    ///
+    /// # Errors
+    ///
+    /// [`BarrierError::Broken`] if the barrier is or gets broken before that.
+    pub fn wait(&self,) -> Result<BarrierWaitResult, BarrierError,> {
+        self.wait_until(None,)
+    }
+
+    /// Like `wait`, but gives up after `timeout`. Giving up breaks the barrier: the threads that
+    /// already arrived can't all meet anymore.
+    ///
+    /// # Errors
+    ///
+    /// [`BarrierError::TimedOut`] for the wait that timed out, [`BarrierError::Broken`] if the
+    /// barrier is or gets broken otherwise.
+    pub fn wait_timeout(&self, timeout: Duration,) -> Result<BarrierWaitResult, BarrierError,> {
+        self.wait_until(Instant::now().checked_add(timeout,),)
+    }
+
+    fn wait_until(&self, deadline: Option<Instant,>,) -> Result<BarrierWaitResult, BarrierError,> {
+        let generation = self.generation.load(Acquire,);
+        if generation & BROKEN != 0 {
+            return Err(BarrierError::Broken,);
+        }
+        // AcqRel: the leader sees everything the others did before arriving.
+        if self.arrived.fetch_add(1, AcqRel,) + 1 == self.n {
+            // Nobody arrives for the next generation before this one is released.
+            self.arrived.store(0, Relaxed,);
+            self.generation.fetch_add(NEXT_GENERATION, Release,);
+            futex::wake(&self.generation, u32::MAX,);
+            return Ok(BarrierWaitResult { is_leader: true, },);
+        }
+        loop {
+            let now = self.generation.load(Acquire,);
+            // Released, even if someone broke the barrier right after.
+            if now & !BROKEN != generation {
+                return Ok(BarrierWaitResult { is_leader: false, },);
+            }
+            if now & BROKEN != 0 {
+                return Err(BarrierError::Broken,);
+            }
+            if futex::wait(&self.generation, generation, deadline,) == WaitResult::TimedOut {
+                // Break the barrier, unless it opened at the last moment.
+                return match self.generation.compare_exchange(
+                    generation,
+                    generation | BROKEN,
+                    Acquire,
+                    Acquire,
+                ) {
+                    Ok(_,) => {
+                        futex::wake(&self.generation, u32::MAX,);
+                        Err(BarrierError::TimedOut,)
+                    }
+                    Err(_,) => continue,
+                };
+            }
+        }
+    }
+
+    /// Breaks the barrier: waiting threads return [`BarrierError::Broken`], and so does every
+    /// wait after this.
+    pub fn break_barrier(&self,) {
+        self.generation.fetch_or(BROKEN, Release,);
+        futex::wake(&self.generation, u32::MAX,);
+    }
+
+    pub fn is_broken(&self,) -> bool {
+        self.generation.load(Relaxed,) & BROKEN != 0
+    }
+
+    /// A guard that breaks the barrier if this thread panics before dropping it, so the others
+    /// don't wait for it forever. Meant to be held by a participant across its phases.
+    pub fn break_on_panic(&self,) -> BreakOnPanic<'_,> {
+        BreakOnPanic { barrier: self, }
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_struct("Barrier",)
+            .field("n", &self.n,)
+            .field("arrived", &self.arrived.load(Relaxed,),)
+            .field("broken", &self.is_broken(),)
+            .finish()
+    }
+}
+
+/// Made by [`Barrier::break_on_panic`].
+#[must_use = "the barrier is only broken if the guard is dropped by a panic"]
+pub struct BreakOnPanic<'a,> {
+    barrier: &'a Barrier,
+}
+
+impl Drop for BreakOnPanic<'_,> {
+    fn drop(&mut self,) {
+        if std::thread::panicking() {
+            self.barrier.break_barrier();
+        }
+    }
+}
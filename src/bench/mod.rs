@@ -0,0 +1,354 @@
+//! Benchmark driver used by the `atomics_locks bench` command.
+//!
+//! Every workload runs once on the crate's primitive and once on its std equivalent, with the
+//! same thread count, operation count and read ratio.
+
+mod workloads;
+
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::panic;
+use std::str::FromStr;
+use std::sync::Barrier;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Release};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub enum Primitive {
+    Mutex,
+    SpinLock,
+    RwLock,
+    Arc,
+    BiasedArc,
+    Channel,
+    CondVar,
+    Stack,
+    Queue,
+    HashMap,
+}
+
+impl FromStr for Primitive {
+    type Err = String;
+
+    fn from_str(s: &str,) -> Result<Self, String,> {
+        match s {
+            "mutex" => Ok(Primitive::Mutex,),
+            "spinlock" => Ok(Primitive::SpinLock,),
+            "rwlock" => Ok(Primitive::RwLock,),
+            "arc" => Ok(Primitive::Arc,),
+            "biased-arc" => Ok(Primitive::BiasedArc,),
+            "channel" => Ok(Primitive::Channel,),
+            "condvar" => Ok(Primitive::CondVar,),
+            "stack" => Ok(Primitive::Stack,),
+            "queue" => Ok(Primitive::Queue,),
+            "hashmap" => Ok(Primitive::HashMap,),
+            _ => Err(format!("unknown primitive `{s}`"),),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+#[derive(Clone, Debug,)]
+pub struct Config {
+    pub primitive: Primitive,
+    pub threads: usize,
+    pub ops: usize,
+    pub read_ratio: f64,
+    pub format: Format,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            primitive: Primitive::Mutex,
+            threads: 4,
+            ops: 100_000,
+            read_ratio: 0.9,
+            format: Format::Table,
+        }
+    }
+}
+
+/// The outcome of running one workload on one implementation.
+pub struct Measurement {
+    pub name: Cow<'static, str,>,
+    pub elapsed: Duration,
+    pub total_ops: usize,
+    /// Per-operation latencies in nanoseconds, sorted.
+    pub latencies: Vec<u64,>,
+    /// Wall-clock time each thread needed for its share of the operations.
+    pub per_thread: Vec<Duration,>,
+    /// Context switches of the whole process during the run, where the OS reports them.
+    pub context_switches: Option<u64,>,
+}
+
+impl Measurement {
+    pub fn throughput(&self,) -> f64 {
+        self.total_ops as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON,)
+    }
+
+    pub fn percentile(&self, p: f64,) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let idx = ((self.latencies.len() - 1) as f64 * p).round() as usize;
+        Duration::from_nanos(self.latencies[idx.min(self.latencies.len() - 1,)],)
+    }
+
+    pub fn max(&self,) -> Duration {
+        Duration::from_nanos(self.latencies.last().copied().unwrap_or(0,),)
+    }
+
+    /// Difference between the slowest and the fastest thread, relative to the slowest one. 0%
+    /// means every thread finished its operations at the same time.
+    pub fn fairness_spread(&self,) -> f64 {
+        let slowest = self.per_thread.iter().max().copied().unwrap_or_default();
+        let fastest = self.per_thread.iter().min().copied().unwrap_or_default();
+        if slowest.is_zero() {
+            return 0.0;
+        }
+        (slowest - fastest).as_secs_f64() / slowest.as_secs_f64() * 100.0
+    }
+}
+
+/// Small xorshift generator, each thread gets its own so read/write decisions don't synchronise.
+pub struct Rng(u64,);
+
+impl Rng {
+    pub fn new(seed: u64,) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15,) | 1,)
+    }
+
+    pub fn next_f64(&mut self,) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Voluntary plus involuntary context switches so far, of the whole process or only the calling
+/// thread.
+#[cfg(target_os = "linux")]
+fn context_switches(thread_only: bool,) -> Option<u64,> {
+    let who = if thread_only { libc::RUSAGE_THREAD } else { libc::RUSAGE_SELF };
+    let mut usage = std::mem::MaybeUninit::<libc::rusage,>::uninit();
+    // SAFETY: getrusage only writes to the struct we hand it.
+    if unsafe { libc::getrusage(who, usage.as_mut_ptr(),) } != 0 {
+        return None;
+    }
+    // SAFETY: initialised by the successful call above.
+    let usage = unsafe { usage.assume_init() };
+    u64::try_from(usage.ru_nvcsw + usage.ru_nivcsw,).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn context_switches(_thread_only: bool,) -> Option<u64,> {
+    None
+}
+
+fn switches_since(before: Option<u64,>, thread_only: bool,) -> Option<u64,> {
+    Some(context_switches(thread_only,)?.saturating_sub(before?,),)
+}
+
+/// Runs `op` `ops` times on each of `threads` threads and records the latency of every call.
+pub fn measure<S: Sync,>(
+    name: impl Into<Cow<'static, str,>,>,
+    config: &Config,
+    shared: &S,
+    op: impl Fn(&S, &mut Rng,) + Sync,
+) -> Measurement {
+    let barrier = Barrier::new(config.threads + 1,);
+    let mut start = Instant::now();
+    let mut switches = None;
+    let results: Vec<(Vec<u64,>, Duration,),> = thread::scope(|s| {
+        let handles: Vec<_,> = (0..config.threads)
+            .map(|t| {
+                let barrier = &barrier;
+                let op = &op;
+                s.spawn(move || {
+                    let mut rng = Rng::new(t as u64 + 1,);
+                    let mut latencies = Vec::with_capacity(config.ops,);
+                    barrier.wait();
+                    let thread_start = Instant::now();
+                    for _ in 0..config.ops {
+                        let t0 = Instant::now();
+                        op(shared, &mut rng,);
+                        latencies.push(t0.elapsed().as_nanos() as u64,);
+                    }
+                    (latencies, thread_start.elapsed(),)
+                },)
+            },)
+            .collect();
+        barrier.wait();
+        start = Instant::now();
+        switches = context_switches(false,);
+        // A panicked worker fails the bench, rather than quietly shrinking the sample.
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|e| panic::resume_unwind(e,),),)
+            .collect()
+    },);
+    let elapsed = start.elapsed();
+    let context_switches = switches_since(switches, false,);
+
+    let mut latencies = Vec::with_capacity(config.ops * config.threads,);
+    let mut per_thread = Vec::with_capacity(config.threads,);
+    for (l, d,) in results {
+        latencies.extend(l,);
+        per_thread.push(d,);
+    }
+    latencies.sort_unstable();
+    Measurement {
+        name: name.into(),
+        elapsed,
+        total_ops: latencies.len(),
+        latencies,
+        per_thread,
+        context_switches,
+    }
+}
+
+/// Runs `ops` rounds of `notify` against `threads` threads looping on `wait`, and records how
+/// long each round takes until every waiter has seen it. `wait` gets the last round the waiter
+/// saw and returns the current one. Only the waiters' context switches are counted, not those
+/// of the notifying thread polling for them.
+pub fn broadcast<S: Sync,>(
+    name: impl Into<Cow<'static, str,>,>,
+    config: &Config,
+    shared: &S,
+    wait: impl Fn(&S, u64,) -> u64 + Sync,
+    notify: impl Fn(&S,),
+) -> Measurement {
+    let seen_by = AtomicUsize::new(0,);
+    let rounds = config.ops as u64;
+    let mut latencies = Vec::with_capacity(config.ops,);
+    let start = Instant::now();
+    let results: Vec<(Duration, Option<u64,>,),> = thread::scope(|s| {
+        let handles: Vec<_,> = (0..config.threads)
+            .map(|_| {
+                s.spawn(|| {
+                    let thread_start = Instant::now();
+                    let switches = context_switches(true,);
+                    let mut seen = 0;
+                    while seen < rounds {
+                        seen = wait(shared, seen,);
+                        seen_by.fetch_add(1, Release,);
+                    }
+                    (thread_start.elapsed(), switches_since(switches, true,),)
+                },)
+            },)
+            .collect();
+        'rounds: for round in 1..=config.ops {
+            let t0 = Instant::now();
+            notify(shared,);
+            while seen_by.load(Acquire,) < round * config.threads {
+                // A worker that's done before the last round, or all of them done without having
+                // seen this one, panicked. Keep notifying until the others are out too, and let
+                // the join below pass the panic on.
+                let finished = handles.iter().filter(|h| h.is_finished(),).count();
+                if finished > 0 && (round < config.ops || finished == handles.len()) {
+                    while !handles.iter().all(|h| h.is_finished(),) {
+                        notify(shared,);
+                        thread::yield_now();
+                    }
+                    break 'rounds;
+                }
+                thread::yield_now();
+            }
+            latencies.push(t0.elapsed().as_nanos() as u64,);
+        }
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|e| panic::resume_unwind(e,),),)
+            .collect()
+    },);
+    let elapsed = start.elapsed();
+    let per_thread = results.iter().map(|(d, _,)| *d,).collect();
+    let context_switches = results.iter().map(|(_, n,)| *n,).sum();
+    latencies.sort_unstable();
+    Measurement {
+        name: name.into(),
+        elapsed,
+        total_ops: latencies.len(),
+        latencies,
+        per_thread,
+        context_switches,
+    }
+}
+
+pub fn run(config: &Config,) -> Vec<Measurement,> {
+    match config.primitive {
+        Primitive::Mutex => workloads::mutex(config,),
+        Primitive::SpinLock => workloads::spinlock(config,),
+        Primitive::RwLock => workloads::rwlock(config,),
+        Primitive::Arc => workloads::arc(config,),
+        Primitive::BiasedArc => workloads::biased_arc(config,),
+        Primitive::Channel => workloads::channel(config,),
+        Primitive::CondVar => workloads::condvar(config,),
+        Primitive::Stack => workloads::stack(config,),
+        Primitive::Queue => workloads::queue(config,),
+        Primitive::HashMap => workloads::hash_map(config,),
+    }
+}
+
+pub fn table(config: &Config, results: &[Measurement],) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "primitive={:?} threads={} ops/thread={} read_ratio={}",
+        config.primitive, config.threads, config.ops, config.read_ratio,
+    );
+    let _ = writeln!(
+        out,
+        "{:<28} {:>14} {:>10} {:>10} {:>10} {:>8} {:>10}",
+        "implementation", "ops/s", "p50", "p99", "max", "spread", "ctx-sw",
+    );
+    for m in results {
+        let _ = writeln!(
+            out,
+            "{:<28} {:>14.0} {:>10} {:>10} {:>10} {:>7.1}% {:>10}",
+            m.name,
+            m.throughput(),
+            format!("{:?}", m.percentile(0.50)),
+            format!("{:?}", m.percentile(0.99)),
+            format!("{:?}", m.max()),
+            m.fairness_spread(),
+            m.context_switches.map_or_else(|| "-".to_string(), |n| n.to_string(),),
+        );
+    }
+    out
+}
+
+pub fn json(config: &Config, results: &[Measurement],) -> String {
+    let rows: Vec<String,> = results
+        .iter()
+        .map(|m| {
+            format!(
+                "{{\"implementation\":\"{}\",\"ops_per_sec\":{:.1},\"p50_ns\":{},\"p99_ns\":{},\"max_ns\":{},\"fairness_spread_pct\":{:.2},\"context_switches\":{}}}",
+                m.name,
+                m.throughput(),
+                m.percentile(0.50).as_nanos(),
+                m.percentile(0.99).as_nanos(),
+                m.max().as_nanos(),
+                m.fairness_spread(),
+                m.context_switches.map_or_else(|| "null".to_string(), |n| n.to_string(),),
+            )
+        },)
+        .collect();
+    format!(
+        "{{\"primitive\":\"{:?}\",\"threads\":{},\"ops_per_thread\":{},\"read_ratio\":{},\"results\":[{}]}}",
+        config.primitive,
+        config.threads,
+        config.ops,
+        config.read_ratio,
+        rows.join(",",),
+    )
+}
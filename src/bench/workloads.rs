@@ -0,0 +1,265 @@
+use super::{Config, Measurement, broadcast, measure};
+use atomics_locks::one_shot_channel::unsafe_channel;
+use atomics_locks::{arc, biased_arc, condvar, hash_map, lockfree, mutex, rwlock, spinlock};
+use std::collections::{HashMap, VecDeque};
+use std::hint::black_box;
+use std::sync::PoisonError;
+
+pub fn mutex(config: &Config,) -> Vec<Measurement,> {
+    let ours = mutex::Mutex::new(0u64,);
+    let std = std::sync::Mutex::new(0u64,);
+    vec![
+        measure("atomics_locks::Mutex", config, &ours, |m, _| *m.lock() += 1,),
+        measure("std::sync::Mutex", config, &std, |m, _| {
+            if let Ok(mut g,) = m.lock() {
+                *g += 1;
+            }
+        },),
+    ]
+}
+
+pub fn spinlock(config: &Config,) -> Vec<Measurement,> {
+    let ours = spinlock::SpinLock::new(0u64,);
+    let std = std::sync::Mutex::new(0u64,);
+    vec![
+        measure("atomics_locks::SpinLock", config, &ours, |l, _| *l.lock() += 1,),
+        measure("std::sync::Mutex", config, &std, |m, _| {
+            if let Ok(mut g,) = m.lock() {
+                *g += 1;
+            }
+        },),
+    ]
+}
+
+pub fn rwlock(config: &Config,) -> Vec<Measurement,> {
+    let ratio = config.read_ratio;
+    let ours = rwlock::RwLock::new(0u64,);
+    let std = std::sync::RwLock::new(0u64,);
+    vec![
+        measure("atomics_locks::RwLock", config, &ours, |l, rng| {
+            if rng.next_f64() < ratio {
+                black_box(*l.read(),);
+            } else {
+                *l.write() += 1;
+            }
+        },),
+        measure("std::sync::RwLock", config, &std, |l, rng| {
+            if rng.next_f64() < ratio {
+                if let Ok(g,) = l.read() {
+                    black_box(*g,);
+                }
+            } else if let Ok(mut g,) = l.write() {
+                *g += 1;
+            }
+        },),
+    ]
+}
+
+pub fn arc(config: &Config,) -> Vec<Measurement,> {
+    let ratio = config.read_ratio;
+    let ours = arc::Arc::new(0u64,);
+    let std = std::sync::Arc::new(0u64,);
+    vec![
+        measure("atomics_locks::Arc", config, &ours, |a, rng| {
+            if rng.next_f64() < ratio {
+                black_box(**a,);
+            } else {
+                black_box(a.clone(),);
+            }
+        },),
+        measure("std::sync::Arc", config, &std, |a, rng| {
+            if rng.next_f64() < ratio {
+                black_box(**a,);
+            } else {
+                black_box(a.clone(),);
+            }
+        },),
+    ]
+}
+
+// NOTE: the first two rows give every thread an `Arc` of its own, made by that thread, which is
+// where a `BiasedArc` counts without atomics. The last two share one `Arc` made by the main
+// thread, so every clone on the workers takes the atomic path.
+pub fn biased_arc(config: &Config,) -> Vec<Measurement,> {
+    thread_local! {
+        static BIASED: biased_arc::BiasedArc<u64,> = biased_arc::BiasedArc::new(0,);
+        static ARC: arc::Arc<u64,> = arc::Arc::new(0,);
+    }
+    let ratio = config.read_ratio;
+    let shared_biased = biased_arc::SharedBiasedArc::from(biased_arc::BiasedArc::new(0u64,),);
+    let shared_arc = arc::Arc::new(0u64,);
+    vec![
+        measure("BiasedArc (owner thread)", config, &(), |_, rng| {
+            BIASED.with(|a| {
+                if rng.next_f64() < ratio {
+                    black_box(**a,);
+                } else {
+                    black_box(a.clone(),);
+                }
+            },)
+        },),
+        measure("Arc (one per thread)", config, &(), |_, rng| {
+            ARC.with(|a| {
+                if rng.next_f64() < ratio {
+                    black_box(**a,);
+                } else {
+                    black_box(a.clone(),);
+                }
+            },)
+        },),
+        measure("BiasedArc (other threads)", config, &shared_biased, |a, rng| {
+            if rng.next_f64() < ratio {
+                black_box(**a,);
+            } else {
+                black_box(a.clone(),);
+            }
+        },),
+        measure("Arc (shared)", config, &shared_arc, |a, rng| {
+            if rng.next_f64() < ratio {
+                black_box(**a,);
+            } else {
+                black_box(a.clone(),);
+            }
+        },),
+    ]
+}
+
+// NOTE: the crate only has one-shot channels, so every operation is a fresh channel carrying a
+// single message from the thread to itself.
+pub fn channel(config: &Config,) -> Vec<Measurement,> {
+    vec![
+        measure("atomics_locks::unsafe_channel", config, &(), |_, _| {
+            let channel = unsafe_channel::Channel::new();
+            channel.send(black_box(1u64,),);
+            black_box(channel.receive(),);
+        },),
+        measure("std::sync::mpsc::sync_channel", config, &(), |_, _| {
+            let (tx, rx,) = std::sync::mpsc::sync_channel(1,);
+            let _ = tx.send(black_box(1u64,),);
+            black_box(rx.recv().ok(),);
+        },),
+    ]
+}
+
+// NOTE: `--threads` is the number of waiters here, every round wakes all of them with one
+// notify_all, made while holding the mutex. The number to look at is the context switches.
+pub fn condvar(config: &Config,) -> Vec<Measurement,> {
+    let ours = (mutex::Mutex::new(0u64,), condvar::CondVar::new(),);
+    let std = (std::sync::Mutex::new(0u64,), std::sync::Condvar::new(),);
+    vec![
+        broadcast(
+            "atomics_locks::CondVar",
+            config,
+            &ours,
+            |(m, c,), seen| {
+                let mut round = m.lock();
+                while *round == seen {
+                    round = c.wait(round,);
+                }
+                *round
+            },
+            |(m, c,)| {
+                let mut round = m.lock();
+                *round += 1;
+                c.notify_all();
+            },
+        ),
+        broadcast(
+            "std::sync::Condvar",
+            config,
+            &std,
+            |(m, c,), seen| {
+                let mut round = m.lock().unwrap_or_else(PoisonError::into_inner,);
+                while *round == seen {
+                    round = c.wait(round,).unwrap_or_else(PoisonError::into_inner,);
+                }
+                *round
+            },
+            |(m, c,)| {
+                let mut round = m.lock().unwrap_or_else(PoisonError::into_inner,);
+                *round += 1;
+                c.notify_all();
+            },
+        ),
+    ]
+}
+
+// NOTE: every operation is a push (or enqueue) followed by a pop, so the collection stays small
+// and no pop comes back empty. The baseline is a `VecDeque` behind the crate's own mutex.
+pub fn stack(config: &Config,) -> Vec<Measurement,> {
+    let ours = lockfree::Stack::new();
+    let locked = mutex::Mutex::new(VecDeque::new(),);
+    vec![
+        measure("lockfree::Stack", config, &ours, |s, _| {
+            s.push(black_box(1u64,),);
+            black_box(s.pop(),);
+        },),
+        measure("Mutex<VecDeque>", config, &locked, |m, _| {
+            m.lock().push_back(black_box(1u64,),);
+            black_box(m.lock().pop_back(),);
+        },),
+    ]
+}
+
+pub fn queue(config: &Config,) -> Vec<Measurement,> {
+    let ours = lockfree::Queue::new();
+    let locked = mutex::Mutex::new(VecDeque::new(),);
+    vec![
+        measure("lockfree::Queue", config, &ours, |q, _| {
+            q.enqueue(black_box(1u64,),);
+            black_box(q.dequeue(),);
+        },),
+        measure("Mutex<VecDeque>", config, &locked, |m, _| {
+            m.lock().push_back(black_box(1u64,),);
+            black_box(m.lock().pop_front(),);
+        },),
+    ]
+}
+
+/// Read ratios the hash map workload sweeps.
+const HASH_MAP_READ_RATIOS: [f64; 3] = [0.5, 0.9, 0.99,];
+
+/// Keys the hash map workload picks from, all present from the start.
+const HASH_MAP_KEYS: u64 = 1024;
+
+// NOTE: sweeps the read ratios above instead of taking `--read-ratio`, as where the maps cross
+// over is the point. Reads look up a random key, writes overwrite one.
+pub fn hash_map(config: &Config,) -> Vec<Measurement,> {
+    let key = |rng: &mut super::Rng| (rng.next_f64() * HASH_MAP_KEYS as f64) as u64;
+    let mut results = Vec::new();
+    for ratio in HASH_MAP_READ_RATIOS {
+        let sharded = hash_map::ConcurrentHashMap::new();
+        let lock_free = hash_map::LockFreeHashMap::with_capacity(HASH_MAP_KEYS as usize,);
+        let locked = rwlock::RwLock::new(HashMap::new(),);
+        for k in 0..HASH_MAP_KEYS {
+            sharded.insert(k, k,);
+            lock_free.insert(k, k,);
+            locked.write().insert(k, k,);
+        }
+        let reads = ratio * 100.0;
+        results.extend([
+            measure(format!("ConcurrentHashMap {reads:.0}% reads"), config, &sharded, |m, rng| {
+                if rng.next_f64() < ratio {
+                    black_box(m.get(&key(rng,),).map(|v| *v,),);
+                } else {
+                    m.insert(key(rng,), black_box(1,),);
+                }
+            },),
+            measure(format!("LockFreeHashMap {reads:.0}% reads"), config, &lock_free, |m, rng| {
+                if rng.next_f64() < ratio {
+                    black_box(m.get(&key(rng,),).map(|v| *v,),);
+                } else {
+                    m.insert(key(rng,), black_box(1,),);
+                }
+            },),
+            measure(format!("RwLock<HashMap> {reads:.0}% reads"), config, &locked, |m, rng| {
+                if rng.next_f64() < ratio {
+                    black_box(m.read().get(&key(rng,),).copied(),);
+                } else {
+                    m.write().insert(key(rng,), black_box(1,),);
+                }
+            },),
+        ],);
+    }
+    results
+}
@@ -0,0 +1,235 @@
+//! An `Arc` with biased reference counting, for data that one thread clones and drops all the
+//! time and other threads only now and then.
+//!
+//! Every allocation has an owner, the thread that created it. The owner's handles are counted in
+//! a plain, non-atomic counter that only the owner ever touches, everyone else's in an atomic one.
+//! As long as the owner holds any handle at all, those count as one reference in the atomic
+//! counter. When the owner drops its last one, that reference is given back, and whoever brings the
+//! atomic counter to zero frees the data.
+//!
+//! A [`BiasedArc`] never leaves its thread, so it knows which counter it is in. To hand the data
+//! to another thread, make a [`SharedBiasedArc`], which is always counted atomically, and turn it
+//! back into a `BiasedArc` on the other side.
+
+use crate::sync::abort;
+use crate::sync::atomic::{AtomicUsize, fence};
+use crate::sync::cell::UnsafeCell;
+use std::fmt;
+use std::ops::Deref;
+use std::ptr::NonNull;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+/// Source of thread ids. Unlike addresses of thread locals, these are never reused, so a thread
+/// that happens to start after the owner has exited can't mistake itself for it.
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1,);
+
+thread_local! {
+    static THREAD_ID: u64 = NEXT_THREAD_ID.fetch_add(1, Relaxed,);
+}
+
+fn current_thread() -> u64 {
+    THREAD_ID.with(|id| *id,)
+}
+
+struct BiasedArcData<T,> {
+    owner: u64,
+    /// Handles on the owner thread. Only ever accessed by the owner.
+    biased: UnsafeCell<usize,>,
+    /// Handles everywhere else, plus one while `biased` isn't zero.
+    shared: AtomicUsize,
+    data: T,
+}
+
+impl<T,> BiasedArcData<T,> {
+    /// Drops one reference from the atomic counter, freeing the data if it was the last one.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be live and the caller must own one reference in the atomic counter.
+    unsafe fn release_shared(ptr: NonNull<Self,>,) {
+        // SAFETY: live, guaranteed by the caller.
+        if unsafe { ptr.as_ref() }.shared.fetch_sub(1, Release,) == 1 {
+            // Every other handle released its reference before this point, see `arc::Arc`.
+            fence(Acquire,);
+            // SAFETY: that was the last reference, and the data came from a `Box`.
+            drop(unsafe { Box::from_raw(ptr.as_ptr(),) },);
+        }
+    }
+
+    fn acquire_shared(&self,) {
+        if self.shared.fetch_add(1, Relaxed,) > usize::MAX / 2 {
+            abort();
+        }
+    }
+}
+
+/// A handle that stays on the thread it was made on. On the owner thread, cloning and dropping it
+/// is a plain increment and decrement.
+pub struct BiasedArc<T,> {
+    ptr: NonNull<BiasedArcData<T,>,>,
+    /// Whether this handle is in the owner's counter, that is, lives on the owner thread.
+    biased: bool,
+}
+
+// NOTE: a `BiasedArc` is neither Send nor Sync (the pointer takes care of that): the owner's
+// counter isn't atomic, so its handles must never be cloned or dropped anywhere else.
+
+impl<T,> BiasedArc<T,> {
+    /// Makes the calling thread the owner of `data`.
+    pub fn new(data: T,) -> Self {
+        let ptr = Box::new(BiasedArcData {
+            owner: current_thread(),
+            biased: UnsafeCell::new(1,),
+            shared: AtomicUsize::new(1,),
+            data,
+        },);
+        BiasedArc { ptr: NonNull::from(Box::leak(ptr,),), biased: true, }
+    }
+
+    fn data(&self,) -> &BiasedArcData<T,> {
+        // SAFETY: we hold a reference, so the data is live.
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// A handle that can be sent to other threads.
+    pub fn to_shared(this: &Self,) -> SharedBiasedArc<T,> {
+        this.data().acquire_shared();
+        SharedBiasedArc { ptr: this.ptr, }
+    }
+
+    /// Whether this handle is counted in the owner's non-atomic counter.
+    pub fn is_biased(this: &Self,) -> bool {
+        this.biased
+    }
+
+    pub fn ptr_eq(a: &Self, b: &Self,) -> bool {
+        a.ptr == b.ptr
+    }
+}
+
+impl<T,> Deref for BiasedArc<T,> {
+    type Target = T;
+
+    fn deref(&self,) -> &T {
+        &self.data().data
+    }
+}
+
+impl<T,> Clone for BiasedArc<T,> {
+    fn clone(&self,) -> Self {
+        if self.biased {
+            // SAFETY: biased handles only exist on the owner thread, which is the only one to
+            // access the counter.
+            unsafe { *self.data().biased.write_ptr() += 1 };
+        } else {
+            self.data().acquire_shared();
+        }
+        BiasedArc { ptr: self.ptr, biased: self.biased, }
+    }
+}
+
+impl<T,> Drop for BiasedArc<T,> {
+    fn drop(&mut self,) {
+        if self.biased {
+            let biased = self.data().biased.write_ptr();
+            // SAFETY: as in `clone`.
+            let left = unsafe {
+                *biased -= 1;
+                *biased
+            };
+            if left != 0 {
+                return;
+            }
+        }
+        // Either a shared handle, or the owner's last one, which gives back the owner's reference.
+        // SAFETY: we own a reference in the atomic counter, one way or the other.
+        unsafe { BiasedArcData::release_shared(self.ptr,) }
+    }
+}
+
+impl<T: fmt::Debug,> fmt::Debug for BiasedArc<T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f,)
+    }
+}
+
+/// A handle that can cross threads, always counted in the atomic counter.
+pub struct SharedBiasedArc<T,> {
+    ptr: NonNull<BiasedArcData<T,>,>,
+}
+
+// SAFETY: as for `arc::Arc`. Shared handles never touch the owner's counter.
+unsafe impl<T: Send + Sync,> Send for SharedBiasedArc<T,> {}
+// SAFETY: as above.
+unsafe impl<T: Send + Sync,> Sync for SharedBiasedArc<T,> {}
+
+impl<T,> SharedBiasedArc<T,> {
+    fn data(&self,) -> &BiasedArcData<T,> {
+        // SAFETY: we hold a reference, so the data is live.
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Turns this into a handle for the calling thread. On the owner thread, the reference moves
+    /// over to the owner's counter.
+    pub fn into_local(this: Self,) -> BiasedArc<T,> {
+        let ptr = this.ptr;
+        std::mem::forget(this,);
+        // SAFETY: we held a reference a moment ago, and still own it.
+        let data = unsafe { ptr.as_ref() };
+        if data.owner != current_thread() {
+            return BiasedArc { ptr, biased: false, };
+        }
+        let biased = data.biased.write_ptr();
+        // SAFETY: we are on the owner thread.
+        if unsafe { *biased } == 0 {
+            // The owner had given its reference back, ours becomes the owner's.
+            // SAFETY: as above.
+            unsafe { *biased = 1 };
+        } else {
+            // SAFETY: as above.
+            unsafe { *biased += 1 };
+            // The owner's reference is still there, so this isn't the last one.
+            data.shared.fetch_sub(1, Relaxed,);
+        }
+        BiasedArc { ptr, biased: true, }
+    }
+
+    pub fn ptr_eq(a: &Self, b: &Self,) -> bool {
+        a.ptr == b.ptr
+    }
+}
+
+impl<T,> Deref for SharedBiasedArc<T,> {
+    type Target = T;
+
+    fn deref(&self,) -> &T {
+        &self.data().data
+    }
+}
+
+impl<T,> Clone for SharedBiasedArc<T,> {
+    fn clone(&self,) -> Self {
+        self.data().acquire_shared();
+        SharedBiasedArc { ptr: self.ptr, }
+    }
+}
+
+impl<T,> Drop for SharedBiasedArc<T,> {
+    fn drop(&mut self,) {
+        // SAFETY: we own a reference in the atomic counter.
+        unsafe { BiasedArcData::release_shared(self.ptr,) }
+    }
+}
+
+impl<T,> From<BiasedArc<T,>,> for SharedBiasedArc<T,> {
+    fn from(arc: BiasedArc<T,>,) -> Self {
+        BiasedArc::to_shared(&arc,)
+    }
+}
+
+impl<T: fmt::Debug,> fmt::Debug for SharedBiasedArc<T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f,)
+    }
+}
@@ -1,12 +1,20 @@
-use crate::mutex::MutexGuard;
+use crate::futex::{self, WaitResult};
+use crate::mutex::{Mutex, MutexGuard};
+use crate::stats::{Contention, Counters, WaitTimer};
+use crate::sync::atomic::{AtomicU32, AtomicUsize};
+use core::fmt;
+use core::ops::{Deref, DerefMut};
 use std::sync::atomic::Ordering::Relaxed;
-use std::sync::atomic::{AtomicU32, AtomicUsize};
-
-use atomic_wait::{wait, wake_all, wake_one};
+use std::time::{Duration, Instant};
 
+/// A condition variable. It's bound to the first mutex it's used with (or the one given to
+/// [`CondVar::for_mutex`]), waiting with a guard of any other mutex panics.
 pub struct CondVar {
     counter: AtomicU32,
     waiters_count: AtomicUsize,
+    // NOTE: address of the state of the mutex this is bound to, 0 before the first wait.
+    mutex: AtomicUsize,
+    stats: Counters,
 }
 
 impl Default for CondVar {
@@ -17,35 +25,207 @@ impl Default for CondVar {
 
 impl CondVar {
     pub fn new() -> Self {
-        Self { counter: AtomicU32::new(0,), waiters_count: AtomicUsize::new(0,), }
+        Self::bound_to(0,)
+    }
+
+    /// Creates a condition variable that can only be used with `mutex`.
+    pub fn for_mutex<T,>(mutex: &Mutex<T,>,) -> BoundCondVar<'_, T,> {
+        BoundCondVar { mutex, condvar: Self::bound_to(state_addr(mutex,),), }
+    }
+
+    fn bound_to(mutex: usize,) -> Self {
+        Self {
+            counter: AtomicU32::new(0,),
+            waiters_count: AtomicUsize::new(0,),
+            mutex: AtomicUsize::new(mutex,),
+            stats: Counters::new(),
+        }
     }
 
     pub fn notify_one(&self,) {
         if self.waiters_count.load(Relaxed,) > 0 {
             self.counter.fetch_add(1, Relaxed,);
-            wake_one(&self.counter,);
+            futex::wake(&self.counter, 1,);
         }
     }
+    /// Wakes every waiter. Only one is actually woken, the others are moved onto the mutex and
+    /// woken one at a time as it's unlocked, instead of all of them fighting over it at once.
     pub fn notify_all(&self,) {
         if self.waiters_count.load(Relaxed,) > 0 {
-            self.counter.fetch_add(1, Relaxed,);
-            wake_all(&self.counter,);
+            let v = self.counter.fetch_add(1, Relaxed,).wrapping_add(1,);
+            let mutex = self.mutex.load(Relaxed,);
+            let requeued = mutex != 0
+                // SAFETY: every thread waiting on `counter` is in `wait`, holding a borrow of the
+                // mutex `mutex` points into (a guard of any other mutex panics before waiting).
+                && unsafe {
+                    futex::requeue(&self.counter, v, 1, mutex as *const AtomicU32, u32::MAX,)
+                }
+                .is_some();
+            // Not requeued (no mutex yet, `counter` moved on, or the requeue failed): nobody was
+            // moved, so wake them all here.
+            if !requeued {
+                futex::wake(&self.counter, u32::MAX,);
+            }
         }
     }
 
+    /// # Panics
+    ///
+    /// If `guard` belongs to a different mutex than the one this condvar is bound to.
     pub fn wait<'a, T,>(&self, guard: MutexGuard<'a, T,>,) -> MutexGuard<'a, T,> {
+        self.wait_until(guard, None,).0
+    }
+
+    /// Like `wait`, but gives up after `timeout`. The mutex is locked again either way, check
+    /// [`WaitTimeoutResult::timed_out`] to tell the two apart.
+    ///
+    /// # Panics
+    ///
+    /// Like `wait`.
+    pub fn wait_timeout<'a, T,>(
+        &self,
+        guard: MutexGuard<'a, T,>,
+        timeout: Duration,
+    ) -> (MutexGuard<'a, T,>, WaitTimeoutResult,) {
+        self.wait_until(guard, Instant::now().checked_add(timeout,),)
+    }
+
+    fn wait_until<'a, T,>(
+        &self,
+        guard: MutexGuard<'a, T,>,
+        deadline: Option<Instant,>,
+    ) -> (MutexGuard<'a, T,>, WaitTimeoutResult,) {
+        let m = guard.mutex_ref();
+        let state = state_addr(m,);
+        if let Err(bound,) = self.mutex.compare_exchange(0, state, Relaxed, Relaxed,) {
+            assert!(
+                bound == state,
+                "CondVar used with a different mutex than the one it is bound to"
+            );
+        }
+
         self.waiters_count.fetch_add(1, Relaxed,);
         let v = self.counter.load(Relaxed,);
 
-        let m = guard.mutex;
-
         drop(guard,);
 
-        wait(&self.counter, v,);
+        let timer = WaitTimer::start();
+        let result = futex::wait(&self.counter, v, deadline,);
+        self.stats.record_contended(timer, Contention { spins: 0, futex_waits: 1, },);
+        self.stats.record_acquire();
 
         self.waiters_count.fetch_sub(1, Relaxed,);
 
-        m.lock()
+        m.raw().lock_after_wait();
+        // SAFETY: just locked, and the guard we were given is gone.
+        (unsafe { m.make_guard_unchecked() }, WaitTimeoutResult(result == WaitResult::TimedOut,),)
+    }
+}
+
+fn state_addr<T,>(mutex: &Mutex<T,>,) -> usize {
+    std::ptr::from_ref(&mutex.raw().state,) as usize
+}
+
+/// A [`CondVar`] that carries the mutex it's bound to, made by [`CondVar::for_mutex`].
+///
+/// It only waits with a [`BoundGuard`], which only its own `lock` hands out, so a guard of some
+/// other mutex doesn't compile. A `BoundGuard` of a different `BoundCondVar` still panics, like
+/// with [`CondVar::wait`].
+pub struct BoundCondVar<'m, T,> {
+    mutex: &'m Mutex<T,>,
+    condvar: CondVar,
+}
+
+impl<'m, T,> BoundCondVar<'m, T,> {
+    pub fn mutex(&self,) -> &'m Mutex<T,> {
+        self.mutex
+    }
+
+    pub fn lock(&self,) -> BoundGuard<'m, T,> {
+        BoundGuard(self.mutex.lock(),)
+    }
+
+    pub fn notify_one(&self,) {
+        self.condvar.notify_one();
+    }
+
+    pub fn notify_all(&self,) {
+        self.condvar.notify_all();
+    }
+
+    /// # Panics
+    ///
+    /// If `guard` was locked through a different `BoundCondVar`, bound to another mutex.
+    pub fn wait(&self, guard: BoundGuard<'m, T,>,) -> BoundGuard<'m, T,> {
+        BoundGuard(self.condvar.wait(guard.0,),)
+    }
+
+    /// # Panics
+    ///
+    /// Like `wait`.
+    pub fn wait_timeout(
+        &self,
+        guard: BoundGuard<'m, T,>,
+        timeout: Duration,
+    ) -> (BoundGuard<'m, T,>, WaitTimeoutResult,) {
+        let (guard, result,) = self.condvar.wait_timeout(guard.0, timeout,);
+        (BoundGuard(guard,), result,)
+    }
+}
+
+/// Holds the mutex of a [`BoundCondVar`] locked, made by [`BoundCondVar::lock`].
+pub struct BoundGuard<'m, T,>(MutexGuard<'m, T,>,);
+
+impl<T: fmt::Debug,> fmt::Debug for BoundGuard<'_, T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f,)
+    }
+}
+
+impl<T,> Deref for BoundGuard<'_, T,> {
+    type Target = T;
+    fn deref(&self,) -> &T {
+        &self.0
+    }
+}
+
+impl<T,> DerefMut for BoundGuard<'_, T,> {
+    fn deref_mut(&mut self,) -> &mut T {
+        &mut self.0
+    }
+}
+
+/// Returned by [`CondVar::wait_timeout`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub struct WaitTimeoutResult(bool,);
+
+impl WaitTimeoutResult {
+    /// Whether the wait ended because the timeout elapsed.
+    pub fn timed_out(&self,) -> bool {
+        self.0
+    }
+}
+
+// NOTE: for a CondVar, an "acquisition" is a call to wait and every wait counts as contended.
+#[cfg(feature = "stats")]
+impl CondVar {
+    pub fn stats(&self,) -> crate::stats::LockStats {
+        self.stats.snapshot()
+    }
+
+    pub fn reset_stats(&self,) {
+        self.stats.reset();
+    }
+}
+
+#[cfg(feature = "stats")]
+impl crate::stats::StatsSource for CondVar {
+    fn stats(&self,) -> crate::stats::LockStats {
+        CondVar::stats(self,)
+    }
+
+    fn reset_stats(&self,) {
+        CondVar::reset_stats(self,)
     }
 }
 
@@ -80,4 +260,30 @@ mod tests {
 
         assert!(wakeups < 10);
     }
+
+    #[test]
+    fn test_condvar_wait_timeout() {
+        let c = CondVar::new();
+        let m = Mutex::new(false,);
+
+        let (guard, result,) = c.wait_timeout(m.lock(), Duration::from_millis(50,),);
+        assert!(result.timed_out());
+        assert!(!*guard);
+        drop(guard,);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(100,),);
+                *m.lock() = true;
+                c.notify_one();
+            },);
+
+            let mut guard = m.lock();
+            while !*guard {
+                let (g, result,) = c.wait_timeout(guard, Duration::from_secs(10,),);
+                assert!(!result.timed_out());
+                guard = g;
+            }
+        },);
+    }
 }
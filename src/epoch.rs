@@ -0,0 +1,613 @@
+//! Epoch-based reclamation: readers pin the current epoch instead of protecting every pointer
+//! they load, which makes reads about as cheap as they get.
+//!
+//! There is one global epoch. [`pin`] records it for the calling thread and returns a [`Guard`];
+//! pointers loaded from an [`Atomic`] under the guard stay valid until it is dropped. Unlinked
+//! nodes are handed to [`Guard::defer_destroy`], tagged with the epoch they were retired in. The
+//! global epoch only moves on once every pinned thread has seen the current one, so after it has
+//! moved twice, nobody can still hold a pointer to what was retired, and it is freed.
+//!
+//! Every thread registers a participant the first time it pins, and unregisters it when it exits,
+//! handing whatever it deferred and couldn't free yet to a global list that the other threads
+//! collect. Participants are never freed, an exited thread's one is reused by the next thread to
+//! register. A thread that pins while its thread locals are being torn down gets a participant
+//! just for that guard.
+//!
+//! Like [`crate::hazard`], all of this is global state, so it uses std's atomics.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
+use std::sync::atomic::Ordering::{self, Acquire, Relaxed, Release, SeqCst};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, fence};
+use std::sync::{Mutex, PoisonError, TryLockError};
+
+/// Deferred destructions a thread collects before trying to free them.
+const COLLECT_THRESHOLD: usize = 64;
+
+/// A thread also tries to advance the epoch and collect every this many pins.
+const PINS_PER_COLLECT: usize = 128;
+
+static EPOCH: AtomicUsize = AtomicUsize::new(0,);
+static PARTICIPANTS: AtomicPtr<Participant,> = AtomicPtr::new(ptr::null_mut(),);
+
+/// Deferred by threads that have exited.
+static GARBAGE: Mutex<Bag,> = Mutex::new(VecDeque::new(),);
+
+struct Participant {
+    /// `epoch << 1 | 1` while pinned, 0 otherwise.
+    epoch: AtomicUsize,
+    /// Registered to a thread (or a guard).
+    active: AtomicBool,
+    /// Guards alive. Only the thread the participant is registered to touches it.
+    guards: AtomicUsize,
+    /// No thread local refers to it anymore, so the last guard unregisters it.
+    detached: AtomicBool,
+    /// Set before the participant is published, never changed after.
+    next: *const Participant,
+}
+
+// SAFETY: `next` is immutable once other threads can see the participant.
+unsafe impl Sync for Participant {}
+
+fn participants() -> impl Iterator<Item = &'static Participant,> {
+    let mut next = PARTICIPANTS.load(Acquire,).cast_const();
+    std::iter::from_fn(move || {
+        // SAFETY: participants are never freed, and `next` only points at published ones.
+        let participant = unsafe { next.as_ref() }?;
+        next = participant.next;
+        Some(participant,)
+    },)
+}
+
+fn register() -> &'static Participant {
+    for participant in participants() {
+        if !participant.active.load(Relaxed,)
+            && participant.active.compare_exchange(false, true, Acquire, Relaxed,).is_ok()
+        {
+            return participant;
+        }
+    }
+    let participant: &'static mut Participant = Box::leak(Box::new(Participant {
+        epoch: AtomicUsize::new(0,),
+        active: AtomicBool::new(true,),
+        guards: AtomicUsize::new(0,),
+        detached: AtomicBool::new(false,),
+        next: ptr::null(),
+    },),);
+    let mut head = PARTICIPANTS.load(Relaxed,);
+    loop {
+        participant.next = head;
+        match PARTICIPANTS.compare_exchange_weak(head, participant, Release, Relaxed,) {
+            Ok(_,) => return participant,
+            Err(now,) => head = now,
+        }
+    }
+}
+
+fn unregister(participant: &Participant,) {
+    participant.detached.store(false, Relaxed,);
+    participant.active.store(false, Release,);
+}
+
+/// A destruction waiting for its epoch to pass.
+struct Deferred {
+    ptr: *mut (),
+    destroy: unsafe fn(*mut (),),
+}
+
+// SAFETY: `defer_destroy` requires the value to be fine to drop on any thread.
+unsafe impl Send for Deferred {}
+
+impl Deferred {
+    fn new<T,>(ptr: *mut T,) -> Self {
+        /// # Safety
+        ///
+        /// `ptr` must come from a `Box<T>`, and be dropped once.
+        unsafe fn destroy<T,>(ptr: *mut (),) {
+            // SAFETY: guaranteed by the caller.
+            drop(unsafe { Box::from_raw(ptr.cast::<T>(),) },);
+        }
+        Deferred { ptr: ptr.cast(), destroy: destroy::<T,>, }
+    }
+}
+
+/// Destructions tagged with their epoch. A thread's own are in epoch order.
+type Bag = VecDeque<(usize, Deferred,),>;
+
+/// Runs the destructions at the front of `bag` that are at least two epochs old, up to the first
+/// younger one.
+fn collect(bag: &mut Bag, global: usize,) {
+    while let Some((epoch, deferred,),) = bag.pop_front() {
+        if epoch + 2 > global {
+            bag.push_front((epoch, deferred,),);
+            return;
+        }
+        // SAFETY: the epoch moved on twice since it was unlinked, nobody can still reach it.
+        unsafe { (deferred.destroy)(deferred.ptr,) };
+    }
+}
+
+/// Puts what `collect` left back in front of `bag`, which may have gained newer entries in the
+/// meantime.
+fn put_back(bag: &mut Bag, left: Bag,) {
+    let newer = std::mem::replace(bag, left,);
+    bag.extend(newer,);
+}
+
+/// Moves the global epoch on if every pinned thread has seen it. Returns the global epoch.
+fn try_advance() -> usize {
+    let global = EPOCH.load(Relaxed,);
+    // Pairs with the fence in `pin`: a thread that pinned an older epoch is seen pinned here.
+    fence(SeqCst,);
+    for participant in participants() {
+        let epoch = participant.epoch.load(Relaxed,);
+        if epoch & 1 == 1 && epoch >> 1 != global {
+            return global;
+        }
+    }
+    // Everything the pinned threads did in older epochs happens before the destructions.
+    fence(Acquire,);
+    match EPOCH.compare_exchange(global, global + 1, Release, Relaxed,) {
+        Ok(_,) => global + 1,
+        Err(now,) => now,
+    }
+}
+
+/// What a thread keeps to itself.
+struct Local {
+    participant: &'static Participant,
+    bag: Bag,
+    /// Deferred since the last flush.
+    deferred: usize,
+    pins: usize,
+}
+
+impl Drop for Local {
+    fn drop(&mut self,) {
+        let mut left = std::mem::take(&mut self.bag,);
+        collect(&mut left, EPOCH.load(Acquire,),);
+        GARBAGE.lock().unwrap_or_else(PoisonError::into_inner,).extend(left,);
+        if self.participant.guards.load(Relaxed,) == 0 {
+            unregister(self.participant,);
+        } else {
+            self.participant.detached.store(true, Relaxed,);
+        }
+    }
+}
+
+thread_local! {
+    static LOCAL: RefCell<Local,> =
+        RefCell::new(Local { participant: register(), bag: VecDeque::new(), deferred: 0, pins: 0, },);
+}
+
+/// Pins the current epoch until the guard is dropped. Pinning again while pinned is cheap.
+pub fn pin() -> Guard {
+    let (participant, flush,) = LOCAL
+        .try_with(|l| {
+            let mut l = l.borrow_mut();
+            l.pins += 1;
+            (l.participant, l.pins % PINS_PER_COLLECT == 0,)
+        },)
+        .unwrap_or_else(|_| {
+            let participant = register();
+            participant.detached.store(true, Relaxed,);
+            (participant, false,)
+        },);
+    let guards = participant.guards.load(Relaxed,);
+    participant.guards.store(guards + 1, Relaxed,);
+    let guard = Guard { participant, _not_send: PhantomData, };
+    if guards == 0 {
+        participant.epoch.store(EPOCH.load(Relaxed,) << 1 | 1, Relaxed,);
+        // Pairs with the fence in `try_advance`: either it sees us pinned, or we see every
+        // pointer it unlinked before moving the epoch on, and never load the old ones.
+        fence(SeqCst,);
+        if flush {
+            guard.flush();
+        }
+    }
+    guard
+}
+
+/// Keeps the thread pinned. Not `Send`, the pin belongs to the thread.
+pub struct Guard {
+    participant: &'static Participant,
+    _not_send: PhantomData<*mut (),>,
+}
+
+impl Guard {
+    /// Drops the value behind `ptr` once no thread can be reading it anymore.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be unlinked already, so no thread can newly load it, must come from an
+    /// [`Owned`] (or a `Box`) and must not be destroyed twice. The value may be dropped on any
+    /// thread, at any later point.
+    pub unsafe fn defer_destroy<T,>(&self, ptr: Shared<'_, T,>,) {
+        // Tagged with the global epoch, not the one we pinned, which may be a step behind.
+        // The fence makes sure it's read after the node was unlinked.
+        fence(SeqCst,);
+        let epoch = EPOCH.load(Relaxed,);
+        let mut deferred = Some((epoch, Deferred::new(ptr.ptr,),),);
+        let full = LOCAL.try_with(|l| {
+            let mut l = l.borrow_mut();
+            l.bag.extend(deferred.take(),);
+            l.deferred += 1;
+            l.deferred >= COLLECT_THRESHOLD
+        },);
+        match full {
+            Ok(true,) => self.flush(),
+            Ok(false,) => {}
+            Err(_,) => {
+                GARBAGE.lock().unwrap_or_else(PoisonError::into_inner,).extend(deferred,);
+            }
+        }
+    }
+
+    /// Tries to move the epoch on, and runs the destructions (this thread's, and those left by
+    /// exited threads) that are old enough.
+    pub fn flush(&self,) {
+        let global = try_advance();
+        // Taken out first, destructors may defer more.
+        let bag = LOCAL.try_with(|l| {
+            let mut l = l.borrow_mut();
+            l.deferred = 0;
+            std::mem::take(&mut l.bag,)
+        },);
+        if let Ok(mut bag,) = bag {
+            collect(&mut bag, global,);
+            let mut left = Some(bag,);
+            if LOCAL
+                .try_with(|l| put_back(&mut l.borrow_mut().bag, left.take().unwrap_or_default(),),)
+                .is_err()
+            {
+                GARBAGE
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner,)
+                    .extend(left.into_iter().flatten(),);
+            }
+        }
+        let mut garbage = match GARBAGE.try_lock() {
+            Ok(mut garbage,) => std::mem::take(&mut *garbage,),
+            Err(TryLockError::Poisoned(poisoned,),) => std::mem::take(&mut *poisoned.into_inner(),),
+            // Somebody else is at it already.
+            Err(TryLockError::WouldBlock,) => return,
+        };
+        collect(&mut garbage, global,);
+        put_back(&mut GARBAGE.lock().unwrap_or_else(PoisonError::into_inner,), garbage,);
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self,) {
+        let participant = self.participant;
+        let guards = participant.guards.load(Relaxed,) - 1;
+        participant.guards.store(guards, Relaxed,);
+        if guards == 0 {
+            // Release: our reads of whatever we loaded happen before its destruction.
+            participant.epoch.store(0, Release,);
+            if participant.detached.load(Relaxed,) {
+                unregister(participant,);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Guard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_struct("Guard",)
+            .field("epoch", &(self.participant.epoch.load(Relaxed,) >> 1),)
+            .finish()
+    }
+}
+
+/// Something that can be stored in an [`Atomic`]: an [`Owned`] or a [`Shared`].
+pub trait Pointer<T,> {
+    fn into_ptr(self,) -> *mut T;
+
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_ptr` on the same kind of pointer.
+    unsafe fn from_ptr(ptr: *mut T,) -> Self;
+}
+
+/// An atomic pointer to a heap-allocated `T`, to be loaded under a [`Guard`]. Dropping it doesn't
+/// drop what it points to.
+pub struct Atomic<T,> {
+    ptr: AtomicPtr<T,>,
+}
+
+// SAFETY: like `Box<T>` shared between threads.
+unsafe impl<T: Send + Sync,> Send for Atomic<T,> {}
+// SAFETY: as above.
+unsafe impl<T: Send + Sync,> Sync for Atomic<T,> {}
+
+/// The failure of [`Atomic::compare_exchange`], with the value found and `new` given back.
+pub struct CompareExchangeError<'g, T, P: Pointer<T,>,> {
+    pub current: Shared<'g, T,>,
+    pub new: P,
+}
+
+impl<T, P: Pointer<T,>,> fmt::Debug for CompareExchangeError<'_, T, P,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_struct("CompareExchangeError",)
+            .field("current", &self.current,)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T,> Atomic<T,> {
+    pub const fn null() -> Self {
+        Atomic { ptr: AtomicPtr::new(ptr::null_mut(),), }
+    }
+
+    pub fn new(value: T,) -> Self {
+        Atomic::from(Owned::new(value,),)
+    }
+
+    pub fn load<'g,>(&self, order: Ordering, _: &'g Guard,) -> Shared<'g, T,> {
+        // SAFETY: stored from `into_ptr`, and valid for as long as the guard lives.
+        unsafe { Shared::from_ptr(self.ptr.load(order,),) }
+    }
+
+    pub fn store<P: Pointer<T,>,>(&self, new: P, order: Ordering,) {
+        self.ptr.store(new.into_ptr(), order,);
+    }
+
+    pub fn swap<'g, P: Pointer<T,>,>(
+        &self,
+        new: P,
+        order: Ordering,
+        _: &'g Guard,
+    ) -> Shared<'g, T,> {
+        // SAFETY: as in `load`.
+        unsafe { Shared::from_ptr(self.ptr.swap(new.into_ptr(), order,),) }
+    }
+
+    /// Stores `new` if the pointer is still `current`. Returns the old pointer, or the one found
+    /// along with `new`.
+    pub fn compare_exchange<'g, P: Pointer<T,>,>(
+        &self,
+        current: Shared<'_, T,>,
+        new: P,
+        success: Ordering,
+        failure: Ordering,
+        _: &'g Guard,
+    ) -> Result<Shared<'g, T,>, CompareExchangeError<'g, T, P,>,> {
+        let new = new.into_ptr();
+        match self.ptr.compare_exchange(current.ptr, new, success, failure,) {
+            // SAFETY: as in `load`.
+            Ok(old,) => Ok(unsafe { Shared::from_ptr(old,) },),
+            // SAFETY: as in `load`, and `new` came from `into_ptr` on a `P` a moment ago.
+            Err(found,) => Err(unsafe {
+                CompareExchangeError { current: Shared::from_ptr(found,), new: P::from_ptr(new,), }
+            },),
+        }
+    }
+
+    /// Takes what the pointer points to, `None` if null.
+    ///
+    /// # Safety
+    ///
+    /// No other thread may still be using the pointer.
+    pub unsafe fn into_owned(self,) -> Option<Owned<T,>,> {
+        let ptr = self.ptr.into_inner();
+        // SAFETY: stored from `into_ptr`, and nobody else uses it (guaranteed by the caller).
+        (!ptr.is_null()).then(|| unsafe { Owned::from_ptr(ptr,) },)
+    }
+}
+
+impl<T,> From<Owned<T,>,> for Atomic<T,> {
+    fn from(owned: Owned<T,>,) -> Self {
+        Atomic { ptr: AtomicPtr::new(owned.into_ptr(),), }
+    }
+}
+
+impl<T,> Default for Atomic<T,> {
+    fn default() -> Self {
+        Atomic::null()
+    }
+}
+
+impl<T,> fmt::Debug for Atomic<T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_tuple("Atomic",).field(&self.ptr.load(Relaxed,),).finish()
+    }
+}
+
+/// A heap-allocated `T` that nobody else can see yet, like a `Box`.
+pub struct Owned<T,> {
+    ptr: NonNull<T,>,
+    _marker: PhantomData<Box<T,>,>,
+}
+
+// SAFETY: owns the `T`, like a `Box`.
+unsafe impl<T: Send,> Send for Owned<T,> {}
+// SAFETY: as above.
+unsafe impl<T: Sync,> Sync for Owned<T,> {}
+
+impl<T,> Owned<T,> {
+    pub fn new(value: T,) -> Self {
+        Owned::from(Box::new(value,),)
+    }
+
+    /// Publishes the value, it's now up to whoever unlinks it to destroy it.
+    pub fn into_shared<'g,>(self, _: &'g Guard,) -> Shared<'g, T,> {
+        // SAFETY: from `into_ptr`.
+        unsafe { Shared::from_ptr(self.into_ptr(),) }
+    }
+
+    pub fn into_box(self,) -> Box<T,> {
+        // SAFETY: the pointer came from a `Box`, and we own it.
+        unsafe { Box::from_raw(self.into_ptr(),) }
+    }
+}
+
+impl<T,> Pointer<T,> for Owned<T,> {
+    fn into_ptr(self,) -> *mut T {
+        let ptr = self.ptr.as_ptr();
+        std::mem::forget(self,);
+        ptr
+    }
+
+    unsafe fn from_ptr(ptr: *mut T,) -> Self {
+        // SAFETY: from `into_ptr` on an `Owned`, so not null (guaranteed by the caller).
+        Owned { ptr: unsafe { NonNull::new_unchecked(ptr,) }, _marker: PhantomData, }
+    }
+}
+
+impl<T,> From<Box<T,>,> for Owned<T,> {
+    fn from(value: Box<T,>,) -> Self {
+        Owned { ptr: NonNull::from(Box::leak(value,),), _marker: PhantomData, }
+    }
+}
+
+impl<T,> Deref for Owned<T,> {
+    type Target = T;
+
+    fn deref(&self,) -> &T {
+        // SAFETY: we own the value.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T,> DerefMut for Owned<T,> {
+    fn deref_mut(&mut self,) -> &mut T {
+        // SAFETY: we own the value.
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T,> Drop for Owned<T,> {
+    fn drop(&mut self,) {
+        // SAFETY: the pointer came from a `Box`, and we own it.
+        drop(unsafe { Box::from_raw(self.ptr.as_ptr(),) },);
+    }
+}
+
+impl<T: fmt::Debug,> fmt::Debug for Owned<T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f,)
+    }
+}
+
+/// A pointer loaded under a guard, valid for as long as the guard lives (`'g`). Might be null.
+pub struct Shared<'g, T,> {
+    ptr: *mut T,
+    _marker: PhantomData<(&'g (), *const T,),>,
+}
+
+impl<T,> Clone for Shared<'_, T,> {
+    fn clone(&self,) -> Self {
+        *self
+    }
+}
+
+impl<T,> Copy for Shared<'_, T,> {}
+
+impl<'g, T,> Shared<'g, T,> {
+    pub const fn null() -> Self {
+        Shared { ptr: ptr::null_mut(), _marker: PhantomData, }
+    }
+
+    pub fn is_null(&self,) -> bool {
+        self.ptr.is_null()
+    }
+
+    pub fn as_raw(&self,) -> *const T {
+        self.ptr
+    }
+
+    /// # Safety
+    ///
+    /// Must not be null, and the value must not have been destroyed before the guard was pinned
+    /// (true of everything loaded from an `Atomic` whose nodes are only destroyed through
+    /// [`Guard::defer_destroy`]).
+    pub unsafe fn deref(&self,) -> &'g T {
+        // SAFETY: guaranteed by the caller.
+        unsafe { &*self.ptr }
+    }
+
+    /// `None` if null.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Shared::deref`], null is fine.
+    pub unsafe fn as_ref(&self,) -> Option<&'g T,> {
+        // SAFETY: guaranteed by the caller.
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// # Safety
+    ///
+    /// Must not be null, and nobody else may use the value anymore.
+    pub unsafe fn into_owned(self,) -> Owned<T,> {
+        // SAFETY: guaranteed by the caller.
+        unsafe { Owned::from_ptr(self.ptr,) }
+    }
+}
+
+impl<T,> Pointer<T,> for Shared<'_, T,> {
+    fn into_ptr(self,) -> *mut T {
+        self.ptr
+    }
+
+    unsafe fn from_ptr(ptr: *mut T,) -> Self {
+        Shared { ptr, _marker: PhantomData, }
+    }
+}
+
+impl<T,> PartialEq for Shared<'_, T,> {
+    fn eq(&self, other: &Self,) -> bool {
+        self.ptr == other.ptr
+    }
+}
+
+impl<T,> Eq for Shared<'_, T,> {}
+
+impl<T,> fmt::Debug for Shared<'_, T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        fmt::Pointer::fmt(&self.ptr, f,)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    #[test]
+    fn flush_collects_after_a_panic_poisoned_the_garbage() {
+        static DROPPED: AtomicBool = AtomicBool::new(false,);
+        struct Flag;
+        impl Drop for Flag {
+            fn drop(&mut self,) {
+                DROPPED.store(true, Relaxed,);
+            }
+        }
+
+        let poison = thread::spawn(|| {
+            let _garbage = GARBAGE.lock();
+            panic!("poisoned");
+        },);
+        assert!(poison.join().is_err() && GARBAGE.is_poisoned());
+        let flag = Box::into_raw(Box::new(Flag,),);
+        let epoch = EPOCH.load(Relaxed,);
+        GARBAGE
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner,)
+            .push_back((epoch, Deferred::new(flag,),),);
+        for _ in 0..10_000 {
+            pin().flush();
+            if DROPPED.load(Relaxed,) {
+                return;
+            }
+            thread::yield_now();
+        }
+        panic!("the garbage was never collected");
+    }
+}
@@ -0,0 +1,167 @@
+//! Events, as ported code from other platforms knows them: a [`ManualResetEvent`] stays set and
+//! lets every waiter through until it is reset, an [`AutoResetEvent`] lets exactly one waiter
+//! through per `set`.
+//!
+//! Both are a single futex word. Waiters only sleep while the word holds what they last saw, so a
+//! `set` between their check and their sleep wakes them right away instead of being lost.
+
+use crate::futex::{self, WaitResult};
+use crate::sync::atomic::AtomicU32;
+use core::fmt;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::time::{Duration, Instant};
+
+const SET: u32 = 1;
+// NOTE: a manual-reset event's generation counts in steps of two above SET, like the barrier's.
+const NEXT_GENERATION: u32 = 2;
+
+/// Once set, every wait returns right away, until it is reset.
+pub struct ManualResetEvent {
+    // NOTE: the futex word: SET, and the generation, bumped by every `set` on an unset event.
+    state: AtomicU32,
+}
+
+impl ManualResetEvent {
+    pub const fn new(set: bool,) -> Self {
+        Self { state: AtomicU32::new(if set { SET } else { 0 },), }
+    }
+
+    /// Sets the event and wakes every waiter. Does nothing if it's set already.
+    pub fn set(&self,) {
+        let mut state = self.state.load(Relaxed,);
+        while state & SET == 0 {
+            // Release: pairs with the Acquire in `wait`.
+            match self.state.compare_exchange_weak(
+                state,
+                state.wrapping_add(NEXT_GENERATION,) | SET,
+                Release,
+                Relaxed,
+            ) {
+                Ok(_,) => {
+                    futex::wake(&self.state, u32::MAX,);
+                    return;
+                }
+                Err(e,) => state = e,
+            }
+        }
+    }
+
+    /// Clears the event, so waits block again. Threads that were waiting when it was set still
+    /// get through, even if they only wake up after this.
+    pub fn reset(&self,) {
+        self.state.fetch_and(!SET, Relaxed,);
+    }
+
+    pub fn is_set(&self,) -> bool {
+        self.state.load(Relaxed,) & SET != 0
+    }
+
+    /// Blocks until the event is set.
+    pub fn wait(&self,) {
+        self.wait_until(None,);
+    }
+
+    /// Like `wait`, but gives up after `timeout`. Returns whether the event was set.
+    pub fn wait_timeout(&self, timeout: Duration,) -> bool {
+        self.wait_until(Instant::now().checked_add(timeout,),)
+    }
+
+    fn wait_until(&self, deadline: Option<Instant,>,) -> bool {
+        let unset = self.state.load(Acquire,);
+        if unset & SET != 0 {
+            return true;
+        }
+        loop {
+            // Any change is a `set`: `reset` on an unset event changes nothing.
+            if futex::wait(&self.state, unset, deadline,) == WaitResult::TimedOut {
+                return self.state.load(Acquire,) != unset;
+            }
+            if self.state.load(Acquire,) != unset {
+                return true;
+            }
+        }
+    }
+}
+
+impl Default for ManualResetEvent {
+    fn default() -> Self {
+        Self::new(false,)
+    }
+}
+
+impl fmt::Debug for ManualResetEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_struct("ManualResetEvent",).field("set", &self.is_set(),).finish()
+    }
+}
+
+/// Each `set` lets exactly one wait through, and the event is unset again. Set with nobody
+/// waiting, it stays set for the next wait.
+pub struct AutoResetEvent {
+    // NOTE: the futex word: SET or 0.
+    state: AtomicU32,
+}
+
+impl AutoResetEvent {
+    pub const fn new(set: bool,) -> Self {
+        Self { state: AtomicU32::new(if set { SET } else { 0 },), }
+    }
+
+    /// Sets the event and wakes one waiter. Does nothing if it's set already: a set that no wait
+    /// took yet isn't counted twice.
+    pub fn set(&self,) {
+        // Release: pairs with the Acquire in `wait`.
+        if self.state.swap(SET, Release,) == 0 {
+            futex::wake(&self.state, 1,);
+        }
+    }
+
+    /// Clears the event without letting anyone through.
+    pub fn reset(&self,) {
+        self.state.store(0, Relaxed,);
+    }
+
+    pub fn is_set(&self,) -> bool {
+        self.state.load(Relaxed,) == SET
+    }
+
+    /// Blocks until the event is set, and unsets it.
+    pub fn wait(&self,) {
+        self.wait_until(None,);
+    }
+
+    /// Like `wait`, but gives up after `timeout`. Returns whether this wait took the event.
+    pub fn wait_timeout(&self, timeout: Duration,) -> bool {
+        self.wait_until(Instant::now().checked_add(timeout,),)
+    }
+
+    fn wait_until(&self, deadline: Option<Instant,>,) -> bool {
+        loop {
+            if self.try_take() {
+                return true;
+            }
+            if futex::wait(&self.state, 0, deadline,) == WaitResult::TimedOut {
+                // The wake for a set may have picked this thread just as it timed out. Then the
+                // set is ours, or nobody else would be woken for it.
+                return self.try_take();
+            }
+        }
+    }
+
+    fn try_take(&self,) -> bool {
+        self.state.load(Relaxed,) == SET
+            && self.state.compare_exchange(SET, 0, Acquire, Relaxed,).is_ok()
+    }
+}
+
+impl Default for AutoResetEvent {
+    fn default() -> Self {
+        Self::new(false,)
+    }
+}
+
+impl fmt::Debug for AutoResetEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_struct("AutoResetEvent",).field("set", &self.is_set(),).finish()
+    }
+}
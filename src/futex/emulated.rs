@@ -0,0 +1,194 @@
+//! The futex operations emulated with std's `Mutex` and `Condvar`, for targets without a futex.
+//!
+//! Waiters are queued in a fixed table of buckets, hashed by address. A waiter compares the value
+//! while holding its bucket's lock and every waker changes the value before taking that lock, so
+//! no wake can slip in between the comparison and going to sleep.
+
+use super::{Op, WaitResult, WakeOp};
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+struct Waiter {
+    /// The address the waiter is queued on. Only changed by `requeue`, with both buckets locked.
+    key: std::sync::atomic::AtomicUsize,
+    woken: Mutex<bool,>,
+    cv: Condvar,
+}
+
+type Queue = VecDeque<Arc<Waiter,>,>;
+
+const BUCKETS: usize = 64;
+
+static TABLE: [Mutex<Queue,>; BUCKETS] = [const { Mutex::new(VecDeque::new(),) }; BUCKETS];
+
+fn key(atomic: &AtomicU32,) -> usize {
+    atomic.as_ptr() as usize
+}
+
+fn index(key: usize,) -> usize {
+    (key / 4) % BUCKETS
+}
+
+// The queues are never left inconsistent, so a panic elsewhere doesn't matter here.
+fn lock<T,>(m: &Mutex<T,>,) -> MutexGuard<'_, T,> {
+    m.lock().unwrap_or_else(PoisonError::into_inner,)
+}
+
+/// Locks the buckets of two keys in a fixed order. The second guard is `None` when both keys
+/// hash to the same bucket.
+fn lock_two(
+    a: usize,
+    b: usize,
+) -> (MutexGuard<'static, Queue,>, Option<MutexGuard<'static, Queue,>,>,) {
+    let (i, j,) = (index(a,), index(b,),);
+    if i == j {
+        (lock(&TABLE[i],), None,)
+    } else if i < j {
+        let first = lock(&TABLE[i],);
+        (first, Some(lock(&TABLE[j],),),)
+    } else {
+        let second = lock(&TABLE[j],);
+        (lock(&TABLE[i],), Some(second,),)
+    }
+}
+
+/// Removes up to `n` waiters on `key` from `queue`.
+fn take(queue: &mut Queue, key: usize, n: u32,) -> Vec<Arc<Waiter,>,> {
+    let mut taken = Vec::new();
+    queue.retain(|w| {
+        if taken.len() < n as usize && w.key.load(Relaxed,) == key {
+            taken.push(w.clone(),);
+            false
+        } else {
+            true
+        }
+    },);
+    taken
+}
+
+fn notify(waiters: Vec<Arc<Waiter,>,>,) -> usize {
+    let n = waiters.len();
+    for w in waiters {
+        *lock(&w.woken,) = true;
+        w.cv.notify_one();
+    }
+    n
+}
+
+/// Takes a timed-out waiter off its queue. Returns `false` if a wake already took it.
+fn dequeue(waiter: &Arc<Waiter,>,) -> bool {
+    loop {
+        let key = waiter.key.load(Relaxed,);
+        let mut queue = lock(&TABLE[index(key,)],);
+        if waiter.key.load(Relaxed,) != key {
+            // Requeued while we were waiting for the lock.
+            continue;
+        }
+        return match queue.iter().position(|w| Arc::ptr_eq(w, waiter,),) {
+            Some(i,) => {
+                queue.remove(i,);
+                true
+            }
+            None => false,
+        };
+    }
+}
+
+pub(crate) fn wait(atomic: &AtomicU32, expected: u32, deadline: Option<Instant,>,) -> WaitResult {
+    let key = key(atomic,);
+    let mut queue = lock(&TABLE[index(key,)],);
+    if atomic.load(SeqCst,) != expected {
+        return WaitResult::Mismatch;
+    }
+    let waiter = Arc::new(Waiter {
+        key: std::sync::atomic::AtomicUsize::new(key,),
+        woken: Mutex::new(false,),
+        cv: Condvar::new(),
+    },);
+    queue.push_back(waiter.clone(),);
+    drop(queue,);
+
+    let mut woken = lock(&waiter.woken,);
+    while !*woken {
+        match deadline {
+            None => woken = waiter.cv.wait(woken,).unwrap_or_else(PoisonError::into_inner,),
+            Some(deadline,) => {
+                let left = deadline.saturating_duration_since(Instant::now(),);
+                if left.is_zero() {
+                    break;
+                }
+                woken =
+                    waiter.cv.wait_timeout(woken, left,).unwrap_or_else(PoisonError::into_inner,).0;
+            }
+        }
+    }
+    if *woken {
+        return WaitResult::Woken;
+    }
+    drop(woken,);
+    if dequeue(&waiter,) { WaitResult::TimedOut } else { WaitResult::Woken }
+}
+
+pub(crate) fn wake(atomic: &AtomicU32, n: u32,) -> usize {
+    let key = key(atomic,);
+    let woken = take(&mut lock(&TABLE[index(key,)],), key, n,);
+    notify(woken,)
+}
+
+/// # Safety
+///
+/// See `futex::requeue`. `to` is only used as an address.
+pub(crate) unsafe fn requeue(
+    from: &AtomicU32,
+    expected: u32,
+    n_wake: u32,
+    to: *const AtomicU32,
+    n_requeue: u32,
+) -> Option<usize,> {
+    let (from_key, to_key,) = (key(from,), to as usize,);
+    let (mut from_queue, mut to_queue,) = lock_two(from_key, to_key,);
+    if from.load(SeqCst,) != expected {
+        return None;
+    }
+    let woken = take(&mut from_queue, from_key, n_wake,);
+    let moved = take(&mut from_queue, from_key, n_requeue,);
+    let n_moved = moved.len();
+    for w in &moved {
+        w.key.store(to_key, Relaxed,);
+    }
+    match &mut to_queue {
+        Some(queue,) => queue.extend(moved,),
+        None => from_queue.extend(moved,),
+    }
+    drop((from_queue, to_queue,),);
+    Some(notify(woken,) + n_moved,)
+}
+
+pub(crate) fn wake_op(
+    first: &AtomicU32,
+    n_first: u32,
+    second: &AtomicU32,
+    n_second: u32,
+    op: WakeOp,
+) -> usize {
+    let (first_key, second_key,) = (key(first,), key(second,),);
+    let (mut first_queue, mut second_queue,) = lock_two(first_key, second_key,);
+    let arg = op.arg as u32;
+    let old = match op.op {
+        Op::Set => second.swap(arg, SeqCst,),
+        Op::Add => second.fetch_add(arg, SeqCst,),
+        Op::Or => second.fetch_or(arg, SeqCst,),
+        Op::AndNot => second.fetch_and(!arg, SeqCst,),
+        Op::Xor => second.fetch_xor(arg, SeqCst,),
+    };
+    let mut woken = take(&mut first_queue, first_key, n_first,);
+    if op.matches(old,) {
+        let queue = second_queue.as_deref_mut().unwrap_or(&mut *first_queue,);
+        woken.extend(take(queue, second_key, n_second,),);
+    }
+    drop((first_queue, second_queue,),);
+    notify(woken,)
+}
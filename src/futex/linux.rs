@@ -0,0 +1,126 @@
+//! The futex operations as direct `futex(2)` syscalls. All of them use `FUTEX_PRIVATE_FLAG`,
+//! the primitives are never shared between processes.
+
+use super::{WaitResult, WakeOp};
+use std::ptr::null;
+use std::sync::atomic::AtomicU32;
+use std::time::Instant;
+
+/// Clamps a wake or requeue count to the `int` the kernel takes.
+fn count(n: u32,) -> u32 {
+    n.min(i32::MAX as u32,)
+}
+
+fn errno() -> Option<i32,> {
+    std::io::Error::last_os_error().raw_os_error()
+}
+
+pub(crate) fn wait(atomic: &AtomicU32, expected: u32, deadline: Option<Instant,>,) -> WaitResult {
+    let timeout = match deadline {
+        None => None,
+        Some(deadline,) => {
+            let left = deadline.saturating_duration_since(Instant::now(),);
+            if left.is_zero() {
+                return if atomic.load(std::sync::atomic::Ordering::Relaxed,) == expected {
+                    WaitResult::TimedOut
+                } else {
+                    WaitResult::Mismatch
+                };
+            }
+            Some(libc::timespec {
+                tv_sec: left.as_secs().try_into().unwrap_or(libc::time_t::MAX,),
+                tv_nsec: left.subsec_nanos() as libc::c_long,
+            },)
+        }
+    };
+    let timeout = timeout.as_ref().map_or(null(), |t| t as *const libc::timespec,);
+    // SAFETY: FUTEX_WAIT only reads the u32 behind the pointer, which is valid for the duration of
+    // the call, and the timespec (if any) which lives on our stack.
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            atomic.as_ptr(),
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            expected,
+            timeout,
+        )
+    };
+    if r == 0 {
+        return WaitResult::Woken;
+    }
+    match errno() {
+        Some(libc::EAGAIN,) => WaitResult::Mismatch,
+        Some(libc::ETIMEDOUT,) => WaitResult::TimedOut,
+        // EINTR: a signal handler ran, same as a spurious wake.
+        _ => WaitResult::Woken,
+    }
+}
+
+pub(crate) fn wake(atomic: &AtomicU32, n: u32,) -> usize {
+    // SAFETY: FUTEX_WAKE doesn't access the memory behind the pointer, it only uses the address.
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            atomic.as_ptr(),
+            libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+            count(n,),
+        )
+    };
+    usize::try_from(r,).unwrap_or(0,)
+}
+
+/// # Safety
+///
+/// See `futex::requeue`. The kernel only uses `to` as an address.
+pub(crate) unsafe fn requeue(
+    from: &AtomicU32,
+    expected: u32,
+    n_wake: u32,
+    to: *const AtomicU32,
+    n_requeue: u32,
+) -> Option<usize,> {
+    // SAFETY: FUTEX_CMP_REQUEUE reads `from`, which is valid for the duration of the call, and
+    // only uses `to` as an address. The requeue count is passed in the timeout slot, as the
+    // kernel expects.
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            from.as_ptr(),
+            libc::FUTEX_CMP_REQUEUE | libc::FUTEX_PRIVATE_FLAG,
+            count(n_wake,),
+            count(n_requeue,) as usize,
+            to.cast::<u32>(),
+            expected,
+        )
+    };
+    // EAGAIN, but also anything else (EINVAL, ENOSYS under seccomp, ...): nobody was moved, and
+    // the caller has to wake them instead.
+    usize::try_from(r,).ok()
+}
+
+pub(crate) fn wake_op(
+    first: &AtomicU32,
+    n_first: u32,
+    second: &AtomicU32,
+    n_second: u32,
+    op: WakeOp,
+) -> usize {
+    let encoded = (op.op as u32) << 28
+        | (op.cmp as u32) << 24
+        | (op.arg as u32 & 0xfff) << 12
+        | (op.cmp_arg as u32 & 0xfff);
+    // SAFETY: FUTEX_WAKE_OP atomically updates `second`, both pointers are valid for the duration
+    // of the call. The second wake count is passed in the timeout slot, as the kernel expects.
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            first.as_ptr(),
+            libc::FUTEX_WAKE_OP | libc::FUTEX_PRIVATE_FLAG,
+            count(n_first,),
+            count(n_second,) as usize,
+            second.as_ptr(),
+            encoded,
+        )
+    };
+    usize::try_from(r,).unwrap_or(0,)
+}
@@ -0,0 +1,233 @@
+//! The futex operations every blocking primitive is built on.
+//!
+//! On Linux these are the `futex` syscall itself. Everywhere else (or with the `futex-emulation`
+//! feature) they are emulated with a table of wait queues protected by std locks. Under
+//! `cfg(model_check)` they are routed to the model checker instead.
+//!
+//! All operations take the crate's `AtomicU32`, so the same call sites work in every build.
+
+#![allow(
+    dead_code,
+    reason = "the whole futex interface is kept, not every primitive uses all of it"
+)]
+
+#[cfg(all(target_os = "linux", not(feature = "futex-emulation")))]
+#[path = "linux.rs"]
+pub(crate) mod os;
+
+#[cfg(any(not(target_os = "linux"), feature = "futex-emulation"))]
+#[path = "emulated.rs"]
+pub(crate) mod os;
+
+#[cfg(not(model_check))]
+use os as imp;
+
+#[cfg(model_check)]
+use crate::model::futex as imp;
+
+use crate::sync::atomic::AtomicU32;
+use std::time::Instant;
+
+/// How a [`wait`] ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub(crate) enum WaitResult {
+    /// Woken by a wake or requeue, or spuriously. Callers have to re-check their condition.
+    Woken,
+    /// The atomic did not hold the expected value, the thread never went to sleep.
+    Mismatch,
+    /// The deadline passed.
+    TimedOut,
+}
+
+/// The operation [`wake_op`] applies to its second atomic. The discriminants are the kernel's
+/// `FUTEX_OP_*` values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub(crate) enum Op {
+    Set = 0,
+    Add = 1,
+    Or = 2,
+    AndNot = 3,
+    Xor = 4,
+}
+
+/// The comparison [`wake_op`] makes between the old value of its second atomic (as an `i32`) and
+/// `cmp_arg`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub(crate) enum Cmp {
+    Eq = 0,
+    Ne = 1,
+    Lt = 2,
+    Le = 3,
+    Gt = 4,
+    Ge = 5,
+}
+
+/// An operation for [`wake_op`]. The kernel packs both arguments into 12 bits, so they have to
+/// be in `-2048..=2047`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub(crate) struct WakeOp {
+    pub(crate) op: Op,
+    pub(crate) arg: i32,
+    pub(crate) cmp: Cmp,
+    pub(crate) cmp_arg: i32,
+}
+
+impl WakeOp {
+    /// The new value of the second atomic.
+    pub(crate) fn apply(self, old: u32,) -> u32 {
+        let arg = self.arg as u32;
+        match self.op {
+            Op::Set => arg,
+            Op::Add => old.wrapping_add(arg,),
+            Op::Or => old | arg,
+            Op::AndNot => old & !arg,
+            Op::Xor => old ^ arg,
+        }
+    }
+
+    /// Whether the waiters on the second atomic are woken, given its old value.
+    pub(crate) fn matches(self, old: u32,) -> bool {
+        let old = old as i32;
+        match self.cmp {
+            Cmp::Eq => old == self.cmp_arg,
+            Cmp::Ne => old != self.cmp_arg,
+            Cmp::Lt => old < self.cmp_arg,
+            Cmp::Le => old <= self.cmp_arg,
+            Cmp::Gt => old > self.cmp_arg,
+            Cmp::Ge => old >= self.cmp_arg,
+        }
+    }
+}
+
+/// Blocks until woken, if `atomic` holds `expected`. `None` waits without a deadline.
+#[inline]
+pub(crate) fn wait(atomic: &AtomicU32, expected: u32, deadline: Option<Instant,>,) -> WaitResult {
+    imp::wait(atomic, expected, deadline,)
+}
+
+/// Wakes up to `n` threads waiting on `atomic`, returns how many were woken.
+#[inline]
+pub(crate) fn wake(atomic: &AtomicU32, n: u32,) -> usize {
+    imp::wake(atomic, n,)
+}
+
+/// If `from` holds `expected`, wakes up to `n_wake` of its waiters and moves up to `n_requeue`
+/// of the others over to `to`, without waking them. Returns the number of threads woken or
+/// moved, or `None` if `from` did not hold `expected` or the requeue failed for another reason.
+/// On `None` nothing was moved, and the caller has to wake the waiters some other way.
+///
+/// `to` is taken as a pointer because the caller usually can't prove it's still alive: a waiter
+/// could have left and freed it in the meantime. That's fine as long as nobody is left to move.
+///
+/// # Safety
+///
+/// `to` must point to a live `AtomicU32` for as long as any thread is waiting on `from`.
+#[inline]
+pub(crate) unsafe fn requeue(
+    from: &AtomicU32,
+    expected: u32,
+    n_wake: u32,
+    to: *const AtomicU32,
+    n_requeue: u32,
+) -> Option<usize,> {
+    // SAFETY: forwarded from our caller.
+    unsafe { imp::requeue(from, expected, n_wake, to, n_requeue,) }
+}
+
+/// Atomically applies `op` to `second`, wakes up to `n_first` waiters on `first`, and, if the old
+/// value of `second` matches `op`'s comparison, up to `n_second` waiters on `second`. Returns the
+/// number of threads woken. The update of `second` is `SeqCst`.
+#[inline]
+pub(crate) fn wake_op(
+    first: &AtomicU32,
+    n_first: u32,
+    second: &AtomicU32,
+    n_second: u32,
+    op: WakeOp,
+) -> usize {
+    imp::wake_op(first, n_first, second, n_second, op,)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Long enough for spawned threads to have gone to sleep.
+    fn settle() {
+        thread::sleep(Duration::from_millis(100,),);
+    }
+
+    #[test]
+    fn wait_mismatch_and_timeout() {
+        let a = AtomicU32::new(1,);
+        assert_eq!(wait(&a, 0, None), WaitResult::Mismatch);
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(50,);
+        assert_eq!(wait(&a, 1, Some(deadline)), WaitResult::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(wait(&a, 1, Some(start)), WaitResult::TimedOut);
+    }
+
+    #[test]
+    fn wake_and_requeue() {
+        let from = AtomicU32::new(0,);
+        let to = AtomicU32::new(0,);
+        let done = AtomicU32::new(0,);
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    while from.load(Acquire,) == 0 {
+                        wait(&from, 0, None,);
+                    }
+                    done.fetch_add(1, Relaxed,);
+                },);
+            }
+            settle();
+            // SAFETY: `to` outlives every thread waiting on `from`.
+            assert_eq!(unsafe { requeue(&from, 1, 1, &to, u32::MAX) }, None);
+            // One is woken, but goes right back to sleep, the other two move over to `to`.
+            // SAFETY: as above.
+            assert_eq!(unsafe { requeue(&from, 0, 1, &to, u32::MAX) }, Some(3));
+            settle();
+            from.store(1, Release,);
+            assert!(wake(&from, u32::MAX,) <= 1);
+            assert_eq!(wake(&to, u32::MAX), 2);
+        },);
+        assert_eq!(done.into_inner(), 3);
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "futex-emulation")))]
+    #[test]
+    fn failed_requeue_is_none() {
+        let from = AtomicU32::new(0,);
+        let to = [AtomicU32::new(0,), AtomicU32::new(0,),];
+        // A misaligned `to` makes the kernel fail with EINVAL rather than EAGAIN.
+        let misaligned = to.as_ptr().cast::<u8>().wrapping_add(1,).cast::<AtomicU32>();
+        // SAFETY: nobody waits on `from`, and the kernel only uses `to` as an address.
+        assert_eq!(unsafe { requeue(&from, 0, 1, misaligned, u32::MAX) }, None);
+    }
+
+    #[test]
+    fn wake_op_updates_and_wakes() {
+        let first = AtomicU32::new(0,);
+        let second = AtomicU32::new(5,);
+        let op = WakeOp { op: Op::Add, arg: 2, cmp: Cmp::Eq, cmp_arg: 5, };
+        assert_eq!(wake_op(&first, 1, &second, 1, op), 0);
+        assert_eq!(second.load(Relaxed), 7);
+        thread::scope(|s| {
+            s.spawn(|| while wait(&second, 7, None,) != WaitResult::Mismatch {},);
+            settle();
+            let op = WakeOp { op: Op::Set, arg: -1, cmp: Cmp::Lt, cmp_arg: 0, };
+            // The old value (7) isn't negative, so nobody on `second` is woken...
+            assert_eq!(wake_op(&first, 1, &second, 1, op), 0);
+            assert_eq!(second.load(Relaxed), u32::MAX);
+            // ...until it is.
+            let op = WakeOp { op: Op::AndNot, arg: -1, cmp: Cmp::Lt, cmp_arg: 0, };
+            assert_eq!(wake_op(&first, 1, &second, 1, op), 1);
+            assert_eq!(second.load(Relaxed), 0);
+        },);
+    }
+}
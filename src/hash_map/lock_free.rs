@@ -0,0 +1,425 @@
+//! A lock-free, open-addressing hash map for read-mostly workloads.
+//!
+//! The table is an array of slots probed linearly, each a key pointer and a value pointer. A key
+//! is set once, when an insert claims its slot, and stays there; a value pointer is null while
+//! the key is absent. Replaced and removed values are freed through [`crate::epoch`], so a reader
+//! only pins and loads, it never writes to shared memory.
+//!
+//! Once 3/4 of the slots are claimed, the table is replaced by a new one sized for the live
+//! entries, which drops the keys removed in the meantime. The resize seals the old table, waits
+//! for the writers in it to finish, and moves the entries over. Readers carry on in the old table
+//! meanwhile (it holds every entry until the new one is published), but writers wait for the new
+//! one, so heavy writing is better left to [`ConcurrentHashMap`](super::ConcurrentHashMap).
+//!
+//! Those values (and the keys of removed entries) may be dropped on any thread, after the map
+//! itself is gone, so keys and values have to be `'static`.
+//!
+//! Like [`crate::epoch`], this uses std's atomics.
+
+use crate::epoch::{self, Atomic, Guard, Owned, Shared};
+use crate::mutex::Mutex;
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, RandomState};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release, SeqCst};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+/// The smallest table.
+const MIN_CAPACITY: usize = 16;
+
+/// How often a resize spins on the writers of the old table before yielding to them.
+const SPINS: usize = 64;
+
+struct Slot<K, V,> {
+    /// Null until an insert claims the slot, never changed after (but moved on resize).
+    key: Atomic<K,>,
+    /// Null while the key is absent.
+    value: Atomic<V,>,
+}
+
+struct Table<K, V,> {
+    slots: Box<[Slot<K, V,>],>,
+    /// Slots with a key. Kept under 3/4 of the capacity, so probes always reach an empty slot.
+    claimed: AtomicUsize,
+    /// Writers working in the table. A resize waits for it to drop to 0 once sealed.
+    writers: AtomicUsize,
+    /// Set by the resize that is replacing the table. No writer enters after.
+    sealed: AtomicBool,
+}
+
+impl<K, V,> Table<K, V,> {
+    fn new(capacity: usize,) -> Self {
+        Table {
+            slots: (0..capacity)
+                .map(|_| Slot { key: Atomic::null(), value: Atomic::null(), },)
+                .collect(),
+            claimed: AtomicUsize::new(0,),
+            writers: AtomicUsize::new(0,),
+            sealed: AtomicBool::new(false,),
+        }
+    }
+
+    fn is_full(&self, claimed: usize,) -> bool {
+        claimed >= self.slots.len() / 4 * 3
+    }
+
+    /// The slots to look at for `hash`, in order.
+    fn probe(&self, hash: u64,) -> impl Iterator<Item = &Slot<K, V,>,> {
+        let mask = self.slots.len() - 1;
+        let start = hash as usize & mask;
+        (0..self.slots.len()).map(move |i| &self.slots[(start + i) & mask],)
+    }
+}
+
+/// What a write in a table came to.
+enum Write<K, V,> {
+    /// With the value it replaced.
+    Done(Option<V,>,),
+    /// Sealed, or no slot left to claim. Hands the key and value back.
+    Retry(Owned<K,>, Owned<V,>,),
+}
+
+/// A lock-free hash map. Lookups never block or write to shared memory; writers only wait while
+/// the table is being resized.
+pub struct LockFreeHashMap<K, V, S = RandomState,> {
+    table: Atomic<Table<K, V,>,>,
+    /// Held by the resize in progress.
+    resize: Mutex<(),>,
+    len: AtomicUsize,
+    hasher: S,
+}
+
+// SAFETY: keys and values are shared between threads, and dropped on whichever thread frees them.
+unsafe impl<K: Send + Sync + 'static, V: Send + Sync + 'static, S: Send,> Send
+    for LockFreeHashMap<K, V, S,>
+{
+}
+// SAFETY: as above.
+unsafe impl<K: Send + Sync + 'static, V: Send + Sync + 'static, S: Sync,> Sync
+    for LockFreeHashMap<K, V, S,>
+{
+}
+
+// NOTE: `'static` because what the map replaces or removes is dropped by the epoch collector,
+// whenever it gets to it: borrowed data could be gone by then.
+impl<K: Hash + Eq + 'static, V: 'static,> LockFreeHashMap<K, V,> {
+    pub fn new() -> Self {
+        Self::with_capacity(0,)
+    }
+
+    /// A map that holds `capacity` entries before its first resize.
+    pub fn with_capacity(capacity: usize,) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new(),)
+    }
+}
+
+impl<K: Hash + Eq + 'static, V: 'static, S: BuildHasher,> LockFreeHashMap<K, V, S,> {
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S,) -> Self {
+        LockFreeHashMap {
+            table: Atomic::new(Table::new(table_capacity(capacity,),),),
+            resize: Mutex::new((),),
+            len: AtomicUsize::new(0,),
+            hasher,
+        }
+    }
+
+    /// The value for `key`. The epoch stays pinned while the `Ref` lives, so don't keep it long.
+    pub fn get<Q,>(&self, key: &Q,) -> Option<Ref<'_, K, V,>,>
+    where
+        K: Borrow<Q,>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let guard = epoch::pin();
+        let (key, value,) = self.find(key, &guard,)?;
+        Some(Ref { key: key.as_raw(), value: value.as_raw(), _guard: guard, _map: PhantomData, },)
+    }
+
+    pub fn contains_key<Q,>(&self, key: &Q,) -> bool
+    where
+        K: Borrow<Q,>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key, &epoch::pin(),).is_some()
+    }
+
+    fn find<'g, Q,>(&self, key: &Q, guard: &'g Guard,) -> Option<(Shared<'g, K,>, Shared<'g, V,>,),>
+    where
+        K: Borrow<Q,>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hasher.hash_one(key,);
+        let mut table = self.table.load(SeqCst, guard,);
+        loop {
+            let mut found = None;
+            // SAFETY: tables are only destroyed through `defer_destroy`, and we are pinned.
+            for slot in unsafe { table.deref() }.probe(hash,) {
+                let k = slot.key.load(Acquire, guard,);
+                // SAFETY: keys are only destroyed through `defer_destroy`, or with the map.
+                let Some(k_ref,) = (unsafe { k.as_ref() }) else { break };
+                if k_ref.borrow() == key {
+                    let value = slot.value.load(Acquire, guard,);
+                    found = (!value.is_null()).then_some((k, value,),);
+                    break;
+                }
+            }
+            // A resize may have published a new table, and writes there wouldn't show here.
+            let now = self.table.load(SeqCst, guard,);
+            if now == table {
+                return found;
+            }
+            table = now;
+        }
+    }
+
+    /// Inserts `value` under `key`. Returns the value it replaced, cloned: other threads may
+    /// still be reading it, so it is only dropped once they are done.
+    pub fn insert(&self, key: K, value: V,) -> Option<V,>
+    where
+        V: Clone,
+    {
+        let hash = self.hasher.hash_one(&key,);
+        let (mut key, mut value,) = (Owned::new(key,), Owned::new(value,),);
+        loop {
+            match self.write(hash, key, value,) {
+                Write::Done(old,) => return old,
+                Write::Retry(k, v,) => (key, value,) = (k, v,),
+            }
+        }
+    }
+
+    /// Removes `key`. Returns its value, cloned, as with `insert`.
+    pub fn remove<Q,>(&self, key: &Q,) -> Option<V,>
+    where
+        K: Borrow<Q,>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        let hash = self.hasher.hash_one(key,);
+        let guard = epoch::pin();
+        loop {
+            let table = self.table.load(SeqCst, &guard,);
+            // SAFETY: as in `find`.
+            let t = unsafe { table.deref() };
+            if !self.enter(t,) {
+                continue;
+            }
+            let mut removed = None;
+            for slot in t.probe(hash,) {
+                let k = slot.key.load(Acquire, &guard,);
+                // SAFETY: as in `find`.
+                let Some(k,) = (unsafe { k.as_ref() }) else { break };
+                if k.borrow() == key {
+                    removed = self.replace(slot, Shared::null(), &guard,);
+                    break;
+                }
+            }
+            t.writers.fetch_sub(1, Release,);
+            return removed;
+        }
+    }
+
+    /// The number of entries. With concurrent writers, the map may never have held exactly this
+    /// many.
+    pub fn len(&self,) -> usize {
+        self.len.load(Relaxed,)
+    }
+
+    pub fn is_empty(&self,) -> bool {
+        self.len() == 0
+    }
+
+    /// Registers as a writer in `table`. False if it is sealed, after waiting for the resize.
+    fn enter(&self, table: &Table<K, V,>,) -> bool {
+        table.writers.fetch_add(1, SeqCst,);
+        // Pairs with `resize`: either it sees us in `writers`, or we see the seal.
+        if !table.sealed.load(SeqCst,) {
+            return true;
+        }
+        table.writers.fetch_sub(1, Release,);
+        // The resize holds the lock until the new table is published.
+        drop(self.resize.lock(),);
+        false
+    }
+
+    fn write(&self, hash: u64, key: Owned<K,>, value: Owned<V,>,) -> Write<K, V,>
+    where
+        V: Clone,
+    {
+        let guard = epoch::pin();
+        let table = self.table.load(SeqCst, &guard,);
+        // SAFETY: as in `find`.
+        let t = unsafe { table.deref() };
+        if !self.enter(t,) {
+            return Write::Retry(key, value,);
+        }
+        let mut key = key;
+        for slot in t.probe(hash,) {
+            let mut k = slot.key.load(Acquire, &guard,);
+            if k.is_null() {
+                if t.is_full(t.claimed.fetch_add(1, Relaxed,),) {
+                    t.claimed.fetch_sub(1, Relaxed,);
+                    t.writers.fetch_sub(1, Release,);
+                    self.resize(table, &guard,);
+                    return Write::Retry(key, value,);
+                }
+                match slot.key.compare_exchange(Shared::null(), key, AcqRel, Acquire, &guard,) {
+                    Ok(_,) => {
+                        let old = self.replace(slot, value.into_shared(&guard,), &guard,);
+                        t.writers.fetch_sub(1, Release,);
+                        return Write::Done(old,);
+                    }
+                    Err(e,) => {
+                        t.claimed.fetch_sub(1, Relaxed,);
+                        (k, key,) = (e.current, e.new,);
+                    }
+                }
+            }
+            // SAFETY: as in `find`.
+            if unsafe { k.deref() } == &*key {
+                let old = self.replace(slot, value.into_shared(&guard,), &guard,);
+                t.writers.fetch_sub(1, Release,);
+                return Write::Done(old,);
+            }
+        }
+        unreachable!("the table is never full")
+    }
+
+    /// Swaps `value` into `slot` and frees the old one. Returns a clone of the old one.
+    fn replace(&self, slot: &Slot<K, V,>, value: Shared<'_, V,>, guard: &Guard,) -> Option<V,>
+    where
+        V: Clone,
+    {
+        let inserting = !value.is_null();
+        let old = slot.value.swap(value, AcqRel, guard,);
+        if old.is_null() == inserting {
+            if inserting {
+                self.len.fetch_add(1, Relaxed,);
+            } else {
+                self.len.fetch_sub(1, Relaxed,);
+            }
+        }
+        // SAFETY: values are only destroyed through `defer_destroy`, and we are pinned.
+        let replaced = unsafe { old.as_ref() }.cloned();
+        if !old.is_null() {
+            // SAFETY: swapped out, so unlinked, and values come from `Owned`.
+            unsafe { guard.defer_destroy(old,) };
+        }
+        replaced
+    }
+
+    /// Replaces `table` with one sized for its live entries, unless that already happened.
+    fn resize(&self, table: Shared<'_, Table<K, V,>,>, guard: &Guard,) {
+        let _lock = self.resize.lock();
+        if self.table.load(SeqCst, guard,) != table {
+            return;
+        }
+        // SAFETY: as in `find`.
+        let old = unsafe { table.deref() };
+        old.sealed.store(true, SeqCst,);
+        let mut spins = 0;
+        // Acquire: their writes to the slots happen before we move them.
+        while old.writers.load(SeqCst,) != 0 {
+            if spins < SPINS {
+                spins += 1;
+                std::hint::spin_loop();
+            } else {
+                std::thread::yield_now();
+            }
+        }
+        let live = old.slots.iter().filter(|s| !s.value.load(Relaxed, guard,).is_null(),).count();
+        let new = Table::new(table_capacity(live + 1,),);
+        for slot in old.slots.iter() {
+            let key = slot.key.load(Relaxed, guard,);
+            let value = slot.value.load(Relaxed, guard,);
+            if key.is_null() {
+                continue;
+            }
+            if value.is_null() {
+                // Removed. Readers of the old table may still be comparing it.
+                // SAFETY: it doesn't go in the new table, and keys come from `Owned`.
+                unsafe { guard.defer_destroy(key,) };
+                continue;
+            }
+            // SAFETY: keys are alive until the old table is gone.
+            let hash = self.hasher.hash_one(unsafe { key.deref() },);
+            let empty = new.probe(hash,).find(|s| s.key.load(Relaxed, guard,).is_null(),);
+            let Some(empty,) = empty else { unreachable!("the new table has room for everything") };
+            empty.key.store(key, Relaxed,);
+            empty.value.store(value, Relaxed,);
+        }
+        new.claimed.store(live, Relaxed,);
+        let old = self.table.swap(Owned::new(new,), SeqCst, guard,);
+        // SAFETY: replaced, so unlinked. Its keys and values moved to the new table, and dropping
+        // a `Table` doesn't drop them.
+        unsafe { guard.defer_destroy(old,) };
+    }
+}
+
+/// A power of two with room for `entries` below the resize threshold.
+fn table_capacity(entries: usize,) -> usize {
+    (entries * 2).next_power_of_two().max(MIN_CAPACITY,)
+}
+
+impl<K: Hash + Eq + 'static, V: 'static,> Default for LockFreeHashMap<K, V,> {
+    fn default() -> Self {
+        LockFreeHashMap::new()
+    }
+}
+
+impl<K, V, S,> Drop for LockFreeHashMap<K, V, S,> {
+    fn drop(&mut self,) {
+        let table = std::mem::take(&mut self.table,);
+        // SAFETY: we have `&mut self`, so no thread is in the map, and what it replaced or removed
+        // went through `defer_destroy`. What is left in the table is ours.
+        if let Some(table,) = unsafe { table.into_owned() } {
+            for slot in table.into_box().slots.into_vec() {
+                // SAFETY: as above.
+                unsafe { drop((slot.key.into_owned(), slot.value.into_owned(),),) };
+            }
+        }
+    }
+}
+
+impl<K, V, S,> fmt::Debug for LockFreeHashMap<K, V, S,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_struct("LockFreeHashMap",)
+            .field("len", &self.len.load(Relaxed,),)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A value in a [`LockFreeHashMap`], kept alive by a pinned epoch.
+pub struct Ref<'a, K, V,> {
+    key: *const K,
+    value: *const V,
+    _guard: Guard,
+    _map: PhantomData<&'a (K, V,),>,
+}
+
+impl<K, V,> Ref<'_, K, V,> {
+    pub fn key(&self,) -> &K {
+        // SAFETY: loaded under the guard, and the map outlives us.
+        unsafe { &*self.key }
+    }
+
+    pub fn value(&self,) -> &V {
+        // SAFETY: as above.
+        unsafe { &*self.value }
+    }
+}
+
+impl<K, V,> Deref for Ref<'_, K, V,> {
+    type Target = V;
+
+    fn deref(&self,) -> &V {
+        self.value()
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug,> fmt::Debug for Ref<'_, K, V,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_tuple("Ref",).field(self.key(),).field(self.value(),).finish()
+    }
+}
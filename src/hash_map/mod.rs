@@ -0,0 +1,341 @@
+//! Concurrent hash maps.
+//!
+//! [`ConcurrentHashMap`] splits its entries over a number of shards, each a `HashMap` behind one
+//! of the crate's [`RwLock`]s, so a writer only blocks the readers (and writers) of its own shard.
+//! [`LockFreeHashMap`] never blocks readers at all, and is meant for maps that are read far more
+//! often than they are written.
+
+pub mod lock_free;
+
+pub use lock_free::LockFreeHashMap;
+
+use crate::rwlock::{ReadGuard, RwLock, WriteGuard};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, RandomState};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+/// Shards per available thread, by default.
+const SHARDS_PER_THREAD: usize = 4;
+
+type Shard<K, V, S,> = RwLock<HashMap<K, V, S,>,>;
+
+/// A hash map sharded over `RwLock`s. Lookups return guards that keep their shard read-locked
+/// (or write-locked, for [`get_mut`](Self::get_mut) and [`entry`](Self::entry)), so don't hold
+/// on to one while touching another key of the same map on the same thread.
+pub struct ConcurrentHashMap<K, V, S = RandomState,> {
+    shards: Box<[Shard<K, V, S,>],>,
+    hasher: S,
+}
+
+impl<K: Hash + Eq, V,> ConcurrentHashMap<K, V,> {
+    /// A map with a few shards per available thread.
+    pub fn new() -> Self {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get(),);
+        Self::with_shards(threads * SHARDS_PER_THREAD,)
+    }
+
+    /// A map with `shards` shards, rounded up to a power of two.
+    pub fn with_shards(shards: usize,) -> Self {
+        Self::with_shards_and_hasher(shards, RandomState::new(),)
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone,> ConcurrentHashMap<K, V, S,> {
+    pub fn with_shards_and_hasher(shards: usize, hasher: S,) -> Self {
+        let shards = shards.max(1,).next_power_of_two();
+        ConcurrentHashMap {
+            shards: (0..shards)
+                .map(|_| RwLock::new(HashMap::with_hasher(hasher.clone(),),),)
+                .collect(),
+            hasher,
+        }
+    }
+
+    fn shard<Q: Hash + ?Sized,>(&self, key: &Q,) -> &Shard<K, V, S,> {
+        // The shards hash with the same hasher and pick buckets by the low bits, so take the
+        // shard from the upper half, or every shard would only use a fraction of its buckets.
+        let hash = self.hasher.hash_one(key,);
+        &self.shards[(hash >> 32) as usize & (self.shards.len() - 1)]
+    }
+
+    /// The value for `key`, with its shard read-locked until the `Ref` is dropped.
+    pub fn get<Q,>(&self, key: &Q,) -> Option<Ref<'_, K, V, S,>,>
+    where
+        K: Borrow<Q,>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let guard = self.shard(key,).read();
+        let (key, value,) = guard.get_key_value(key,)?;
+        let (key, value,) = (NonNull::from(key,), NonNull::from(value,),);
+        Some(Ref { _guard: guard, key, value, },)
+    }
+
+    /// The value for `key`, with its shard write-locked until the `RefMut` is dropped.
+    pub fn get_mut<Q,>(&self, key: &Q,) -> Option<RefMut<'_, K, V, S,>,>
+    where
+        K: Borrow<Q,>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut guard = self.shard(key,).write();
+        let value = NonNull::from(guard.get_mut(key,)?,);
+        Some(RefMut { _guard: guard, value, },)
+    }
+
+    pub fn contains_key<Q,>(&self, key: &Q,) -> bool
+    where
+        K: Borrow<Q,>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key,).read().contains_key(key,)
+    }
+
+    /// Inserts `value` under `key`, returning the value it replaced.
+    pub fn insert(&self, key: K, value: V,) -> Option<V,> {
+        self.shard(&key,).write().insert(key, value,)
+    }
+
+    pub fn remove<Q,>(&self, key: &Q,) -> Option<V,>
+    where
+        K: Borrow<Q,>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key,).write().remove(key,)
+    }
+
+    /// The entry for `key`, with its shard write-locked until the entry (or the `RefMut` it turns
+    /// into) is dropped.
+    pub fn entry(&self, key: K,) -> Entry<'_, K, V, S,> {
+        let mut guard = self.shard(&key,).write();
+        match guard.get_mut(&key,).map(NonNull::from,) {
+            Some(value,) => Entry::Occupied(OccupiedEntry { guard, key, value, },),
+            None => Entry::Vacant(VacantEntry { guard, key, },),
+        }
+    }
+
+    /// Keeps only the entries `f` returns true for, one shard at a time.
+    pub fn retain(&self, mut f: impl FnMut(&K, &mut V,) -> bool,) {
+        for shard in self.shards.iter() {
+            shard.write().retain(&mut f,);
+        }
+    }
+
+    pub fn clear(&self,) {
+        for shard in self.shards.iter() {
+            shard.write().clear();
+        }
+    }
+
+    /// The number of entries, counted one shard at a time. With concurrent writers, the map may
+    /// never have held exactly this many.
+    pub fn len(&self,) -> usize {
+        self.shards.iter().map(|s| s.read().len(),).sum()
+    }
+
+    pub fn is_empty(&self,) -> bool {
+        self.shards.iter().all(|s| s.read().is_empty(),)
+    }
+
+    /// The shards, each read-locked while the iterator is on it, for iterating over the map a
+    /// shard at a time.
+    pub fn shards(&self,) -> impl Iterator<Item = ReadGuard<'_, HashMap<K, V, S,>,>,> {
+        self.shards.iter().map(RwLock::read,)
+    }
+
+    /// The shards, each write-locked while the iterator is on it.
+    pub fn shards_mut(&self,) -> impl Iterator<Item = WriteGuard<'_, HashMap<K, V, S,>,>,> {
+        self.shards.iter().map(RwLock::write,)
+    }
+}
+
+impl<K: Hash + Eq, V,> Default for ConcurrentHashMap<K, V,> {
+    fn default() -> Self {
+        ConcurrentHashMap::new()
+    }
+}
+
+impl<K: Hash + Eq, V,> FromIterator<(K, V,),> for ConcurrentHashMap<K, V,> {
+    fn from_iter<I: IntoIterator<Item = (K, V,),>,>(iter: I,) -> Self {
+        let map = ConcurrentHashMap::new();
+        for (k, v,) in iter {
+            map.insert(k, v,);
+        }
+        map
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S,> fmt::Debug for ConcurrentHashMap<K, V, S,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        let mut map = f.debug_map();
+        for shard in self.shards.iter() {
+            map.entries(shard.read().iter(),);
+        }
+        map.finish()
+    }
+}
+
+/// A value in a [`ConcurrentHashMap`], with its shard read-locked.
+pub struct Ref<'a, K, V, S = RandomState,> {
+    _guard: ReadGuard<'a, HashMap<K, V, S,>,>,
+    // Point into the shard, which can't change while we hold the guard.
+    key: NonNull<K,>,
+    value: NonNull<V,>,
+}
+
+impl<K, V, S,> Ref<'_, K, V, S,> {
+    pub fn key(&self,) -> &K {
+        // SAFETY: the shard is read-locked, so the entry is still there.
+        unsafe { self.key.as_ref() }
+    }
+
+    pub fn value(&self,) -> &V {
+        // SAFETY: as above.
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<K, V, S,> Deref for Ref<'_, K, V, S,> {
+    type Target = V;
+
+    fn deref(&self,) -> &V {
+        self.value()
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S,> fmt::Debug for Ref<'_, K, V, S,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_tuple("Ref",).field(self.key(),).field(self.value(),).finish()
+    }
+}
+
+/// A value in a [`ConcurrentHashMap`], with its shard write-locked.
+pub struct RefMut<'a, K, V, S = RandomState,> {
+    _guard: WriteGuard<'a, HashMap<K, V, S,>,>,
+    // Points into the shard, which nobody else can touch while we hold the guard.
+    value: NonNull<V,>,
+}
+
+impl<K, V, S,> Deref for RefMut<'_, K, V, S,> {
+    type Target = V;
+
+    fn deref(&self,) -> &V {
+        // SAFETY: the shard is write-locked, so the entry is still there.
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<K, V, S,> DerefMut for RefMut<'_, K, V, S,> {
+    fn deref_mut(&mut self,) -> &mut V {
+        // SAFETY: as above, and the lock is ours alone.
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<K, V: fmt::Debug, S,> fmt::Debug for RefMut<'_, K, V, S,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_tuple("RefMut",).field(&**self,).finish()
+    }
+}
+
+/// An entry of a [`ConcurrentHashMap`], see [`ConcurrentHashMap::entry`].
+pub enum Entry<'a, K, V, S = RandomState,> {
+    Occupied(OccupiedEntry<'a, K, V, S,>,),
+    Vacant(VacantEntry<'a, K, V, S,>,),
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher,> Entry<'a, K, V, S,> {
+    pub fn key(&self,) -> &K {
+        match self {
+            Entry::Occupied(e,) => e.key(),
+            Entry::Vacant(e,) => e.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V,) -> RefMut<'a, K, V, S,> {
+        self.or_insert_with(|| default,)
+    }
+
+    pub fn or_insert_with(self, default: impl FnOnce() -> V,) -> RefMut<'a, K, V, S,> {
+        match self {
+            Entry::Occupied(e,) => e.into_ref(),
+            Entry::Vacant(e,) => e.insert(default(),),
+        }
+    }
+
+    pub fn or_default(self,) -> RefMut<'a, K, V, S,>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default,)
+    }
+
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V,),) -> Self {
+        if let Entry::Occupied(e,) = &mut self {
+            f(e.get_mut(),);
+        }
+        self
+    }
+}
+
+pub struct OccupiedEntry<'a, K, V, S = RandomState,> {
+    guard: WriteGuard<'a, HashMap<K, V, S,>,>,
+    key: K,
+    // Points into the shard, which nobody else can touch while we hold the guard, and which we
+    // only change through this pointer or by removing the entry, which consumes us.
+    value: NonNull<V,>,
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher,> OccupiedEntry<'a, K, V, S,> {
+    /// The key the entry was looked up with.
+    pub fn key(&self,) -> &K {
+        &self.key
+    }
+
+    pub fn get(&self,) -> &V {
+        // SAFETY: see `value`.
+        unsafe { self.value.as_ref() }
+    }
+
+    pub fn get_mut(&mut self,) -> &mut V {
+        // SAFETY: see `value`.
+        unsafe { self.value.as_mut() }
+    }
+
+    /// Replaces the value, returning the old one.
+    pub fn insert(&mut self, value: V,) -> V {
+        std::mem::replace(self.get_mut(), value,)
+    }
+
+    pub fn remove(mut self,) -> V {
+        match self.guard.remove(&self.key,) {
+            Some(value,) => value,
+            None => unreachable!("an occupied entry's key is in the map"),
+        }
+    }
+
+    pub fn into_ref(self,) -> RefMut<'a, K, V, S,> {
+        RefMut { _guard: self.guard, value: self.value, }
+    }
+}
+
+pub struct VacantEntry<'a, K, V, S = RandomState,> {
+    guard: WriteGuard<'a, HashMap<K, V, S,>,>,
+    key: K,
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher,> VacantEntry<'a, K, V, S,> {
+    pub fn key(&self,) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self,) -> K {
+        self.key
+    }
+
+    pub fn insert(mut self, value: V,) -> RefMut<'a, K, V, S,> {
+        let value = NonNull::from(self.guard.entry(self.key,).or_insert(value,),);
+        RefMut { _guard: self.guard, value, }
+    }
+}
@@ -0,0 +1,314 @@
+//! Hazard pointers, for freeing the nodes of lock-free structures while other threads may still
+//! be reading them.
+//!
+//! A thread that is about to dereference a shared pointer first publishes it in a
+//! [`HazardPointer`]. A thread that unlinks a node doesn't free it, it [`retire`]s it, and retired
+//! nodes are only freed by a scan that finds no hazard pointer protecting them. Scans run once a
+//! thread has retired enough nodes (at least [`SCAN_THRESHOLD`], and more when there are many
+//! hazard pointers, so every scan frees a fair share), or on demand with [`scan`].
+//!
+//! The hazard slots live in a global list and are never freed. A thread keeps the slots of the
+//! hazard pointers it dropped and hands them out again, and gives them back to everyone when it
+//! exits. Whatever it retired and couldn't free by then is left for the next thread that scans.
+//!
+//! The registry is global and outlives any model execution, so this module uses std's atomics
+//! directly rather than going through [`crate::sync`].
+//!
+//! A Treiber stack, the classic user:
+//!
+//! ```
+//! use atomics_locks::hazard::{self, HazardPointer};
+//! use std::marker::PhantomData;
+//! use std::mem::ManuallyDrop;
+//! use std::ptr;
+//! use std::sync::atomic::AtomicPtr;
+//! use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+//!
+//! struct Node<T> {
+//!     // Moved out by `pop`, so dropping the node mustn't drop it again.
+//!     value: ManuallyDrop<T>,
+//!     next: *mut Node<T>,
+//! }
+//!
+//! pub struct Stack<T> {
+//!     head: AtomicPtr<Node<T>>,
+//!     _marker: PhantomData<T>,
+//! }
+//!
+//! impl<T> Stack<T> {
+//!     pub fn push(&self, value: T) {
+//!         let node = Box::into_raw(Box::new(Node {
+//!             value: ManuallyDrop::new(value),
+//!             next: ptr::null_mut(),
+//!         }));
+//!         let mut head = self.head.load(Relaxed);
+//!         loop {
+//!             // SAFETY: not published yet, the node is still ours.
+//!             unsafe { (*node).next = head };
+//!             match self.head.compare_exchange_weak(head, node, Release, Relaxed) {
+//!                 Ok(_) => return,
+//!                 Err(now) => head = now,
+//!             }
+//!         }
+//!     }
+//!
+//!     pub fn pop(&self) -> Option<T> {
+//!         let mut hazard = HazardPointer::new();
+//!         loop {
+//!             let head = hazard.protect(&self.head);
+//!             if head.is_null() {
+//!                 return None;
+//!             }
+//!             // SAFETY: protected, so not freed even if another thread pops it first. And as
+//!             // it can't be freed, it can't come back either, so the CAS below has no ABA.
+//!             let next = unsafe { (*head).next };
+//!             if self.head.compare_exchange(head, next, Acquire, Relaxed).is_ok() {
+//!                 // SAFETY: we unlinked the node, nobody else takes its value.
+//!                 let value = unsafe { ptr::read(&(*head).value) };
+//!                 // SAFETY: unlinked, and it came from a `Box`.
+//!                 unsafe { hazard::retire(head, hazard::drop_box) };
+//!                 return Some(ManuallyDrop::into_inner(value));
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! let stack = Stack { head: AtomicPtr::new(ptr::null_mut()), _marker: PhantomData };
+//! std::thread::scope(|s| {
+//!     s.spawn(|| (0..100).for_each(|i| stack.push(i)));
+//!     s.spawn(|| (0..100).for_each(|_| drop(stack.pop())));
+//! });
+//! while stack.pop().is_some() {}
+//! ```
+
+use std::cell::RefCell;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, fence};
+use std::sync::{Mutex, PoisonError};
+
+/// How many nodes a thread retires before it scans, at least.
+pub const SCAN_THRESHOLD: usize = 64;
+
+/// One hazard slot. Never freed, so a `&'static` to it is fine.
+struct Record {
+    hazard: AtomicPtr<(),>,
+    /// Owned by a hazard pointer, or cached by a thread.
+    active: AtomicBool,
+    /// Set before the record is published, never changed after.
+    next: *const Record,
+}
+
+// SAFETY: `next` is immutable once other threads can see the record.
+unsafe impl Sync for Record {}
+
+static RECORDS: AtomicPtr<Record,> = AtomicPtr::new(ptr::null_mut(),);
+static RECORD_COUNT: AtomicUsize = AtomicUsize::new(0,);
+
+/// Retired by threads that have exited, still protected back then.
+static ORPHANS: Mutex<Vec<Retired,>,> = Mutex::new(Vec::new(),);
+
+struct Retired {
+    ptr: *mut (),
+    deleter: unsafe fn(*mut (),),
+}
+
+// SAFETY: `retire` requires the deleter to be callable on any thread.
+unsafe impl Send for Retired {}
+
+/// What a thread keeps to itself.
+struct Local {
+    /// Slots of dropped hazard pointers, still marked active.
+    free: Vec<&'static Record,>,
+    retired: Vec<Retired,>,
+}
+
+impl Drop for Local {
+    fn drop(&mut self,) {
+        for record in self.free.drain(..,) {
+            record.active.store(false, Release,);
+        }
+        let left = reclaim(mem::take(&mut self.retired,),);
+        orphan(left,);
+    }
+}
+
+thread_local! {
+    static LOCAL: RefCell<Local,> =
+        const { RefCell::new(Local { free: Vec::new(), retired: Vec::new(), },) };
+}
+
+fn records() -> impl Iterator<Item = &'static Record,> {
+    let mut next = RECORDS.load(Acquire,).cast_const();
+    std::iter::from_fn(move || {
+        // SAFETY: records are never freed, and `next` only points at published ones.
+        let record = unsafe { next.as_ref() }?;
+        next = record.next;
+        Some(record,)
+    },)
+}
+
+fn acquire_record() -> &'static Record {
+    if let Ok(Some(record,),) = LOCAL.try_with(|l| l.borrow_mut().free.pop(),) {
+        return record;
+    }
+    for record in records() {
+        if !record.active.load(Relaxed,)
+            && record.active.compare_exchange(false, true, Acquire, Relaxed,).is_ok()
+        {
+            return record;
+        }
+    }
+    let record: &'static mut Record = Box::leak(Box::new(Record {
+        hazard: AtomicPtr::new(ptr::null_mut(),),
+        active: AtomicBool::new(true,),
+        next: ptr::null(),
+    },),);
+    let mut head = RECORDS.load(Relaxed,);
+    loop {
+        record.next = head;
+        match RECORDS.compare_exchange_weak(head, record, Release, Relaxed,) {
+            Ok(_,) => break,
+            Err(now,) => head = now,
+        }
+    }
+    RECORD_COUNT.fetch_add(1, Relaxed,);
+    record
+}
+
+/// Keeps the pointer it protects from being freed by a scan. Holds one slot in the global list,
+/// for as long as it lives.
+pub struct HazardPointer {
+    record: &'static Record,
+}
+
+impl HazardPointer {
+    pub fn new() -> Self {
+        HazardPointer { record: acquire_record(), }
+    }
+
+    /// Loads `src` and protects what it points to, until the next `protect`, `reset` or drop.
+    ///
+    /// The pointer can be dereferenced as long as everything ever stored in `src` is either
+    /// still there or was unlinked and then [`retire`]d, never freed directly.
+    pub fn protect<T,>(&mut self, src: &AtomicPtr<T,>,) -> *mut T {
+        let mut ptr = src.load(Relaxed,);
+        loop {
+            self.record.hazard.store(ptr.cast(), Release,);
+            // Pairs with the fence in `reclaim`: either the scan sees our hazard, or we see the
+            // node unlinked below and try again.
+            fence(SeqCst,);
+            let now = src.load(Acquire,);
+            if now == ptr {
+                return ptr;
+            }
+            ptr = now;
+        }
+    }
+
+    /// Stops protecting anything.
+    pub fn reset(&mut self,) {
+        // Release: our reads of the node happen before whoever frees it.
+        self.record.hazard.store(ptr::null_mut(), Release,);
+    }
+}
+
+impl Default for HazardPointer {
+    fn default() -> Self {
+        HazardPointer::new()
+    }
+}
+
+impl Drop for HazardPointer {
+    fn drop(&mut self,) {
+        self.reset();
+        let record = self.record;
+        if LOCAL.try_with(|l| l.borrow_mut().free.push(record,),).is_err() {
+            record.active.store(false, Release,);
+        }
+    }
+}
+
+/// Hands `ptr` over to be freed with `deleter` once no hazard pointer protects it. Might free it
+/// (and other retired nodes) right away.
+///
+/// # Safety
+///
+/// `ptr` must be unlinked already, so no thread can newly protect it, and must not be retired
+/// twice. `deleter(ptr)` must be sound to call on any thread, at any later point.
+pub unsafe fn retire<T,>(ptr: *mut T, deleter: unsafe fn(*mut T,),) {
+    // SAFETY: `*mut T` and `*mut ()` are ABI-compatible (T is sized), so the deleter can be
+    // called through either type.
+    let deleter = unsafe { mem::transmute::<unsafe fn(*mut T,), unsafe fn(*mut (),),>(deleter,) };
+    let mut retired = Some(Retired { ptr: ptr.cast(), deleter, },);
+    let full = LOCAL.try_with(|l| {
+        let mut l = l.borrow_mut();
+        l.retired.extend(retired.take(),);
+        l.retired.len() >= SCAN_THRESHOLD.max(2 * RECORD_COUNT.load(Relaxed,),)
+    },);
+    match full {
+        Ok(true,) => scan(),
+        Ok(false,) => {}
+        // The thread is exiting.
+        Err(_,) => orphan(retired.into_iter().collect(),),
+    }
+}
+
+/// A deleter for nodes that came from [`Box::into_raw`].
+///
+/// # Safety
+///
+/// `ptr` must come from `Box::into_raw` and not have been freed yet.
+pub unsafe fn drop_box<T,>(ptr: *mut T,) {
+    // SAFETY: guaranteed by the caller.
+    drop(unsafe { Box::from_raw(ptr,) },);
+}
+
+/// Frees everything the calling thread retired, plus what exited threads left behind, that no
+/// hazard pointer protects anymore.
+pub fn scan() {
+    let mut retired =
+        LOCAL.try_with(|l| mem::take(&mut l.borrow_mut().retired,),).unwrap_or_default();
+    retired.append(&mut ORPHANS.lock().unwrap_or_else(PoisonError::into_inner,),);
+    let left = reclaim(retired,);
+    if left.is_empty() {
+        return;
+    }
+    // Deleters may have retired more in the meantime, keep those too.
+    let mut left = Some(left,);
+    if LOCAL
+        .try_with(|l| l.borrow_mut().retired.extend(left.take().into_iter().flatten(),),)
+        .is_err()
+    {
+        orphan(left.unwrap_or_default(),);
+    }
+}
+
+/// Frees the nodes nobody protects, returns the others. Doesn't touch the thread local, so the
+/// deleters can retire more.
+fn reclaim(mut retired: Vec<Retired,>,) -> Vec<Retired,> {
+    if retired.is_empty() {
+        return retired;
+    }
+    // Pairs with the fence in `protect`.
+    fence(SeqCst,);
+    let mut hazards: Vec<*mut (),> =
+        records().map(|r| r.hazard.load(Acquire,),).filter(|p| !p.is_null(),).collect();
+    hazards.sort_unstable();
+    retired.retain(|r| {
+        if hazards.binary_search(&r.ptr,).is_ok() {
+            return true;
+        }
+        // SAFETY: unlinked and unprotected, so nobody can reach it anymore.
+        unsafe { (r.deleter)(r.ptr,) };
+        false
+    },);
+    retired
+}
+
+fn orphan(mut retired: Vec<Retired,>,) {
+    if !retired.is_empty() {
+        ORPHANS.lock().unwrap_or_else(PoisonError::into_inner,).append(&mut retired,);
+    }
+}
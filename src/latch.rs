@@ -0,0 +1,178 @@
+//! Waiting for a count to reach zero: a one-shot [`Latch`], and a [`WaitGroup`] whose count can
+//! go back up.
+//!
+//! Both are a single futex word holding the count. Counting down to zero wakes every waiter, and
+//! waiters only sleep while the count is what they last saw, so a count that hits zero between
+//! their check and their sleep isn't missed.
+
+use crate::futex;
+use crate::sync::atomic::AtomicU32;
+use core::fmt;
+use std::sync::atomic::Ordering::{self, Acquire, Relaxed, Release};
+use std::time::{Duration, Instant};
+
+/// Applies `f` to `count` until it sticks, or `f` returns `None`. Returns the old count, if it
+/// changed it.
+fn update(count: &AtomicU32, order: Ordering, f: impl Fn(u32,) -> Option<u32,>,) -> Option<u32,> {
+    let mut n = count.load(Relaxed,);
+    loop {
+        match count.compare_exchange_weak(n, f(n,)?, order, Relaxed,) {
+            Ok(_,) => return Some(n,),
+            Err(e,) => n = e,
+        }
+    }
+}
+
+/// Waits until `count` reads zero, or until the deadline. Returns whether it did.
+fn wait_for_zero(count: &AtomicU32, deadline: Option<Instant,>,) -> bool {
+    loop {
+        // Acquire: everything done before the last count down is visible after the wait.
+        let n = count.load(Acquire,);
+        if n == 0 {
+            return true;
+        }
+        if futex::wait(count, n, deadline,) == futex::WaitResult::TimedOut {
+            return count.load(Acquire,) == 0;
+        }
+    }
+}
+
+/// Opens once it has been counted down `count` times, and stays open.
+pub struct Latch {
+    count: AtomicU32,
+}
+
+impl Latch {
+    /// A latch that opens after `count` calls to `count_down`. `0` makes it open already.
+    pub const fn new(count: u32,) -> Self {
+        Self { count: AtomicU32::new(count,), }
+    }
+
+    /// Counts down by one, and opens the latch for everyone if that was the last one. Does
+    /// nothing on an open latch.
+    pub fn count_down(&self,) {
+        // Release: pairs with the Acquire in `wait`.
+        if update(&self.count, Release, |n| n.checked_sub(1,),) == Some(1,) {
+            futex::wake(&self.count, u32::MAX,);
+        }
+    }
+
+    /// How many more `count_down`s it takes to open.
+    pub fn count(&self,) -> u32 {
+        self.count.load(Relaxed,)
+    }
+
+    /// Blocks until the latch is open.
+    pub fn wait(&self,) {
+        wait_for_zero(&self.count, None,);
+    }
+
+    /// Like `wait`, but gives up after `timeout`. Returns whether the latch opened.
+    pub fn wait_timeout(&self, timeout: Duration,) -> bool {
+        wait_for_zero(&self.count, Instant::now().checked_add(timeout,),)
+    }
+}
+
+impl fmt::Debug for Latch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_struct("Latch",).field("count", &self.count(),).finish()
+    }
+}
+
+/// Counts outstanding work: `add` before handing work out, `done` when it's finished, and `wait`
+/// for all of it.
+///
+/// Unlike a [`Latch`], the count can go up again after reaching zero, so the group can be reused.
+/// A `wait` returns once it sees zero, even if more work is added right after.
+#[derive(Default,)]
+pub struct WaitGroup {
+    count: AtomicU32,
+}
+
+impl WaitGroup {
+    pub const fn new() -> Self {
+        Self { count: AtomicU32::new(0,), }
+    }
+
+    /// Adds `n` to the outstanding work.
+    ///
+    /// # Panics
+    ///
+    /// If the count would overflow a `u32`.
+    pub fn add(&self, n: u32,) {
+        if update(&self.count, Relaxed, |c| c.checked_add(n,),).is_none() {
+            panic!("WaitGroup count overflowed");
+        }
+    }
+
+    /// Marks one piece of work as finished, and wakes the waiters if it was the last.
+    ///
+    /// # Panics
+    ///
+    /// If there is no outstanding work, i.e. more `done`s than were `add`ed.
+    pub fn done(&self,) {
+        // Release: pairs with the Acquire in `wait`.
+        match update(&self.count, Release, |c| c.checked_sub(1,),) {
+            Some(1,) => {
+                futex::wake(&self.count, u32::MAX,);
+            }
+            Some(_,) => {}
+            None => panic!("WaitGroup::done called more often than add"),
+        }
+    }
+
+    /// Adds one piece of work, finished when the returned handle (and every clone of it) is
+    /// dropped. Each clone counts as another piece of work.
+    pub fn handle(&self,) -> WaitGroupHandle<'_,> {
+        self.add(1,);
+        WaitGroupHandle { group: self, }
+    }
+
+    /// How much work is outstanding.
+    pub fn count(&self,) -> u32 {
+        self.count.load(Relaxed,)
+    }
+
+    /// Blocks until there is no outstanding work.
+    pub fn wait(&self,) {
+        wait_for_zero(&self.count, None,);
+    }
+}
+
+impl fmt::Debug for WaitGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_struct("WaitGroup",).field("count", &self.count(),).finish()
+    }
+}
+
+/// One piece of work in a [`WaitGroup`], made by [`WaitGroup::handle`]. Calls `done` when
+/// dropped.
+#[must_use = "the work is done as soon as the handle is dropped"]
+pub struct WaitGroupHandle<'a,> {
+    group: &'a WaitGroup,
+}
+
+impl WaitGroupHandle<'_,> {
+    /// Marks this piece of work as finished, the same as dropping the handle.
+    pub fn done(self,) {
+        drop(self,);
+    }
+}
+
+impl Clone for WaitGroupHandle<'_,> {
+    fn clone(&self,) -> Self {
+        self.group.handle()
+    }
+}
+
+impl Drop for WaitGroupHandle<'_,> {
+    fn drop(&mut self,) {
+        self.group.done();
+    }
+}
+
+impl fmt::Debug for WaitGroupHandle<'_,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_struct("WaitGroupHandle",).field("group", self.group,).finish()
+    }
+}
@@ -3,10 +3,42 @@
 #![deny(clippy::expect_used)]
 #![deny(clippy::undocumented_unsafe_blocks)]
 #![deny(unsafe_op_in_unsafe_fn)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
+pub mod allocator;
 pub mod arc;
+#[cfg(feature = "std")]
+pub mod atomic_arc;
+#[cfg(feature = "std")]
+pub mod barrier;
+#[cfg(feature = "std")]
+pub mod biased_arc;
+#[cfg(feature = "std")]
 pub mod condvar;
+#[cfg(feature = "std")]
+pub mod epoch;
+#[cfg(feature = "std")]
+pub mod event;
+#[cfg(feature = "std")]
+mod futex;
+#[cfg(feature = "std")]
+pub mod hash_map;
+#[cfg(feature = "std")]
+pub mod hazard;
+#[cfg(feature = "std")]
+pub mod latch;
+pub mod lock_api;
+#[cfg(feature = "std")]
+pub mod lockfree;
+#[cfg(model_check)]
+pub mod model;
 pub mod mutex;
+pub mod once;
 pub mod one_shot_channel;
 pub mod rwlock;
 pub mod spinlock;
+pub mod stats;
+mod sync;
+pub mod wait;
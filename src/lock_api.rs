@@ -0,0 +1,585 @@
+//! Raw lock traits and the generic wrappers built on them, in the style of the `lock_api` crate.
+//!
+//! A raw lock is only the locking protocol: [`RawMutex`], [`RawRwLock`] and, for reader-writer
+//! locks that can turn a read lock into a write lock, [`RawRwLockUpgrade`]. [`Mutex`] and
+//! [`RwLock`] add the data and the guards on top, so a downstream crate that brings its own raw
+//! lock gets the whole guard API for free. The crate's own locks are these wrappers around
+//! [`RawSpinLock`](crate::spinlock::RawSpinLock),
+//! [`RawFutexMutex`](crate::mutex::RawFutexMutex) and
+//! [`RawFutexRwLock`](crate::rwlock::RawFutexRwLock).
+//!
+//! ```
+//! use atomics_locks::lock_api::{Mutex, RawMutex};
+//! use std::sync::atomic::AtomicBool;
+//! use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+//!
+//! struct RawTasLock(AtomicBool);
+//!
+//! // SAFETY: the swap hands the lock to exactly one thread until `unlock`.
+//! unsafe impl RawMutex for RawTasLock {
+//!     const INIT: Self = RawTasLock(AtomicBool::new(false));
+//!
+//!     fn lock(&self) {
+//!         while !self.try_lock() {
+//!             std::hint::spin_loop();
+//!         }
+//!     }
+//!
+//!     fn try_lock(&self) -> bool {
+//!         self.0.compare_exchange(false, true, Acquire, Relaxed).is_ok()
+//!     }
+//!
+//!     unsafe fn unlock(&self) {
+//!         self.0.store(false, Release);
+//!     }
+//! }
+//!
+//! let m: Mutex<RawTasLock, Vec<u32>> = Mutex::new(Vec::new());
+//! m.lock().push(1);
+//! assert_eq!(*m.lock(), [1]);
+//! ```
+
+use crate::sync::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+
+/// A mutual exclusion protocol, without the data it protects.
+///
+/// # Safety
+///
+/// Between a successful `lock` or `try_lock` and the matching `unlock`, no other `lock` or
+/// `try_lock` may succeed, and everything done before the `unlock` must happen before whatever
+/// is done after the next successful lock (Release/Acquire, at least). A lock may be unlocked on
+/// a different thread than the one that locked it, as guards can be sent.
+pub unsafe trait RawMutex {
+    /// An unlocked lock, so the wrappers can have `const` constructors.
+    const INIT: Self;
+
+    /// Blocks until the lock is acquired.
+    fn lock(&self,);
+
+    /// Acquires the lock if it is free, without blocking.
+    fn try_lock(&self,) -> bool;
+
+    /// # Safety
+    ///
+    /// The lock must be held, and the holder gives it up: it must not touch the data again.
+    unsafe fn unlock(&self,);
+}
+
+/// A reader-writer protocol, without the data it protects.
+///
+/// # Safety
+///
+/// While an exclusive lock is held, no other lock may succeed. While shared locks are held, only
+/// other shared locks may. Like [`RawMutex`], unlocks must be Release and locks Acquire, and
+/// either may happen on any thread.
+pub unsafe trait RawRwLock {
+    /// An unlocked lock.
+    const INIT: Self;
+
+    fn lock_shared(&self,);
+
+    fn try_lock_shared(&self,) -> bool;
+
+    /// # Safety
+    ///
+    /// A shared lock must be held, and is given up.
+    unsafe fn unlock_shared(&self,);
+
+    fn lock_exclusive(&self,);
+
+    fn try_lock_exclusive(&self,) -> bool;
+
+    /// # Safety
+    ///
+    /// The exclusive lock must be held, and is given up.
+    unsafe fn unlock_exclusive(&self,);
+}
+
+/// A reader-writer protocol with upgradable locks: shared locks that can become exclusive without
+/// letting a writer in between. Only one upgradable lock is held at a time, next to any number of
+/// plain shared ones.
+///
+/// # Safety
+///
+/// An upgradable lock excludes exclusive and other upgradable locks, but not shared ones. An
+/// upgrade turns it into an exclusive lock with nobody else getting exclusive access first.
+pub unsafe trait RawRwLockUpgrade: RawRwLock {
+    fn lock_upgradable(&self,);
+
+    fn try_lock_upgradable(&self,) -> bool;
+
+    /// # Safety
+    ///
+    /// An upgradable lock must be held, and is given up.
+    unsafe fn unlock_upgradable(&self,);
+
+    /// Waits until the other readers are gone and turns the upgradable lock into an exclusive one.
+    ///
+    /// # Safety
+    ///
+    /// An upgradable lock must be held.
+    unsafe fn upgrade(&self,);
+
+    /// Like `upgrade`, but fails instead of waiting for other readers.
+    ///
+    /// # Safety
+    ///
+    /// An upgradable lock must be held.
+    unsafe fn try_upgrade(&self,) -> bool;
+}
+
+/// A value protected by the raw mutex `R`.
+pub struct Mutex<R, T,> {
+    pub(crate) raw: R,
+    data: UnsafeCell<T,>,
+}
+
+// SAFETY: the raw lock hands the data to one thread at a time, so it only has to be `Send`.
+unsafe impl<R: Sync, T: Send,> Sync for Mutex<R, T,> {}
+
+impl<R: RawMutex, T,> Mutex<R, T,> {
+    pub const fn new(value: T,) -> Self {
+        Self::from_raw(R::INIT, value,)
+    }
+}
+
+impl<R, T,> Mutex<R, T,> {
+    /// A mutex using `raw`, which must be unlocked.
+    pub const fn from_raw(raw: R, value: T,) -> Self {
+        Mutex { raw, data: UnsafeCell::new(value,), }
+    }
+
+    pub fn into_inner(self,) -> T {
+        self.data.into_inner()
+    }
+
+    /// The data, without locking: the `&mut` already rules out anyone else.
+    pub fn get_mut(&mut self,) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// The raw lock. A lock taken through it is given back with [`Mutex::force_unlock`].
+    pub fn raw(&self,) -> &R {
+        &self.raw
+    }
+
+    /// A pointer to the data, for code that keeps track of the locking itself. Dereferencing it
+    /// is only sound while the lock is held, and the aliasing rules of whoever holds it apply.
+    pub fn data_ptr(&self,) -> *mut T {
+        // SAFETY: `self.data` is a live cell. Getting the pointer doesn't access the value.
+        unsafe { UnsafeCell::raw_get(&self.data,) }
+    }
+}
+
+impl<R: RawMutex, T,> Mutex<R, T,> {
+    pub fn lock(&self,) -> MutexGuard<'_, R, T,> {
+        self.raw.lock();
+        // SAFETY: just locked.
+        unsafe { self.make_guard_unchecked() }
+    }
+
+    pub fn try_lock(&self,) -> Option<MutexGuard<'_, R, T,>,> {
+        // SAFETY: just locked.
+        self.raw.try_lock().then(|| unsafe { self.make_guard_unchecked() },)
+    }
+
+    /// A guard for a lock the caller already holds, which it hands over to the guard.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held, and by nobody else who could use or unlock it.
+    pub unsafe fn make_guard_unchecked(&self,) -> MutexGuard<'_, R, T,> {
+        MutexGuard { mutex: self, _marker: PhantomData, }
+    }
+
+    /// Unlocks the mutex without a guard, for a lock that was taken through [`Mutex::raw`] or
+    /// whose guard was forgotten with [`MutexGuard::leak`] or `mem::forget`.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held, and whoever holds it must not touch the data after this: no guard
+    /// may be alive, and references from `leak` must be gone.
+    pub unsafe fn force_unlock(&self,) {
+        // SAFETY: the caller guarantees the lock is held and given up.
+        unsafe { self.raw.unlock() }
+    }
+}
+
+impl<R: RawMutex, T: Default,> Default for Mutex<R, T,> {
+    fn default() -> Self {
+        Self::new(T::default(),)
+    }
+}
+
+impl<R: RawMutex, T,> From<T,> for Mutex<R, T,> {
+    fn from(value: T,) -> Self {
+        Self::new(value,)
+    }
+}
+
+/// Shows the data if the lock is free, and `<locked>` rather than waiting if it isn't.
+impl<R: RawMutex, T: fmt::Debug,> fmt::Debug for Mutex<R, T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        let mut d = f.debug_struct("Mutex",);
+        match self.try_lock() {
+            Some(guard,) => d.field("data", &&*guard,),
+            None => d.field("data", &format_args!("<locked>"),),
+        };
+        d.finish_non_exhaustive()
+    }
+}
+
+/// Serialises the data, holding the lock meanwhile: a mutex this thread holds deadlocks.
+#[cfg(feature = "serde")]
+impl<R: RawMutex, T: serde::Serialize,> serde::Serialize for Mutex<R, T,> {
+    fn serialize<S: serde::Serializer,>(&self, serializer: S,) -> Result<S::Ok, S::Error,> {
+        self.lock().serialize(serializer,)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, R: RawMutex, T: serde::Deserialize<'de,>,> serde::Deserialize<'de,> for Mutex<R, T,> {
+    fn deserialize<D: serde::Deserializer<'de,>,>(deserializer: D,) -> Result<Self, D::Error,> {
+        T::deserialize(deserializer,).map(Self::new,)
+    }
+}
+
+#[cfg(feature = "stats")]
+impl<R: crate::stats::StatsSource, T,> Mutex<R, T,> {
+    pub fn stats(&self,) -> crate::stats::LockStats {
+        self.raw.stats()
+    }
+
+    pub fn reset_stats(&self,) {
+        self.raw.reset_stats();
+    }
+}
+
+#[cfg(feature = "stats")]
+impl<R: crate::stats::StatsSource, T: Send,> crate::stats::StatsSource for Mutex<R, T,> {
+    fn stats(&self,) -> crate::stats::LockStats {
+        self.raw.stats()
+    }
+
+    fn reset_stats(&self,) {
+        self.raw.reset_stats();
+    }
+}
+
+/// Holds a [`Mutex`] locked, and unlocks it when dropped.
+pub struct MutexGuard<'a, R: RawMutex, T,> {
+    mutex: &'a Mutex<R, T,>,
+    // The guard hands out `&mut T`: only `Send` or `Sync` along with `T`.
+    _marker: PhantomData<&'a mut T,>,
+}
+
+impl<'a, R: RawMutex, T,> MutexGuard<'a, R, T,> {
+    /// The mutex this guard holds, borrowed no longer than the guard.
+    pub fn mutex(this: &Self,) -> &Mutex<R, T,> {
+        this.mutex
+    }
+
+    /// The mutex for as long as it's borrowed, for `CondVar` to lock it again after a wait.
+    #[cfg(feature = "std")]
+    pub(crate) fn mutex_ref(&self,) -> &'a Mutex<R, T,> {
+        self.mutex
+    }
+
+    /// Unlocks the mutex while `f` runs, and locks it again afterwards, even if `f` panics.
+    pub fn unlocked<U,>(this: &mut Self, f: impl FnOnce() -> U,) -> U {
+        struct Relock<'a, R: RawMutex,>(&'a R,);
+
+        impl<R: RawMutex,> Drop for Relock<'_, R,> {
+            fn drop(&mut self,) {
+                self.0.lock();
+            }
+        }
+
+        // SAFETY: the guard holds the lock, and it's borrowed until `Relock` took it back.
+        unsafe { this.mutex.raw.unlock() };
+        let _relock = Relock(&this.mutex.raw,);
+        f()
+    }
+
+    /// Gives up the guard but keeps the mutex locked, for as long as the mutex is borrowed. The
+    /// lock stays held until [`Mutex::force_unlock`], if ever.
+    pub fn leak(this: Self,) -> &'a mut T {
+        let mutex = this.mutex;
+        core::mem::forget(this,);
+        // SAFETY: the lock is held, and with the guard forgotten this is the only way to the data.
+        unsafe { &mut *mutex.data.write_ptr() }
+    }
+}
+
+impl<R: RawMutex, T: fmt::Debug,> fmt::Debug for MutexGuard<'_, R, T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f,)
+    }
+}
+
+impl<R: RawMutex, T,> Deref for MutexGuard<'_, R, T,> {
+    type Target = T;
+    fn deref(&self,) -> &T {
+        // SAFETY: the guard holds the lock.
+        unsafe { &*self.mutex.data.read_ptr() }
+    }
+}
+
+impl<R: RawMutex, T,> DerefMut for MutexGuard<'_, R, T,> {
+    fn deref_mut(&mut self,) -> &mut T {
+        // SAFETY: the guard holds the lock.
+        unsafe { &mut *self.mutex.data.write_ptr() }
+    }
+}
+
+impl<R: RawMutex, T,> Drop for MutexGuard<'_, R, T,> {
+    fn drop(&mut self,) {
+        // SAFETY: the guard holds the lock, and is gone after this.
+        unsafe { self.mutex.raw.unlock() }
+    }
+}
+
+/// A value protected by the raw reader-writer lock `R`.
+pub struct RwLock<R, T,> {
+    pub(crate) raw: R,
+    data: UnsafeCell<T,>,
+}
+
+// SAFETY: readers share `&T` across threads, writers move the data between them.
+unsafe impl<R: Sync, T: Send + Sync,> Sync for RwLock<R, T,> {}
+
+impl<R: RawRwLock, T,> RwLock<R, T,> {
+    pub const fn new(value: T,) -> Self {
+        Self::from_raw(R::INIT, value,)
+    }
+}
+
+impl<R, T,> RwLock<R, T,> {
+    /// A lock using `raw`, which must be unlocked.
+    pub const fn from_raw(raw: R, value: T,) -> Self {
+        RwLock { raw, data: UnsafeCell::new(value,), }
+    }
+
+    pub fn into_inner(self,) -> T {
+        self.data.into_inner()
+    }
+
+    /// The data, without locking: the `&mut` already rules out anyone else.
+    pub fn get_mut(&mut self,) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// The raw lock. Locking it directly is fine (if pointless), unlocking takes `unsafe`.
+    pub fn raw(&self,) -> &R {
+        &self.raw
+    }
+}
+
+impl<R: RawRwLock, T,> RwLock<R, T,> {
+    pub fn read(&self,) -> RwLockReadGuard<'_, R, T,> {
+        self.raw.lock_shared();
+        RwLockReadGuard { rwlock: self, }
+    }
+
+    pub fn try_read(&self,) -> Option<RwLockReadGuard<'_, R, T,>,> {
+        self.raw.try_lock_shared().then(|| RwLockReadGuard { rwlock: self, },)
+    }
+
+    pub fn write(&self,) -> RwLockWriteGuard<'_, R, T,> {
+        self.raw.lock_exclusive();
+        RwLockWriteGuard { rwlock: self, _marker: PhantomData, }
+    }
+
+    pub fn try_write(&self,) -> Option<RwLockWriteGuard<'_, R, T,>,> {
+        self.raw
+            .try_lock_exclusive()
+            .then(|| RwLockWriteGuard { rwlock: self, _marker: PhantomData, },)
+    }
+}
+
+impl<R: RawRwLockUpgrade, T,> RwLock<R, T,> {
+    /// A read lock that can later be upgraded to a write lock. Only one is handed out at a time.
+    pub fn upgradable_read(&self,) -> RwLockUpgradableReadGuard<'_, R, T,> {
+        self.raw.lock_upgradable();
+        RwLockUpgradableReadGuard { rwlock: self, }
+    }
+
+    pub fn try_upgradable_read(&self,) -> Option<RwLockUpgradableReadGuard<'_, R, T,>,> {
+        self.raw.try_lock_upgradable().then(|| RwLockUpgradableReadGuard { rwlock: self, },)
+    }
+}
+
+impl<R: RawRwLock, T: Default,> Default for RwLock<R, T,> {
+    fn default() -> Self {
+        Self::new(T::default(),)
+    }
+}
+
+impl<R: RawRwLock, T,> From<T,> for RwLock<R, T,> {
+    fn from(value: T,) -> Self {
+        Self::new(value,)
+    }
+}
+
+/// Shows the data unless a writer holds (or waits for) the lock, then `<locked>`.
+impl<R: RawRwLock, T: fmt::Debug,> fmt::Debug for RwLock<R, T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        let mut d = f.debug_struct("RwLock",);
+        match self.try_read() {
+            Some(guard,) => d.field("data", &&*guard,),
+            None => d.field("data", &format_args!("<locked>"),),
+        };
+        d.finish_non_exhaustive()
+    }
+}
+
+/// Serialises the data under a read lock: a write lock this thread holds deadlocks.
+#[cfg(feature = "serde")]
+impl<R: RawRwLock, T: serde::Serialize,> serde::Serialize for RwLock<R, T,> {
+    fn serialize<S: serde::Serializer,>(&self, serializer: S,) -> Result<S::Ok, S::Error,> {
+        self.read().serialize(serializer,)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, R: RawRwLock, T: serde::Deserialize<'de,>,> serde::Deserialize<'de,> for RwLock<R, T,> {
+    fn deserialize<D: serde::Deserializer<'de,>,>(deserializer: D,) -> Result<Self, D::Error,> {
+        T::deserialize(deserializer,).map(Self::new,)
+    }
+}
+
+#[cfg(feature = "stats")]
+impl<R: crate::stats::StatsSource, T,> RwLock<R, T,> {
+    pub fn stats(&self,) -> crate::stats::LockStats {
+        self.raw.stats()
+    }
+
+    pub fn reset_stats(&self,) {
+        self.raw.reset_stats();
+    }
+}
+
+#[cfg(feature = "stats")]
+impl<R: crate::stats::StatsSource, T: Send + Sync,> crate::stats::StatsSource for RwLock<R, T,> {
+    fn stats(&self,) -> crate::stats::LockStats {
+        self.raw.stats()
+    }
+
+    fn reset_stats(&self,) {
+        self.raw.reset_stats();
+    }
+}
+
+/// Holds a shared lock on an [`RwLock`].
+pub struct RwLockReadGuard<'a, R: RawRwLock, T,> {
+    rwlock: &'a RwLock<R, T,>,
+}
+
+impl<R: RawRwLock, T: fmt::Debug,> fmt::Debug for RwLockReadGuard<'_, R, T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f,)
+    }
+}
+
+impl<R: RawRwLock, T,> Deref for RwLockReadGuard<'_, R, T,> {
+    type Target = T;
+    fn deref(&self,) -> &T {
+        // SAFETY: the guard holds a shared lock, nobody writes.
+        unsafe { &*self.rwlock.data.read_ptr() }
+    }
+}
+
+impl<R: RawRwLock, T,> Drop for RwLockReadGuard<'_, R, T,> {
+    fn drop(&mut self,) {
+        // SAFETY: the guard holds a shared lock, and is gone after this.
+        unsafe { self.rwlock.raw.unlock_shared() }
+    }
+}
+
+/// Holds an [`RwLock`] exclusively.
+pub struct RwLockWriteGuard<'a, R: RawRwLock, T,> {
+    rwlock: &'a RwLock<R, T,>,
+    // Hands out `&mut T`, like a `MutexGuard`.
+    _marker: PhantomData<&'a mut T,>,
+}
+
+impl<R: RawRwLock, T: fmt::Debug,> fmt::Debug for RwLockWriteGuard<'_, R, T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f,)
+    }
+}
+
+impl<R: RawRwLock, T,> Deref for RwLockWriteGuard<'_, R, T,> {
+    type Target = T;
+    fn deref(&self,) -> &T {
+        // SAFETY: the guard holds the lock exclusively.
+        unsafe { &*self.rwlock.data.read_ptr() }
+    }
+}
+
+impl<R: RawRwLock, T,> DerefMut for RwLockWriteGuard<'_, R, T,> {
+    fn deref_mut(&mut self,) -> &mut T {
+        // SAFETY: the guard holds the lock exclusively.
+        unsafe { &mut *self.rwlock.data.write_ptr() }
+    }
+}
+
+impl<R: RawRwLock, T,> Drop for RwLockWriteGuard<'_, R, T,> {
+    fn drop(&mut self,) {
+        // SAFETY: the guard holds the lock exclusively, and is gone after this.
+        unsafe { self.rwlock.raw.unlock_exclusive() }
+    }
+}
+
+/// Holds an upgradable lock on an [`RwLock`]: reads like a shared lock, and can become a
+/// [`RwLockWriteGuard`] without letting a writer in first.
+pub struct RwLockUpgradableReadGuard<'a, R: RawRwLockUpgrade, T,> {
+    rwlock: &'a RwLock<R, T,>,
+}
+
+impl<'a, R: RawRwLockUpgrade, T,> RwLockUpgradableReadGuard<'a, R, T,> {
+    /// Waits for the other readers to leave, then holds the lock exclusively.
+    pub fn upgrade(this: Self,) -> RwLockWriteGuard<'a, R, T,> {
+        let rwlock = this.rwlock;
+        core::mem::forget(this,);
+        // SAFETY: the upgradable lock was ours, and the forgotten guard won't release it.
+        unsafe { rwlock.raw.upgrade() };
+        RwLockWriteGuard { rwlock, _marker: PhantomData, }
+    }
+
+    /// Upgrades if there are no other readers, or hands the guard back.
+    pub fn try_upgrade(this: Self,) -> Result<RwLockWriteGuard<'a, R, T,>, Self,> {
+        // SAFETY: the guard holds the upgradable lock.
+        if unsafe { this.rwlock.raw.try_upgrade() } {
+            let rwlock = this.rwlock;
+            core::mem::forget(this,);
+            Ok(RwLockWriteGuard { rwlock, _marker: PhantomData, },)
+        } else {
+            Err(this,)
+        }
+    }
+}
+
+impl<R: RawRwLockUpgrade, T: fmt::Debug,> fmt::Debug for RwLockUpgradableReadGuard<'_, R, T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f,)
+    }
+}
+
+impl<R: RawRwLockUpgrade, T,> Deref for RwLockUpgradableReadGuard<'_, R, T,> {
+    type Target = T;
+    fn deref(&self,) -> &T {
+        // SAFETY: the guard holds a shared lock, nobody writes.
+        unsafe { &*self.rwlock.data.read_ptr() }
+    }
+}
+
+impl<R: RawRwLockUpgrade, T,> Drop for RwLockUpgradableReadGuard<'_, R, T,> {
+    fn drop(&mut self,) {
+        // SAFETY: the guard holds the upgradable lock, and is gone after this.
+        unsafe { self.rwlock.raw.unlock_upgradable() }
+    }
+}
@@ -0,0 +1,9 @@
+//! Lock-free collections. Nodes are reclaimed with [`crate::epoch`]: a node can't be freed, let
+//! alone reused, while a thread that might still compare against it is pinned, which also rules
+//! out ABA on the compare-and-swaps.
+
+mod queue;
+mod stack;
+
+pub use queue::Queue;
+pub use stack::Stack;
@@ -0,0 +1,118 @@
+use crate::epoch::{self, Atomic, Owned, Shared};
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+struct Node<T,> {
+    /// Uninitialised in the sentinel. Moved out by the `dequeue` that makes the node the new
+    /// sentinel.
+    value: MaybeUninit<T,>,
+    next: Atomic<Node<T,>,>,
+}
+
+/// A Michael-Scott queue: a linked list from `head`, which is always a sentinel, to `tail`, which
+/// may lag one node behind and is moved on by whoever notices.
+pub struct Queue<T,> {
+    head: Atomic<Node<T,>,>,
+    tail: Atomic<Node<T,>,>,
+}
+
+// SAFETY: values are moved in and out, never shared, so sending them is all it takes.
+unsafe impl<T: Send,> Send for Queue<T,> {}
+// SAFETY: as above.
+unsafe impl<T: Send,> Sync for Queue<T,> {}
+
+impl<T,> Queue<T,> {
+    pub fn new() -> Self {
+        let queue = Queue { head: Atomic::null(), tail: Atomic::null(), };
+        let guard = epoch::pin();
+        let sentinel = Owned::new(Node { value: MaybeUninit::uninit(), next: Atomic::null(), },)
+            .into_shared(&guard,);
+        queue.head.store(sentinel, Relaxed,);
+        queue.tail.store(sentinel, Relaxed,);
+        queue
+    }
+
+    pub fn enqueue(&self, value: T,) {
+        let guard = epoch::pin();
+        let node = Owned::new(Node { value: MaybeUninit::new(value,), next: Atomic::null(), },)
+            .into_shared(&guard,);
+        loop {
+            let tail = self.tail.load(Acquire, &guard,);
+            // SAFETY: never null, and nodes are only destroyed through `defer_destroy`.
+            let next = unsafe { tail.deref() }.next.load(Acquire, &guard,);
+            if !next.is_null() {
+                // The tail is lagging, help it along and try again.
+                let _ = self.tail.compare_exchange(tail, next, Release, Relaxed, &guard,);
+                continue;
+            }
+            // SAFETY: as above.
+            let linked = unsafe { tail.deref() }.next.compare_exchange(
+                Shared::null(),
+                node,
+                Release,
+                Relaxed,
+                &guard,
+            );
+            if linked.is_ok() {
+                // If this fails, somebody else already moved it on.
+                let _ = self.tail.compare_exchange(tail, node, Release, Relaxed, &guard,);
+                return;
+            }
+        }
+    }
+
+    pub fn dequeue(&self,) -> Option<T,> {
+        let guard = epoch::pin();
+        loop {
+            let head = self.head.load(Acquire, &guard,);
+            // SAFETY: never null, and nodes are only destroyed through `defer_destroy`.
+            let next = unsafe { head.deref() }.next.load(Acquire, &guard,);
+            // SAFETY: as above, null is fine.
+            let node = unsafe { next.as_ref() }?;
+            if self.head.compare_exchange(head, next, Acquire, Relaxed, &guard,).is_err() {
+                continue;
+            }
+            // The tail must not be left on the old sentinel once it's destroyed.
+            let tail = self.tail.load(Relaxed, &guard,);
+            if tail == head {
+                let _ = self.tail.compare_exchange(tail, next, Release, Relaxed, &guard,);
+            }
+            // SAFETY: `node` is the new sentinel, we're the one that made it so, and its value
+            // is ours. The old one is unlinked, and came from an `Owned`.
+            unsafe {
+                guard.defer_destroy(head,);
+                return Some(node.value.assume_init_read(),);
+            }
+        }
+    }
+
+    /// Whether the queue was empty at the time of the call.
+    pub fn is_empty(&self,) -> bool {
+        let guard = epoch::pin();
+        // SAFETY: as in `dequeue`.
+        unsafe { self.head.load(Acquire, &guard,).deref() }.next.load(Acquire, &guard,).is_null()
+    }
+}
+
+impl<T,> Default for Queue<T,> {
+    fn default() -> Self {
+        Queue::new()
+    }
+}
+
+impl<T,> Drop for Queue<T,> {
+    fn drop(&mut self,) {
+        while self.dequeue().is_some() {}
+        let guard = epoch::pin();
+        // SAFETY: nobody else can reach the queue anymore, and the sentinel came from an
+        // `Owned` (its value was moved out or never there, and isn't dropped).
+        drop(unsafe { self.head.load(Relaxed, &guard,).into_owned() },);
+    }
+}
+
+impl<T,> fmt::Debug for Queue<T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_struct("Queue",).finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,87 @@
+use crate::epoch::{self, Atomic, Owned};
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::ptr;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+struct Node<T,> {
+    /// Moved out by the `pop` that unlinks the node.
+    value: ManuallyDrop<T,>,
+    /// Set before the node is pushed, never changed after.
+    next: Atomic<Node<T,>,>,
+}
+
+/// A Treiber stack: a linked list with a single atomic head.
+pub struct Stack<T,> {
+    head: Atomic<Node<T,>,>,
+}
+
+// SAFETY: values are moved in and out, never shared, so sending them is all it takes.
+unsafe impl<T: Send,> Send for Stack<T,> {}
+// SAFETY: as above.
+unsafe impl<T: Send,> Sync for Stack<T,> {}
+
+impl<T,> Stack<T,> {
+    pub const fn new() -> Self {
+        Stack { head: Atomic::null(), }
+    }
+
+    pub fn push(&self, value: T,) {
+        let mut node =
+            Owned::new(Node { value: ManuallyDrop::new(value,), next: Atomic::null(), },);
+        let guard = epoch::pin();
+        let mut head = self.head.load(Relaxed, &guard,);
+        loop {
+            node.next.store(head, Relaxed,);
+            match self.head.compare_exchange(head, node, Release, Relaxed, &guard,) {
+                Ok(_,) => return,
+                Err(e,) => (head, node,) = (e.current, e.new,),
+            }
+        }
+    }
+
+    pub fn pop(&self,) -> Option<T,> {
+        let guard = epoch::pin();
+        let mut head = self.head.load(Acquire, &guard,);
+        loop {
+            // SAFETY: nodes are only destroyed through `defer_destroy`, and we are pinned.
+            let node = unsafe { head.as_ref() }?;
+            let next = node.next.load(Relaxed, &guard,);
+            match self.head.compare_exchange(head, next, Acquire, Acquire, &guard,) {
+                Ok(_,) => {
+                    // SAFETY: we unlinked the node, so the value is ours, and the node came from
+                    // an `Owned`.
+                    unsafe {
+                        let value = ptr::read(&node.value,);
+                        guard.defer_destroy(head,);
+                        return Some(ManuallyDrop::into_inner(value,),);
+                    }
+                }
+                Err(e,) => head = e.current,
+            }
+        }
+    }
+
+    /// Whether the stack was empty at the time of the call.
+    pub fn is_empty(&self,) -> bool {
+        self.head.load(Acquire, &epoch::pin(),).is_null()
+    }
+}
+
+impl<T,> Default for Stack<T,> {
+    fn default() -> Self {
+        Stack::new()
+    }
+}
+
+impl<T,> Drop for Stack<T,> {
+    fn drop(&mut self,) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T,> fmt::Debug for Stack<T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_struct("Stack",).finish_non_exhaustive()
+    }
+}
@@ -1,3 +1,83 @@
-fn main() {
-    println!("Hello, world!");
+mod bench;
+
+use bench::{Config, Format, Primitive};
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+usage: atomics_locks bench [options]
+
+options:
+    --primitive <mutex|spinlock|rwlock|arc|biased-arc|channel|condvar|stack|queue|hashmap>   workload to run (default: mutex)
+    --threads <N>                                                                            number of threads (default: 4)
+    --ops <M>                                                                                operations per thread (default: 100000)
+    --read-ratio <R>                                                                         fraction of read operations, 0.0..=1.0 (default: 0.9)
+    --format <table|json>                                                                    output format (default: table)
+";
+
+fn main() -> ExitCode {
+    let args: Vec<String,> = std::env::args().skip(1,).collect();
+    match parse(&args,) {
+        Ok(Some(config,),) => {
+            let results = bench::run(&config,);
+            match config.format {
+                Format::Table => print!("{}", bench::table(&config, &results)),
+                Format::Json => println!("{}", bench::json(&config, &results)),
+            }
+            ExitCode::SUCCESS
+        }
+        Ok(None,) => {
+            print!("{USAGE}");
+            ExitCode::SUCCESS
+        }
+        Err(e,) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            ExitCode::from(2,)
+        }
+    }
+}
+
+fn parse(args: &[String],) -> Result<Option<Config,>, String,> {
+    match args.first().map(String::as_str,) {
+        Some("bench",) => {}
+        None | Some("-h" | "--help" | "help",) => return Ok(None,),
+        Some(other,) => return Err(format!("unknown command `{other}`"),),
+    }
+
+    let mut config = Config::default();
+    let mut rest = args[1..].iter();
+    while let Some(flag,) = rest.next() {
+        if flag == "-h" || flag == "--help" {
+            return Ok(None,);
+        }
+        let value = rest.next().ok_or_else(|| format!("missing value for `{flag}`"),)?;
+        match flag.as_str() {
+            "--primitive" => config.primitive = value.parse::<Primitive>()?,
+            "--threads" => config.threads = parse_num(flag, value,)?,
+            "--ops" => config.ops = parse_num(flag, value,)?,
+            "--read-ratio" => {
+                config.read_ratio = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|r| (0.0..=1.0).contains(r,),)
+                    .ok_or_else(|| format!("`{flag}` expects a number between 0 and 1"),)?;
+            }
+            "--format" => {
+                config.format = match value.as_str() {
+                    "table" => Format::Table,
+                    "json" => Format::Json,
+                    _ => return Err(format!("unknown format `{value}`"),),
+                }
+            }
+            _ => return Err(format!("unknown option `{flag}`"),),
+        }
+    }
+    Ok(Some(config,),)
+}
+
+fn parse_num(flag: &str, value: &str,) -> Result<usize, String,> {
+    value
+        .parse::<usize>()
+        .ok()
+        .filter(|n| *n > 0,)
+        .ok_or_else(|| format!("`{flag}` expects a positive integer"),)
 }
@@ -0,0 +1,308 @@
+//! Atomic types that report every access to the model checker.
+//!
+//! Outside of a `model::check` run they behave exactly like their std counterparts (the value
+//! lives in a real atomic), so a `cfg(model_check)` build can still run ordinary tests.
+
+use super::rt::{self, State};
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::{self, AcqRel, Acquire, Relaxed, Release, SeqCst};
+
+fn acquires(order: Ordering,) -> bool {
+    matches!(order, Acquire | AcqRel | SeqCst)
+}
+
+fn releases(order: Ordering,) -> bool {
+    matches!(order, Release | AcqRel | SeqCst)
+}
+
+fn seq_cst(order: Ordering,) -> bool {
+    order == SeqCst
+}
+
+pub fn fence(order: Ordering,) {
+    if rt::step(|st, t| st.fence(t, acquires(order,), releases(order,), seq_cst(order,),),)
+        .is_none()
+    {
+        std::sync::atomic::fence(order,);
+    }
+}
+
+macro_rules! atomic_shim {
+    ($name:ident, $std:ty, $prim:ty, $to:expr, $from:expr) => {
+        pub struct $name {
+            id: AtomicU64,
+            pub(super) inner: $std,
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self::new(<$prim>::default(),)
+            }
+        }
+
+        impl std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_,>,) -> std::fmt::Result {
+                std::fmt::Debug::fmt(&self.inner, f,)
+            }
+        }
+
+        #[allow(dead_code)]
+        impl $name {
+            pub const fn new(value: $prim,) -> Self {
+                Self { id: AtomicU64::new(0,), inner: <$std>::new(value,), }
+            }
+
+            /// Registers the atomic with the running execution. A value written through
+            /// `get_mut` since the last access shows up as a new store by the current thread.
+            pub(super) fn object(&self, st: &mut State, t: usize,) -> usize {
+                let current = $to(self.inner.load(Relaxed,),);
+                let obj = st.object(&self.id, Some(current,),);
+                if st.latest(obj,) != current {
+                    st.store(t, obj, current, false, false,);
+                }
+                obj
+            }
+
+            pub fn load(&self, order: Ordering,) -> $prim {
+                rt::step(|st, t| {
+                    let obj = self.object(st, t,);
+                    $from(st.load(t, obj, acquires(order,), seq_cst(order,),),)
+                },)
+                .unwrap_or_else(|| self.inner.load(order,),)
+            }
+
+            pub fn store(&self, value: $prim, order: Ordering,) {
+                let modeled = rt::step(|st, t| {
+                    let obj = self.object(st, t,);
+                    st.store(t, obj, $to(value,), releases(order,), seq_cst(order,),);
+                    self.inner.store(value, Relaxed,);
+                },);
+                if modeled.is_none() {
+                    self.inner.store(value, order,);
+                }
+            }
+
+            fn rmw(
+                &self,
+                success: Ordering,
+                failure: Ordering,
+                f: impl FnOnce($prim,) -> Option<$prim,>,
+                fallback: impl FnOnce(&$std,) -> Result<$prim, $prim,>,
+            ) -> Result<$prim, $prim,> {
+                let modeled = rt::step(|st, t| {
+                    let obj = self.object(st, t,);
+                    let mut new = None;
+                    let acquire = acquires(success,) || acquires(failure,);
+                    let (old, ok,) =
+                        st.rmw(t, obj, acquire, releases(success,), seq_cst(success,), |old| {
+                            new = f($from(old,),);
+                            new.map($to,)
+                        },);
+                    if let Some(new,) = new {
+                        self.inner.store(new, Relaxed,);
+                    }
+                    if ok { Ok($from(old,),) } else { Err($from(old,),) }
+                },);
+                modeled.unwrap_or_else(|| fallback(&self.inner,),)
+            }
+
+            pub fn swap(&self, value: $prim, order: Ordering,) -> $prim {
+                let r = self.rmw(order, order, |_| Some(value,), |a| Ok(a.swap(value, order,),),);
+                r.unwrap_or_else(|v| v,)
+            }
+
+            pub fn compare_exchange(
+                &self,
+                current: $prim,
+                new: $prim,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$prim, $prim,> {
+                self.rmw(
+                    success,
+                    failure,
+                    |old| (old == current).then_some(new,),
+                    |a| a.compare_exchange(current, new, success, failure,),
+                )
+            }
+
+            pub fn compare_exchange_weak(
+                &self,
+                current: $prim,
+                new: $prim,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$prim, $prim,> {
+                self.compare_exchange(current, new, success, failure,)
+            }
+
+            pub fn get_mut(&mut self,) -> &mut $prim {
+                self.inner.get_mut()
+            }
+
+            pub fn into_inner(self,) -> $prim {
+                self.inner.into_inner()
+            }
+        }
+    };
+}
+
+macro_rules! atomic_int_ops {
+    ($name:ident, $prim:ty) => {
+        #[allow(dead_code)]
+        impl $name {
+            pub fn fetch_add(&self, v: $prim, order: Ordering,) -> $prim {
+                let r = self.rmw(
+                    order,
+                    order,
+                    |old| Some(old.wrapping_add(v,),),
+                    |a| Ok(a.fetch_add(v, order,),),
+                );
+                r.unwrap_or_else(|v| v,)
+            }
+
+            pub fn fetch_sub(&self, v: $prim, order: Ordering,) -> $prim {
+                let r = self.rmw(
+                    order,
+                    order,
+                    |old| Some(old.wrapping_sub(v,),),
+                    |a| Ok(a.fetch_sub(v, order,),),
+                );
+                r.unwrap_or_else(|v| v,)
+            }
+
+            pub fn fetch_max(&self, v: $prim, order: Ordering,) -> $prim {
+                let r = self.rmw(
+                    order,
+                    order,
+                    |old| Some(old.max(v,),),
+                    |a| Ok(a.fetch_max(v, order,),),
+                );
+                r.unwrap_or_else(|v| v,)
+            }
+
+            pub fn fetch_min(&self, v: $prim, order: Ordering,) -> $prim {
+                let r = self.rmw(
+                    order,
+                    order,
+                    |old| Some(old.min(v,),),
+                    |a| Ok(a.fetch_min(v, order,),),
+                );
+                r.unwrap_or_else(|v| v,)
+            }
+
+            pub fn fetch_or(&self, v: $prim, order: Ordering,) -> $prim {
+                let r =
+                    self.rmw(order, order, |old| Some(old | v,), |a| Ok(a.fetch_or(v, order,),),);
+                r.unwrap_or_else(|v| v,)
+            }
+
+            pub fn fetch_and(&self, v: $prim, order: Ordering,) -> $prim {
+                let r =
+                    self.rmw(order, order, |old| Some(old & v,), |a| Ok(a.fetch_and(v, order,),),);
+                r.unwrap_or_else(|v| v,)
+            }
+
+            pub fn fetch_xor(&self, v: $prim, order: Ordering,) -> $prim {
+                let r =
+                    self.rmw(order, order, |old| Some(old ^ v,), |a| Ok(a.fetch_xor(v, order,),),);
+                r.unwrap_or_else(|v| v,)
+            }
+        }
+    };
+}
+
+atomic_shim!(AtomicBool, std::sync::atomic::AtomicBool, bool, |v: bool| v as u64, |v: u64| v != 0);
+atomic_shim!(AtomicU8, std::sync::atomic::AtomicU8, u8, |v: u8| v as u64, |v: u64| v as u8);
+atomic_shim!(AtomicU32, std::sync::atomic::AtomicU32, u32, |v: u32| v as u64, |v: u64| v as u32);
+atomic_shim!(AtomicUsize, std::sync::atomic::AtomicUsize, usize, |v: usize| v as u64, |v: u64| v
+    as usize);
+atomic_int_ops!(AtomicU8, u8);
+atomic_int_ops!(AtomicU32, u32);
+atomic_int_ops!(AtomicUsize, usize);
+
+#[allow(dead_code)]
+impl AtomicBool {
+    pub fn fetch_or(&self, v: bool, order: Ordering,) -> bool {
+        let r = self.rmw(order, order, |old| Some(old | v,), |a| Ok(a.fetch_or(v, order,),),);
+        r.unwrap_or_else(|v| v,)
+    }
+
+    pub fn fetch_and(&self, v: bool, order: Ordering,) -> bool {
+        let r = self.rmw(order, order, |old| Some(old & v,), |a| Ok(a.fetch_and(v, order,),),);
+        r.unwrap_or_else(|v| v,)
+    }
+
+    pub fn fetch_xor(&self, v: bool, order: Ordering,) -> bool {
+        let r = self.rmw(order, order, |old| Some(old ^ v,), |a| Ok(a.fetch_xor(v, order,),),);
+        r.unwrap_or_else(|v| v,)
+    }
+}
+
+/// An `AtomicPtr` that goes through the model as its address, in an `AtomicUsize`. Pointers
+/// come back with exposed provenance, which is fine for model builds (they don't run under
+/// miri). Unlike std's, `new` isn't `const`.
+pub struct AtomicPtr<T,> {
+    addr: AtomicUsize,
+    _marker: std::marker::PhantomData<std::sync::atomic::AtomicPtr<T,>,>,
+}
+
+impl<T,> std::fmt::Debug for AtomicPtr<T,> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_,>,) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.addr, f,)
+    }
+}
+
+#[allow(dead_code)]
+impl<T,> AtomicPtr<T,> {
+    fn addr(ptr: *mut T,) -> usize {
+        ptr.expose_provenance()
+    }
+
+    fn ptr(addr: usize,) -> *mut T {
+        std::ptr::with_exposed_provenance_mut(addr,)
+    }
+
+    pub fn new(ptr: *mut T,) -> Self {
+        Self { addr: AtomicUsize::new(Self::addr(ptr,),), _marker: std::marker::PhantomData, }
+    }
+
+    pub fn load(&self, order: Ordering,) -> *mut T {
+        Self::ptr(self.addr.load(order,),)
+    }
+
+    pub fn store(&self, ptr: *mut T, order: Ordering,) {
+        self.addr.store(Self::addr(ptr,), order,);
+    }
+
+    pub fn swap(&self, ptr: *mut T, order: Ordering,) -> *mut T {
+        Self::ptr(self.addr.swap(Self::addr(ptr,), order,),)
+    }
+
+    pub fn compare_exchange(
+        &self,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<*mut T, *mut T,> {
+        self.addr
+            .compare_exchange(Self::addr(current,), Self::addr(new,), success, failure,)
+            .map(Self::ptr,)
+            .map_err(Self::ptr,)
+    }
+
+    pub fn compare_exchange_weak(
+        &self,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<*mut T, *mut T,> {
+        self.compare_exchange(current, new, success, failure,)
+    }
+
+    pub fn into_inner(self,) -> *mut T {
+        Self::ptr(self.addr.into_inner(),)
+    }
+}
@@ -0,0 +1,78 @@
+//! An `UnsafeCell` that reports every access to the model checker, which flags two accesses from
+//! different threads that are not ordered by happens-before (at least one of them a write) as a
+//! data race.
+
+use super::rt;
+use std::alloc::Layout;
+use std::sync::atomic::AtomicU64;
+
+/// `repr(C)` so the layout of an unsized cell built in place is known, see `layout_for`.
+#[repr(C)]
+pub struct UnsafeCell<T: ?Sized,> {
+    id: AtomicU64,
+    inner: std::cell::UnsafeCell<T,>,
+}
+
+impl<T,> UnsafeCell<T,> {
+    pub const fn new(value: T,) -> Self {
+        Self { id: AtomicU64::new(0,), inner: std::cell::UnsafeCell::new(value,), }
+    }
+
+    pub fn into_inner(self,) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized,> UnsafeCell<T,> {
+    fn access(&self, write: bool,) {
+        rt::access(|st, t| {
+            let obj = st.object(&self.id, None,);
+            st.cell_access(t, obj, write,);
+        },);
+    }
+
+    /// Pointer for reading the value; records a read by the current thread.
+    pub fn read_ptr(&self,) -> *const T {
+        self.access(false,);
+        self.inner.get()
+    }
+
+    /// Pointer for writing the value; records a write by the current thread.
+    pub fn write_ptr(&self,) -> *mut T {
+        self.access(true,);
+        self.inner.get()
+    }
+
+    pub fn get_mut(&mut self,) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// Pointer to the value of a cell that may not be initialised yet. Not an access.
+    ///
+    /// # Safety
+    ///
+    /// `this` must point into an allocation big enough for the cell.
+    pub const unsafe fn raw_get(this: *const Self,) -> *mut T {
+        // SAFETY: only computes a field address within the allocation, guaranteed by the caller.
+        std::cell::UnsafeCell::raw_get(unsafe { &raw const (*this).inner },)
+    }
+
+    /// The layout of a cell holding a value with layout `value`, and the offset of the value.
+    pub fn layout_for(value: Layout,) -> (Layout, usize,) {
+        match Layout::new::<AtomicU64,>().extend(value,) {
+            Ok((layout, offset,),) => (layout.pad_to_align(), offset,),
+            Err(_,) => panic!("cell too large"),
+        }
+    }
+
+    /// Initialises everything in the cell at `this` but the value itself, for cells built in
+    /// place.
+    ///
+    /// # Safety
+    ///
+    /// `this` must be valid for writes.
+    pub unsafe fn init_header(this: *mut Self,) {
+        // SAFETY: guaranteed by the caller.
+        unsafe { (&raw mut (*this).id).write(AtomicU64::new(0,),) }
+    }
+}
@@ -0,0 +1,164 @@
+//! The futex operations under the model checker. Which waiters a wake picks is a choice the
+//! model explores. A timed wait only times out once no other thread can run.
+//!
+//! Outside of a `model::check` run they fall through to the real futex.
+
+use super::atomic::AtomicU32;
+use super::rt::{self, Block, State, Status};
+use crate::futex::{WaitResult, WakeOp, os};
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::Instant;
+
+fn waiters(st: &State, obj: usize,) -> Vec<usize,> {
+    (0..st.threads.len())
+        .filter(|&w| {
+            matches!(
+                st.threads[w].status,
+                Status::Blocked(Block::Futex { object, .. }) if object == obj
+            )
+        },)
+        .collect()
+}
+
+/// Picks up to `n` of `waiters`.
+fn choose(st: &mut State, mut waiters: Vec<usize,>, n: u32,) -> Vec<usize,> {
+    let n = usize::try_from(n,).unwrap_or(usize::MAX,);
+    if waiters.len() <= n {
+        return waiters;
+    }
+    let mut chosen = Vec::with_capacity(n,);
+    while chosen.len() < n {
+        let i = st.branch(waiters.len(),);
+        chosen.push(waiters.remove(i,),);
+    }
+    chosen
+}
+
+fn wake_threads(st: &mut State, t: usize, obj: usize, woken: &[usize],) {
+    if woken.is_empty() {
+        return;
+    }
+    st.log(format!("thread {t}: wake #{obj} -> threads {woken:?}"),);
+    for &w in woken {
+        st.threads[w].status = Status::Runnable;
+    }
+}
+
+pub(crate) fn wait(atomic: &AtomicU32, expected: u32, deadline: Option<Instant,>,) -> WaitResult {
+    let blocked = rt::step(|st, t| {
+        let obj = atomic.object(st, t,);
+        let value = st.futex_load(t, obj,);
+        st.log(format!("thread {t}: futex wait on #{obj}, value {value}"),);
+        if value != u64::from(expected,) {
+            return false;
+        }
+        let timed = deadline.is_some();
+        st.threads[t].status = Status::Blocked(Block::Futex { object: obj, timed, },);
+        true
+    },);
+    match blocked {
+        Some(true,) => {
+            rt::block_current();
+            match rt::access(|st, t| std::mem::take(&mut st.threads[t].timed_out,),) {
+                Some(true,) => WaitResult::TimedOut,
+                _ => WaitResult::Woken,
+            }
+        }
+        Some(false,) => WaitResult::Mismatch,
+        None if rt::in_model() => WaitResult::Woken,
+        None => os::wait(&atomic.inner, expected, deadline,),
+    }
+}
+
+pub(crate) fn wake(atomic: &AtomicU32, n: u32,) -> usize {
+    let woken = rt::step(|st, t| {
+        let obj = atomic.object(st, t,);
+        let waiting = waiters(st, obj,);
+        let woken = choose(st, waiting, n,);
+        wake_threads(st, t, obj, &woken,);
+        woken.len()
+    },);
+    match woken {
+        Some(n,) => n,
+        None if rt::in_model() => 0,
+        None => os::wake(&atomic.inner, n,),
+    }
+}
+
+/// # Safety
+///
+/// See `futex::requeue`. `to` is dereferenced only when there are waiters to move.
+pub(crate) unsafe fn requeue(
+    from: &AtomicU32,
+    expected: u32,
+    n_wake: u32,
+    to: *const AtomicU32,
+    n_requeue: u32,
+) -> Option<usize,> {
+    let requeued = rt::step(|st, t| {
+        let from_obj = from.object(st, t,);
+        if st.futex_load(t, from_obj,) != u64::from(expected,) {
+            st.log(format!("thread {t}: requeue #{from_obj} failed, value changed"),);
+            return None;
+        }
+        let mut waiting = waiters(st, from_obj,);
+        let woken = choose(st, waiting.clone(), n_wake,);
+        waiting.retain(|w| !woken.contains(w,),);
+        wake_threads(st, t, from_obj, &woken,);
+        let n_requeue = usize::try_from(n_requeue,).unwrap_or(usize::MAX,);
+        let moved: Vec<usize,> = waiting.into_iter().take(n_requeue,).collect();
+        if moved.is_empty() {
+            return Some(woken.len(),);
+        }
+        // SAFETY: there are threads waiting on `from`, so our caller guarantees `to` is alive.
+        let to_obj = unsafe { &*to }.object(st, t,);
+        for &w in &moved {
+            if let Status::Blocked(Block::Futex { object, .. },) = &mut st.threads[w].status {
+                *object = to_obj;
+            }
+        }
+        st.log(format!("thread {t}: requeue #{from_obj} -> #{to_obj}, threads {moved:?}"),);
+        Some(woken.len() + moved.len(),)
+    },);
+    match requeued {
+        Some(r,) => r,
+        None if rt::in_model() => Some(0,),
+        None => {
+            let to = to.cast::<u8>().wrapping_add(std::mem::offset_of!(AtomicU32, inner),);
+            // SAFETY: forwarded from our caller.
+            unsafe { os::requeue(&from.inner, expected, n_wake, to.cast(), n_requeue,) }
+        }
+    }
+}
+
+pub(crate) fn wake_op(
+    first: &AtomicU32,
+    n_first: u32,
+    second: &AtomicU32,
+    n_second: u32,
+    op: WakeOp,
+) -> usize {
+    let woken = rt::step(|st, t| {
+        let first_obj = first.object(st, t,);
+        let second_obj = second.object(st, t,);
+        let (old, _,) = st
+            .rmw(t, second_obj, true, true, true, |old| Some(u64::from(op.apply(old as u32,),),),);
+        let old = old as u32;
+        second.inner.store(op.apply(old,), Relaxed,);
+        let waiting = waiters(st, first_obj,);
+        let mut woken = choose(st, waiting, n_first,);
+        wake_threads(st, t, first_obj, &woken,);
+        if op.matches(old,) {
+            let waiting = waiters(st, second_obj,);
+            let also = choose(st, waiting, n_second,);
+            wake_threads(st, t, second_obj, &also,);
+            woken.extend(also,);
+        }
+        woken.len()
+    },);
+    match woken {
+        Some(n,) => n,
+        None if rt::in_model() => 0,
+        None => os::wake_op(&first.inner, n_first, &second.inner, n_second, op,),
+    }
+}
@@ -0,0 +1,13 @@
+use super::rt;
+
+/// Tells the scheduler the current thread is waiting for another one: it won't be picked again
+/// until some other thread changed a value, and its next load reads the latest store.
+pub fn spin_loop() {
+    let modeled = rt::step(|st, t| {
+        st.threads[t].yielded = true;
+        st.threads[t].fresh = true;
+    },);
+    if modeled.is_none() {
+        std::hint::spin_loop();
+    }
+}
@@ -0,0 +1,94 @@
+//! An exhaustive interleaving model checker for the crate's own primitives, in the spirit of
+//! loom. Only compiled with `RUSTFLAGS="--cfg model_check"`.
+//!
+//! In that build every atomic, fence, futex call, `UnsafeCell` access and thread park the
+//! primitives make goes through the shims in this module (see `crate::sync`). [`check`] then runs
+//! a closure over and over, each time with a different schedule, until every interleaving (up to
+//! the preemption bound) and every value a load is allowed to read under the C++ memory model has
+//! been tried. An execution fails on a panic, a deadlock, a livelock or a data race on an
+//! `UnsafeCell`.
+//!
+//! ```ignore
+//! model::check(|| {
+//!     let lock = std::sync::Arc::new(Mutex::new(0,),);
+//!     let l = lock.clone();
+//!     let t = model::thread::spawn(move || *l.lock() += 1,);
+//!     *lock.lock() += 1;
+//!     t.join();
+//!     assert_eq!(*lock.lock(), 2);
+//! },);
+//! ```
+//!
+//! The memory model is approximated the same way loom does it: vector clocks for
+//! happens-before, a bounded store history per atomic for weak loads, and `SeqCst` treated as
+//! "reads the latest store and synchronises with every other `SeqCst` operation".
+
+pub mod atomic;
+pub mod cell;
+pub(crate) mod futex;
+pub mod hint;
+mod rt;
+pub mod thread;
+
+use std::sync::Arc;
+
+pub use rt::Report;
+
+/// Configures a model run.
+#[derive(Clone, Copy, Debug,)]
+pub struct Builder {
+    config: rt::Config,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self {
+            config: rt::Config {
+                preemption_bound: Some(2,),
+                max_steps: 10_000,
+                max_executions: None,
+            },
+        }
+    }
+
+    /// Maximum number of times a runnable thread may be switched away from in one execution.
+    /// `None` explores every interleaving, which only finishes for very small tests.
+    pub fn preemption_bound(mut self, bound: Option<usize,>,) -> Self {
+        self.config.preemption_bound = bound;
+        self
+    }
+
+    /// Steps after which an execution is considered a livelock.
+    pub fn max_steps(mut self, steps: usize,) -> Self {
+        self.config.max_steps = steps;
+        self
+    }
+
+    /// Stops the search after this many executions.
+    pub fn max_executions(mut self, executions: Option<usize,>,) -> Self {
+        self.config.max_executions = executions;
+        self
+    }
+
+    /// Runs `f` under every schedule and panics with the failing schedule if one fails.
+    pub fn check<F,>(&self, f: F,) -> Report
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        rt::explore(self.config, Arc::new(f,),)
+    }
+}
+
+/// Runs `f` under every schedule with the default configuration.
+pub fn check<F,>(f: F,) -> Report
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    Builder::new().check(f,)
+}
@@ -0,0 +1,712 @@
+//! The execution engine: one scheduler per execution, a depth-first search over the choices made
+//! across executions.
+//!
+//! Every model thread is a real OS thread, but only the one holding the baton (`active`) runs.
+//! Each visible operation (anything on an atomic, futex or parker) is a `step`: the scheduler
+//! first decides which thread gets to perform its next step, then the operation is applied to
+//! the shared state. Choices (which thread runs, which store a load reads from, which waiter a
+//! wake picks) are recorded on a `Path` and the next execution flips the deepest one that still
+//! has unexplored alternatives.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::panic::{AssertUnwindSafe, catch_unwind, resume_unwind};
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
+/// Stores older than this (in modification order) are never offered to a load.
+const MAX_HISTORY: usize = 4;
+
+/// Number of events kept for the report of a failing execution.
+const TRACE_LEN: usize = 40;
+
+static GENERATION: AtomicU64 = AtomicU64::new(1,);
+
+thread_local! {
+    static CURRENT: RefCell<Option<(Arc<Execution,>, usize,),>,> = const { RefCell::new(None,) };
+}
+
+/// Unwinding payload used to tear down the other threads once an execution has failed.
+struct Aborted;
+
+#[derive(Clone, Default, Debug,)]
+pub(crate) struct VClock(Vec<u32,>,);
+
+impl VClock {
+    pub(crate) fn get(&self, thread: usize,) -> u32 {
+        self.0.get(thread,).copied().unwrap_or(0,)
+    }
+
+    fn tick(&mut self, thread: usize,) -> u32 {
+        if self.0.len() <= thread {
+            self.0.resize(thread + 1, 0,);
+        }
+        self.0[thread] += 1;
+        self.0[thread]
+    }
+
+    pub(crate) fn join(&mut self, other: &VClock,) {
+        if self.0.len() < other.0.len() {
+            self.0.resize(other.0.len(), 0,);
+        }
+        for (a, b,) in self.0.iter_mut().zip(&other.0,) {
+            *a = (*a).max(*b,);
+        }
+    }
+
+    /// Whether the event `time` of `thread` happens-before whoever owns this clock.
+    pub(crate) fn saw(&self, thread: usize, time: u32,) -> bool {
+        self.get(thread,) >= time
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub(crate) enum Block {
+    Futex { object: usize, timed: bool, },
+    Park,
+    Join(usize,),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub(crate) enum Status {
+    Runnable,
+    Blocked(Block,),
+    Finished,
+}
+
+pub(crate) struct Thread {
+    pub(crate) status: Status,
+    pub(crate) clock: VClock,
+    /// Clock at the last release fence, carried by later relaxed stores.
+    pub(crate) release_fence: VClock,
+    /// Release clocks of stores read with a relaxed load, applied at the next acquire fence.
+    pub(crate) acquire_pending: VClock,
+    /// Set by `spin_loop`, the scheduler prefers other threads until something is written.
+    pub(crate) yielded: bool,
+    /// Set by `spin_loop`, the next load reads the latest store.
+    pub(crate) fresh: bool,
+    pub(crate) park_token: Option<VClock,>,
+    /// Set when a timed futex wait was ended by the timeout rather than a wake.
+    pub(crate) timed_out: bool,
+}
+
+pub(crate) struct Store {
+    pub(crate) value: u64,
+    thread: usize,
+    time: u32,
+    sync: VClock,
+}
+
+pub(crate) struct AtomicObject {
+    pub(crate) stores: Vec<Store,>,
+    /// Per thread, the newest store it has observed (coherence).
+    seen: Vec<usize,>,
+}
+
+#[derive(Default,)]
+pub(crate) struct CellObject {
+    write: Option<(usize, u32,),>,
+    reads: Vec<(usize, u32,),>,
+}
+
+pub(crate) enum Object {
+    Atomic(AtomicObject,),
+    Cell(CellObject,),
+}
+
+#[derive(Clone, Default, Debug,)]
+pub(crate) struct Path {
+    choices: Vec<(usize, usize,),>,
+    pos: usize,
+}
+
+impl Path {
+    fn branch(&mut self, options: usize,) -> usize {
+        if options <= 1 {
+            return 0;
+        }
+        let chosen = match self.choices.get(self.pos,) {
+            Some(&(chosen, recorded,),) => {
+                assert_eq!(
+                    recorded, options,
+                    "model execution is not deterministic: a replayed choice changed its options"
+                );
+                chosen
+            }
+            None => {
+                self.choices.push((0, options,),);
+                0
+            }
+        };
+        self.pos += 1;
+        chosen
+    }
+
+    /// Moves to the next unexplored path, false once the whole tree has been explored.
+    fn advance(&mut self,) -> bool {
+        self.pos = 0;
+        while let Some(last,) = self.choices.last_mut() {
+            if last.0 + 1 < last.1 {
+                last.0 += 1;
+                return true;
+            }
+            self.choices.pop();
+        }
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug,)]
+pub(crate) struct Config {
+    pub(crate) preemption_bound: Option<usize,>,
+    pub(crate) max_steps: usize,
+    pub(crate) max_executions: Option<usize,>,
+}
+
+pub(crate) struct State {
+    generation: u64,
+    pub(crate) threads: Vec<Thread,>,
+    pub(crate) objects: Vec<Object,>,
+    active: usize,
+    path: Path,
+    config: Config,
+    steps: usize,
+    preemptions: usize,
+    sc_clock: VClock,
+    failure: Option<String,>,
+    trace: VecDeque<String,>,
+    done: bool,
+    os_threads: Vec<std::thread::JoinHandle<(),>,>,
+}
+
+pub(crate) struct Execution {
+    state: Mutex<State,>,
+    cv: Condvar,
+}
+
+impl Execution {
+    fn lock(&self,) -> MutexGuard<'_, State,> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner,)
+    }
+
+    fn wait<'a,>(&self, guard: MutexGuard<'a, State,>,) -> MutexGuard<'a, State,> {
+        self.cv.wait(guard,).unwrap_or_else(PoisonError::into_inner,)
+    }
+
+    /// Picks the thread that performs the next step and hands it the baton. Returns once `me`
+    /// holds the baton again, or the execution is over.
+    fn schedule<'a,>(
+        &'a self,
+        mut st: MutexGuard<'a, State,>,
+        me: usize,
+    ) -> MutexGuard<'a, State,> {
+        if let Some(next,) = st.pick(me,) {
+            st.active = next;
+            if next == me {
+                return st;
+            }
+        }
+        self.cv.notify_all();
+        while st.active != me && st.failure.is_none() && !st.done {
+            st = self.wait(st,);
+        }
+        st
+    }
+}
+
+impl State {
+    /// Records an event for the report of a failing execution.
+    pub(crate) fn log(&mut self, event: String,) {
+        if self.trace.len() == TRACE_LEN {
+            self.trace.pop_front();
+        }
+        self.trace.push_back(event,);
+    }
+
+    /// Decides which thread performs the next step. `me` may be blocked or finished, in which
+    /// case it's not a candidate. Returns `None` once every thread finished, or on a deadlock.
+    fn pick(&mut self, me: usize,) -> Option<usize,> {
+        let runnable: Vec<usize,> = (0..self.threads.len())
+            .filter(|&t| self.threads[t].status == Status::Runnable,)
+            .collect();
+        if runnable.is_empty() {
+            if self.threads.iter().all(|t| t.status == Status::Finished,) {
+                self.done = true;
+                return None;
+            }
+            if !self.time_out_waiters() {
+                let statuses: Vec<String,> = self
+                    .threads
+                    .iter()
+                    .enumerate()
+                    .map(|(i, t,)| format!("thread {i}: {:?}", t.status),)
+                    .collect();
+                self.fail(format!("deadlock, every thread is blocked ({})", statuses.join(", ")),);
+                return None;
+            }
+            return self.pick(me,);
+        }
+
+        let mut candidates: Vec<usize,> =
+            runnable.iter().copied().filter(|&t| !self.threads[t].yielded,).collect();
+        if candidates.is_empty() {
+            candidates = runnable;
+        }
+        // The current thread goes first, so the first execution runs without preemptions.
+        if let Some(i,) = candidates.iter().position(|&t| t == me,) {
+            candidates.swap(0, i,);
+            candidates[1..].sort_unstable();
+        }
+
+        let me_continues = candidates.first() == Some(&me,) && !self.threads[me].yielded;
+        let bound_reached =
+            self.config.preemption_bound.is_some_and(|bound| self.preemptions >= bound,);
+        let next = if me_continues && bound_reached {
+            me
+        } else {
+            candidates[self.path.branch(candidates.len(),)]
+        };
+        if me_continues && next != me {
+            self.preemptions += 1;
+        }
+        Some(next,)
+    }
+
+    fn fail(&mut self, msg: String,) {
+        if self.failure.is_none() {
+            self.failure = Some(msg,);
+        }
+    }
+
+    /// Ends every timed futex wait; used when nothing else can run.
+    fn time_out_waiters(&mut self,) -> bool {
+        let mut any = false;
+        for t in &mut self.threads {
+            if let Status::Blocked(Block::Futex { timed: true, .. },) = t.status {
+                t.status = Status::Runnable;
+                t.timed_out = true;
+                any = true;
+            }
+        }
+        any
+    }
+
+    pub(crate) fn branch(&mut self, options: usize,) -> usize {
+        self.path.branch(options,)
+    }
+
+    /// Looks up (or registers) the object behind `id`. `init` gives the initial value of an
+    /// atomic, or `None` for a cell.
+    pub(crate) fn object(&mut self, id: &AtomicU64, init: Option<u64,>,) -> usize {
+        let packed = id.load(Relaxed,);
+        if packed >> 32 == self.generation && packed & 0xFFFF_FFFF != 0 {
+            return (packed & 0xFFFF_FFFF) as usize - 1;
+        }
+        let idx = self.objects.len();
+        self.objects.push(match init {
+            Some(value,) => Object::Atomic(AtomicObject {
+                stores: vec![Store { value, thread: 0, time: 0, sync: VClock::default(), }],
+                seen: Vec::new(),
+            },),
+            None => Object::Cell(CellObject::default(),),
+        },);
+        id.store(self.generation << 32 | (idx as u64 + 1), Relaxed,);
+        idx
+    }
+
+    fn atomic(&mut self, obj: usize,) -> &mut AtomicObject {
+        match &mut self.objects[obj] {
+            Object::Atomic(a,) => a,
+            Object::Cell(_,) => unreachable!("object {obj} is a cell, not an atomic"),
+        }
+    }
+
+    pub(crate) fn latest(&mut self, obj: usize,) -> u64 {
+        let a = self.atomic(obj,);
+        a.stores.last().map_or(0, |s| s.value,)
+    }
+
+    /// The relaxed load of the latest store the kernel does to compare a futex word.
+    pub(crate) fn futex_load(&mut self, t: usize, obj: usize,) -> u64 {
+        let hi = self.atomic(obj,).stores.len() - 1;
+        self.observe(t, obj, hi, false,)
+    }
+
+    /// Performs a load, possibly branching on which store it reads from.
+    pub(crate) fn load(&mut self, t: usize, obj: usize, acquire: bool, seq_cst: bool,) -> u64 {
+        if seq_cst {
+            self.sc_sync(t,);
+        }
+        let fresh = std::mem::take(&mut self.threads[t].fresh,);
+        let clock = self.threads[t].clock.clone();
+        let a = self.atomic(obj,);
+        let hi = a.stores.len() - 1;
+        let lo = if seq_cst || fresh {
+            hi
+        } else {
+            let happened =
+                a.stores.iter().rposition(|s| clock.saw(s.thread, s.time,),).unwrap_or(0,);
+            let seen = a.seen.get(t,).copied().unwrap_or(0,);
+            happened.max(seen,).max(hi.saturating_sub(MAX_HISTORY - 1,),)
+        };
+        let idx = hi - self.branch(hi - lo + 1,);
+        let value = self.observe(t, obj, idx, acquire,);
+        self.log(format!(
+            "thread {t}: load #{obj} -> {value}{}",
+            if idx < hi { " (stale)" } else { "" }
+        ),);
+        value
+    }
+
+    /// Reads the latest store for a read-modify-write, and appends `new` if `f` returns one.
+    pub(crate) fn rmw(
+        &mut self,
+        t: usize,
+        obj: usize,
+        acquire: bool,
+        release: bool,
+        seq_cst: bool,
+        f: impl FnOnce(u64,) -> Option<u64,>,
+    ) -> (u64, bool,) {
+        if seq_cst {
+            self.sc_sync(t,);
+        }
+        let hi = self.atomic(obj,).stores.len() - 1;
+        let old = self.observe(t, obj, hi, acquire,);
+        match f(old,) {
+            Some(new,) => {
+                self.log(format!("thread {t}: rmw #{obj} {old} -> {new}"),);
+                let mut sync = self.release_clock(t, release,);
+                // A read-modify-write continues the release sequence of the store it read.
+                sync.join(&self.atomic(obj,).stores[hi].sync,);
+                self.push_store(t, obj, new, sync,);
+                (old, true,)
+            }
+            None => {
+                self.log(format!("thread {t}: failed rmw #{obj}, read {old}"),);
+                (old, false,)
+            }
+        }
+    }
+
+    pub(crate) fn store(
+        &mut self, t: usize, obj: usize, value: u64, release: bool, seq_cst: bool,
+    ) {
+        if seq_cst {
+            self.sc_sync(t,);
+        }
+        let sync = self.release_clock(t, release,);
+        self.log(format!("thread {t}: store #{obj} <- {value}"),);
+        self.push_store(t, obj, value, sync,);
+    }
+
+    pub(crate) fn fence(&mut self, t: usize, acquire: bool, release: bool, seq_cst: bool,) {
+        if seq_cst {
+            self.sc_sync(t,);
+        }
+        let th = &mut self.threads[t];
+        if acquire {
+            let pending = std::mem::take(&mut th.acquire_pending,);
+            th.clock.join(&pending,);
+        }
+        if release {
+            th.release_fence = th.clock.clone();
+        }
+    }
+
+    fn sc_sync(&mut self, t: usize,) {
+        let th = &mut self.threads[t];
+        th.clock.join(&self.sc_clock,);
+        self.sc_clock.join(&th.clock,);
+    }
+
+    fn release_clock(&self, t: usize, release: bool,) -> VClock {
+        let th = &self.threads[t];
+        if release { th.clock.clone() } else { th.release_fence.clone() }
+    }
+
+    fn observe(&mut self, t: usize, obj: usize, idx: usize, acquire: bool,) -> u64 {
+        let a = self.atomic(obj,);
+        if a.seen.len() <= t {
+            a.seen.resize(t + 1, 0,);
+        }
+        a.seen[t] = a.seen[t].max(idx,);
+        let value = a.stores[idx].value;
+        let sync = a.stores[idx].sync.clone();
+        let th = &mut self.threads[t];
+        if acquire {
+            th.clock.join(&sync,);
+        } else {
+            th.acquire_pending.join(&sync,);
+        }
+        value
+    }
+
+    fn push_store(&mut self, t: usize, obj: usize, value: u64, sync: VClock,) {
+        let time = self.threads[t].clock.get(t,);
+        let a = self.atomic(obj,);
+        let changed = a.stores.last().is_none_or(|s| s.value != value,);
+        a.stores.push(Store { value, thread: t, time, sync, },);
+        let idx = a.stores.len() - 1;
+        if a.seen.len() <= t {
+            a.seen.resize(t + 1, 0,);
+        }
+        a.seen[t] = idx;
+        if changed {
+            for th in &mut self.threads {
+                th.yielded = false;
+            }
+        }
+    }
+
+    pub(crate) fn cell_access(&mut self, t: usize, obj: usize, write: bool,) {
+        let time = self.threads[t].clock.tick(t,);
+        let clock = self.threads[t].clock.clone();
+        let Object::Cell(cell,) = &mut self.objects[obj] else {
+            unreachable!("object {obj} is an atomic, not a cell")
+        };
+        let mut race = None;
+        if let Some((wt, wtime,),) = cell.write
+            && wt != t
+            && !clock.saw(wt, wtime,)
+        {
+            race = Some(format!("thread {t} accessed a cell written concurrently by thread {wt}"),);
+        }
+        if write {
+            if let Some(&(rt, _,),) =
+                cell.reads.iter().find(|&&(rt, rtime,)| rt != t && !clock.saw(rt, rtime,),)
+            {
+                race = Some(format!("thread {t} wrote a cell read concurrently by thread {rt}"),);
+            }
+            cell.write = Some((t, time,),);
+            cell.reads.clear();
+        } else {
+            match cell.reads.iter_mut().find(|(rt, _,)| *rt == t,) {
+                Some(r,) => r.1 = time,
+                None => cell.reads.push((t, time,),),
+            }
+        }
+        if let Some(race,) = race {
+            self.fail(format!("data race: {race}"),);
+        }
+    }
+}
+
+/// The execution and thread id of the calling thread, if it is running inside the model.
+fn current() -> Option<(Arc<Execution,>, usize,),> {
+    CURRENT.with(|c| c.borrow().clone(),)
+}
+
+/// Whether the calling thread is a model thread.
+pub(crate) fn in_model() -> bool {
+    CURRENT.with(|c| c.borrow().is_some(),)
+}
+
+fn abort_unwind() -> ! {
+    resume_unwind(Box::new(Aborted,),)
+}
+
+/// Performs one visible operation of the calling thread. Returns `None` when the caller is not
+/// a model thread, or when it is unwinding, in which case the caller should fall back to the real
+/// operation.
+pub(crate) fn step<R,>(op: impl FnOnce(&mut State, usize,) -> R,) -> Option<R,> {
+    let (exec, me,) = current()?;
+    if std::thread::panicking() {
+        return None;
+    }
+    let mut st = exec.schedule(exec.lock(), me,);
+    if st.failure.is_some() {
+        drop(st,);
+        abort_unwind();
+    }
+    st.steps += 1;
+    if st.steps > st.config.max_steps {
+        let max_steps = st.config.max_steps;
+        st.fail(format!(
+            "execution exceeded {} steps, probably a livelock (a thread spinning on a value nobody \
+             changes)",
+            max_steps
+        ),);
+        exec.cv.notify_all();
+        drop(st,);
+        abort_unwind();
+    }
+    st.threads[me].clock.tick(me,);
+    let r = op(&mut st, me,);
+    if st.failure.is_some() {
+        exec.cv.notify_all();
+        drop(st,);
+        abort_unwind();
+    }
+    Some(r,)
+}
+
+/// Accesses the state without scheduling; used for non-atomic accesses (cells).
+pub(crate) fn access<R,>(op: impl FnOnce(&mut State, usize,) -> R,) -> Option<R,> {
+    let (exec, me,) = current()?;
+    if std::thread::panicking() {
+        return None;
+    }
+    let mut st = exec.lock();
+    let r = op(&mut st, me,);
+    if st.failure.is_some() {
+        exec.cv.notify_all();
+        drop(st,);
+        abort_unwind();
+    }
+    Some(r,)
+}
+
+/// Gives the baton away after the calling thread was blocked by its last step, returning once it
+/// has been woken and scheduled again.
+pub(crate) fn block_current() {
+    let Some((exec, me,),) = current() else { return };
+    let st = exec.schedule(exec.lock(), me,);
+    if st.failure.is_some() {
+        drop(st,);
+        abort_unwind();
+    }
+}
+
+/// Spawns the OS thread backing model thread `id`, which waits for the baton before running `f`.
+pub(crate) fn spawn_os(exec: Arc<Execution,>, id: usize, f: Box<dyn FnOnce() + Send,>,) {
+    let e = exec.clone();
+    let handle = std::thread::spawn(move || {
+        CURRENT.with(|c| *c.borrow_mut() = Some((e.clone(), id,),),);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let mut st = e.lock();
+            while st.active != id && st.failure.is_none() {
+                st = e.wait(st,);
+            }
+            let failed = st.failure.is_some();
+            drop(st,);
+            if failed {
+                abort_unwind();
+            }
+            f();
+        },),);
+        let mut st = e.lock();
+        if let Err(payload,) = result
+            && !payload.is::<Aborted>()
+        {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string(),)
+                .or_else(|| payload.downcast_ref::<String>().cloned(),)
+                .unwrap_or_else(|| "<non-string panic payload>".to_string(),);
+            st.fail(format!("thread {id} panicked: {msg}"),);
+        }
+        st.threads[id].status = Status::Finished;
+        for t in &mut st.threads {
+            if t.status == Status::Blocked(Block::Join(id,),) {
+                t.status = Status::Runnable;
+            }
+        }
+        if st.failure.is_none()
+            && st.active == id
+            && let Some(next,) = st.pick(id,)
+        {
+            st.active = next;
+        }
+        e.cv.notify_all();
+        CURRENT.with(|c| *c.borrow_mut() = None,);
+    },);
+    exec.lock().os_threads.push(handle,);
+}
+
+/// Registers a new thread, spawned by `parent`, and returns its id.
+pub(crate) fn new_thread(st: &mut State, parent: Option<usize,>,) -> usize {
+    let clock = parent.map(|p| st.threads[p].clock.clone(),).unwrap_or_default();
+    st.threads.push(Thread {
+        status: Status::Runnable,
+        clock,
+        release_fence: VClock::default(),
+        acquire_pending: VClock::default(),
+        yielded: false,
+        fresh: false,
+        park_token: None,
+        timed_out: false,
+    },);
+    st.threads.len() - 1
+}
+
+pub(crate) fn current_execution() -> Option<Arc<Execution,>,> {
+    current().map(|(e, _,)| e,)
+}
+
+/// The outcome of a successful model run.
+#[derive(Clone, Copy, Debug,)]
+pub struct Report {
+    /// Number of executions explored.
+    pub executions: usize,
+    /// Whether the search stopped at `max_executions` before exploring every path.
+    pub truncated: bool,
+}
+
+pub(crate) fn explore(config: Config, f: Arc<dyn Fn() + Send + Sync,>,) -> Report {
+    assert!(!in_model(), "model::check can't be nested");
+    let mut path = Path::default();
+    let mut executions = 0;
+    loop {
+        executions += 1;
+        let exec = Arc::new(Execution {
+            state: Mutex::new(State {
+                generation: GENERATION.fetch_add(1, Relaxed,),
+                threads: Vec::new(),
+                objects: Vec::new(),
+                active: 0,
+                path,
+                config,
+                steps: 0,
+                preemptions: 0,
+                sc_clock: VClock::default(),
+                failure: None,
+                trace: VecDeque::new(),
+                done: false,
+                os_threads: Vec::new(),
+            },),
+            cv: Condvar::new(),
+        },);
+        new_thread(&mut exec.lock(), None,);
+        let main = f.clone();
+        spawn_os(exec.clone(), 0, Box::new(move || main(),),);
+
+        let mut st = exec.lock();
+        while !st.done && st.failure.is_none() {
+            st = exec.wait(st,);
+        }
+        exec.cv.notify_all();
+        let mut handles = std::mem::take(&mut st.os_threads,);
+        drop(st,);
+        // Threads spawned while the others were being torn down register late.
+        while !handles.is_empty() {
+            for h in handles {
+                let _ = h.join();
+            }
+            exec.cv.notify_all();
+            handles = std::mem::take(&mut exec.lock().os_threads,);
+        }
+
+        let mut st = exec.lock();
+        if let Some(failure,) = st.failure.take() {
+            let choices: Vec<String,> =
+                st.path.choices[..st.path.pos].iter().map(|(c, n,)| format!("{c}/{n}"),).collect();
+            let trace: Vec<&str,> = st.trace.iter().map(String::as_str,).collect();
+            panic!(
+                "model check failed in execution {executions}: {failure}\nchoices: [{}]\nlast \
+                 events:\n  {}",
+                choices.join(", "),
+                trace.join("\n  "),
+            );
+        }
+        path = std::mem::take(&mut st.path,);
+        drop(st,);
+        if !path.advance() {
+            return Report { executions, truncated: false, };
+        }
+        if config.max_executions.is_some_and(|max| executions >= max,) {
+            return Report { executions, truncated: true, };
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Threads inside a model execution.
+
+use super::rt::{self, Block, Status};
+use std::sync::{Arc, Mutex, PoisonError};
+
+pub struct JoinHandle<T,> {
+    id: usize,
+    result: Arc<Mutex<Option<T,>,>,>,
+}
+
+/// Spawns a model thread. Must be called from inside `model::check`.
+pub fn spawn<F, T,>(f: F,) -> JoinHandle<T,>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (Some(exec,), Some(id,),) =
+        (rt::current_execution(), rt::step(|st, t| rt::new_thread(st, Some(t,),),),)
+    else {
+        panic!("model::thread::spawn called outside of model::check");
+    };
+    let result = Arc::new(Mutex::new(None,),);
+    let slot = result.clone();
+    rt::spawn_os(
+        exec,
+        id,
+        Box::new(move || {
+            let value = f();
+            *slot.lock().unwrap_or_else(PoisonError::into_inner,) = Some(value,);
+        },),
+    );
+    JoinHandle { id, result, }
+}
+
+impl<T,> JoinHandle<T,> {
+    /// Waits for the thread to finish; everything it did happens-before the return.
+    pub fn join(self,) -> T {
+        let id = self.id;
+        let blocked = rt::step(|st, t| {
+            if st.threads[id].status == Status::Finished {
+                let clock = st.threads[id].clock.clone();
+                st.threads[t].clock.join(&clock,);
+                false
+            } else {
+                st.threads[t].status = Status::Blocked(Block::Join(id,),);
+                true
+            }
+        },);
+        if blocked == Some(true,) {
+            rt::block_current();
+            rt::access(|st, t| {
+                let clock = st.threads[id].clock.clone();
+                st.threads[t].clock.join(&clock,);
+            },);
+        }
+        match self.result.lock().unwrap_or_else(PoisonError::into_inner,).take() {
+            Some(value,) => value,
+            None => panic!("joined model thread {id} did not produce a value"),
+        }
+    }
+}
+
+/// Lets the scheduler run another thread.
+pub fn yield_now() {
+    super::hint::spin_loop();
+}
+
+#[derive(Clone, Debug,)]
+enum Handle {
+    Model(usize,),
+    Std(std::thread::Thread,),
+}
+
+/// A handle for unparking a thread, model or not.
+#[derive(Clone, Debug,)]
+pub struct Thread(Handle,);
+
+pub fn current() -> Thread {
+    match rt::access(|_, t| t,) {
+        Some(id,) => Thread(Handle::Model(id,),),
+        None => Thread(Handle::Std(std::thread::current(),),),
+    }
+}
+
+impl Thread {
+    pub fn unpark(&self,) {
+        match &self.0 {
+            Handle::Model(id,) => {
+                let id = *id;
+                rt::step(|st, t| {
+                    let clock = st.threads[t].clock.clone();
+                    let target = &mut st.threads[id];
+                    if target.status == Status::Blocked(Block::Park,) {
+                        target.status = Status::Runnable;
+                        target.clock.join(&clock,);
+                    } else {
+                        target.park_token.get_or_insert_default().join(&clock,);
+                    }
+                },);
+            }
+            Handle::Std(thread,) => thread.unpark(),
+        }
+    }
+}
+
+/// Blocks until the current thread is unparked; an earlier `unpark` makes it return right away.
+pub fn park() {
+    let blocked = rt::step(|st, t| match st.threads[t].park_token.take() {
+        Some(clock,) => {
+            st.threads[t].clock.join(&clock,);
+            false
+        }
+        None => {
+            st.threads[t].status = Status::Blocked(Block::Park,);
+            true
+        }
+    },);
+    match blocked {
+        Some(true,) => rt::block_current(),
+        Some(false,) => {}
+        None if rt::in_model() => {}
+        None => std::thread::park(),
+    }
+}
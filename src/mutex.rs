@@ -1,74 +1,119 @@
-use atomic_wait::{wait, wake_one};
-use std::ops::{Deref, DerefMut};
-use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::{cell::UnsafeCell, sync::atomic::AtomicU32};
+use crate::lock_api::{self, RawMutex};
+use crate::stats::{Contention, Counters, WaitTimer};
+use crate::sync::atomic::AtomicU32;
+use crate::sync::spin_loop;
+use crate::wait::{DefaultBackend, WaitBackend};
+use core::marker::PhantomData;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 
 const UNLOCKED: u32 = 0;
 const LOCKED: u32 = 1;
 const LOCKED_WAITING: u32 = 2;
 
-pub struct Mutex<T,> {
-    state: AtomicU32,
-    value: UnsafeCell<T,>,
+/// A mutex that sleeps on a futex when it has to wait. For another [`WaitBackend`], use
+/// `lock_api::Mutex<RawFutexMutex<W>, T>`.
+pub type Mutex<T,> = lock_api::Mutex<RawFutexMutex, T,>;
+pub type MutexGuard<'a, T,> = lock_api::MutexGuard<'a, RawFutexMutex, T,>;
+
+/// The locking protocol of [`Mutex`], sleeping through `W` when it has to wait, see
+/// [`crate::wait`].
+pub struct RawFutexMutex<W = DefaultBackend,> {
+    pub(crate) state: AtomicU32,
+    stats: Counters,
+    backend: PhantomData<fn() -> W,>,
 }
 
-// SAFETY: if Mutex is Send it has to be Sync
-unsafe impl<T,> Sync for Mutex<T,> where T: Send {}
+// SAFETY: only the CAS or swap from UNLOCKED takes the lock, Acquire pairs with the Release swap
+// in unlock.
+unsafe impl<W: WaitBackend,> RawMutex for RawFutexMutex<W,> {
+    #[allow(
+        clippy::declare_interior_mutable_const,
+        reason = "`INIT` is copied into every new lock, it is never shared"
+    )]
+    const INIT: Self = RawFutexMutex {
+        state: AtomicU32::new(UNLOCKED,),
+        stats: Counters::new(),
+        backend: PhantomData,
+    };
 
-impl<T,> Mutex<T,> {
-    pub const fn new(value: T,) -> Self {
-        Self { state: AtomicU32::new(UNLOCKED,), value: UnsafeCell::new(value,), }
-    }
     #[inline]
-    pub fn lock(&self,) -> MutexGuard<'_, T,> {
+    fn lock(&self,) {
         if self.state.compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed,).is_err() {
-            lock_contended(&self.state,);
+            let timer = WaitTimer::start();
+            let contention = lock_contended::<W,>(&self.state,);
+            self.stats.record_contended(timer, contention,);
         }
-        MutexGuard { mutex: self, }
+        self.stats.record_acquire();
+        self.stats.record_hold_start();
     }
-}
 
-#[cold]
-fn lock_contended(state: &AtomicU32,) {
-    let mut spin_count = 0;
-
-    while state.load(Relaxed,) == LOCKED && spin_count < 100 {
-        spin_count += 1;
-        std::hint::spin_loop();
+    #[inline]
+    fn try_lock(&self,) -> bool {
+        let locked = self.state.compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed,).is_ok();
+        if locked {
+            self.stats.record_acquire();
+            self.stats.record_hold_start();
+        }
+        locked
     }
 
-    if state.compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed,).is_ok() {
-        return;
+    #[inline]
+    unsafe fn unlock(&self,) {
+        self.stats.record_hold_end();
+        if self.state.swap(UNLOCKED, Release,) == LOCKED_WAITING {
+            W::wake(&self.state, 1,);
+        }
     }
+}
 
-    while state.swap(LOCKED_WAITING, Acquire,) != UNLOCKED {
-        wait(state, LOCKED_WAITING,);
+impl<W: WaitBackend,> RawFutexMutex<W,> {
+    /// Locks the mutex again at the end of a `CondVar` wait. `notify_all` moves waiters onto
+    /// `state` without marking the mutex as having waiters, so this always locks as if there
+    /// were some, and the unlock then wakes the next one.
+    #[cfg(feature = "std")]
+    pub(crate) fn lock_after_wait(&self,) {
+        let timer = WaitTimer::start();
+        let mut contention = Contention::default();
+        while self.state.swap(LOCKED_WAITING, Acquire,) != UNLOCKED {
+            contention.futex_waits += 1;
+            W::wait(&self.state, LOCKED_WAITING,);
+        }
+        if contention.futex_waits > 0 {
+            self.stats.record_contended(timer, contention,);
+        }
+        self.stats.record_acquire();
+        self.stats.record_hold_start();
     }
 }
 
-pub struct MutexGuard<'a, T,> {
-    pub mutex: &'a Mutex<T,>,
-}
+#[cfg(feature = "stats")]
+impl<W,> crate::stats::StatsSource for RawFutexMutex<W,> {
+    fn stats(&self,) -> crate::stats::LockStats {
+        self.stats.snapshot()
+    }
 
-impl<T,> Deref for MutexGuard<'_, T,> {
-    type Target = T;
-    fn deref(&self,) -> &T {
-        // SAFETY: if the mutex exists, the UnsafeCell will exists (see Drop impl)
-        unsafe { &*self.mutex.value.get() }
+    fn reset_stats(&self,) {
+        self.stats.reset();
     }
 }
 
-impl<T,> DerefMut for MutexGuard<'_, T,> {
-    fn deref_mut(&mut self,) -> &mut T {
-        // SAFETY: if the mutex exists, the UnsafeCell exists.
-        unsafe { &mut *self.mutex.value.get() }
+/// Takes a futex mutex `state` the slow way, shared with the upgradable lock of `RawFutexRwLock`.
+#[cold]
+pub(crate) fn lock_contended<W: WaitBackend,>(state: &AtomicU32,) -> Contention {
+    let mut contention = Contention::default();
+
+    while state.load(Relaxed,) == LOCKED && contention.spins < 100 {
+        contention.spins += 1;
+        spin_loop();
     }
-}
 
-impl<T,> Drop for MutexGuard<'_, T,> {
-    fn drop(&mut self,) {
-        if self.mutex.state.swap(UNLOCKED, Release,) == LOCKED_WAITING {
-            wake_one(&self.mutex.state,);
-        }
+    if state.compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed,).is_ok() {
+        return contention;
+    }
+
+    while state.swap(LOCKED_WAITING, Acquire,) != UNLOCKED {
+        contention.futex_waits += 1;
+        W::wait(state, LOCKED_WAITING,);
     }
+    contention
 }
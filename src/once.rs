@@ -0,0 +1,379 @@
+//! One-time initialisation: [`Once`], [`OnceLock`] and [`LazyLock`].
+//!
+//! They share one futex state machine, the same shape as the mutex's: incomplete, running,
+//! running with waiters (so finishing knows to wake them), and complete. A panic in the
+//! initialiser either poisons the `Once`, after which waiting on it panics, or sets it back to
+//! incomplete so the next caller retries, depending on its [`PanicPolicy`]. Waiting goes through
+//! the [`DefaultBackend`].
+
+use crate::sync::atomic::AtomicU32;
+use crate::sync::cell::UnsafeCell;
+use crate::wait::{DefaultBackend, WaitBackend};
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+const INCOMPLETE: u32 = 0;
+const RUNNING: u32 = 1;
+const RUNNING_WAITING: u32 = 2;
+const COMPLETE: u32 = 3;
+const POISONED: u32 = 4;
+
+/// What happens to a [`Once`] when its initialiser panics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq,)]
+pub enum PanicPolicy {
+    /// The `Once` is poisoned: `call_once` panics from then on, `call_once_force` runs again.
+    Poison,
+    /// The `Once` is incomplete again, and the next caller (or a waiting one) runs its
+    /// initialiser.
+    Retry,
+}
+
+/// Runs an initialiser exactly once, however many threads ask.
+pub struct Once {
+    state: AtomicU32,
+    policy: PanicPolicy,
+}
+
+/// Passed to the closure of [`Once::call_once_force`].
+#[derive(Debug,)]
+pub struct OnceState {
+    poisoned: bool,
+}
+
+impl OnceState {
+    /// Whether an earlier initialiser panicked.
+    pub fn is_poisoned(&self,) -> bool {
+        self.poisoned
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Once {
+    /// A `Once` that is poisoned if its initialiser panics, like `std::sync::Once`.
+    pub const fn new() -> Self {
+        Self::with_policy(PanicPolicy::Poison,)
+    }
+
+    pub const fn with_policy(policy: PanicPolicy,) -> Self {
+        Self { state: AtomicU32::new(INCOMPLETE,), policy, }
+    }
+
+    pub fn is_completed(&self,) -> bool {
+        self.state.load(Acquire,) == COMPLETE
+    }
+
+    /// Runs `f` if nobody has completed this `Once` yet, or waits for whoever is running theirs.
+    ///
+    /// # Panics
+    ///
+    /// If the `Once` is poisoned, or gets poisoned while this waits.
+    pub fn call_once(&self, f: impl FnOnce(),) {
+        if self.is_completed() {
+            return;
+        }
+        let mut f = Some(f,);
+        self.run(false, &mut |_| {
+            if let Some(f,) = f.take() {
+                f();
+            }
+            true
+        },);
+    }
+
+    /// Like `call_once`, but runs `f` on a poisoned `Once` too, and a successful run clears the
+    /// poison.
+    pub fn call_once_force(&self, f: impl FnOnce(&OnceState,),) {
+        if self.is_completed() {
+            return;
+        }
+        let mut f = Some(f,);
+        self.run(true, &mut |state| {
+            if let Some(f,) = f.take() {
+                f(state,);
+            }
+            true
+        },);
+    }
+
+    /// The state machine. `f` returns whether it completed the `Once`: `false` sets it back to
+    /// incomplete, for `OnceLock::get_or_try_init`.
+    #[cold]
+    fn run(&self, ignore_poison: bool, f: &mut dyn FnMut(&OnceState,) -> bool,) {
+        let mut state = self.state.load(Acquire,);
+        loop {
+            match state {
+                COMPLETE => return,
+                POISONED if !ignore_poison => panic!("Once instance has previously been poisoned"),
+                INCOMPLETE | POISONED => {
+                    if let Err(e,) = self.state.compare_exchange(state, RUNNING, Acquire, Acquire,)
+                    {
+                        state = e;
+                        continue;
+                    }
+                    // Until `f` returns, dropping this means it panicked.
+                    let mut finish = Finish {
+                        state: &self.state,
+                        set_to: match self.policy {
+                            PanicPolicy::Poison => POISONED,
+                            PanicPolicy::Retry => INCOMPLETE,
+                        },
+                    };
+                    let completed = f(&OnceState { poisoned: state == POISONED, },);
+                    finish.set_to = if completed { COMPLETE } else { state };
+                    return;
+                }
+                RUNNING => {
+                    // Tell the runner to wake us, then wait.
+                    if let Err(e,) =
+                        self.state.compare_exchange(RUNNING, RUNNING_WAITING, Relaxed, Acquire,)
+                    {
+                        state = e;
+                        continue;
+                    }
+                    DefaultBackend::wait(&self.state, RUNNING_WAITING,);
+                    state = self.state.load(Acquire,);
+                }
+                _ => {
+                    DefaultBackend::wait(&self.state, RUNNING_WAITING,);
+                    state = self.state.load(Acquire,);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Once {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        f.debug_struct("Once",).field("completed", &self.is_completed(),).finish_non_exhaustive()
+    }
+}
+
+/// Leaves the running state when dropped, with `set_to`, and wakes everyone if someone waits.
+struct Finish<'a,> {
+    state: &'a AtomicU32,
+    set_to: u32,
+}
+
+impl Drop for Finish<'_,> {
+    fn drop(&mut self,) {
+        if self.state.swap(self.set_to, Release,) == RUNNING_WAITING {
+            DefaultBackend::wake(self.state, u32::MAX,);
+        }
+    }
+}
+
+/// A value that is set at most once, by whoever gets there first.
+pub struct OnceLock<T,> {
+    once: Once,
+    value: UnsafeCell<MaybeUninit<T,>,>,
+}
+
+// SAFETY: the value is written once, before `once` completes (Release), and only shared after
+// (Acquire). Threads share `&T`, and any of them may have been the one that made it.
+unsafe impl<T: Send + Sync,> Sync for OnceLock<T,> {}
+
+impl<T,> Default for OnceLock<T,> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T,> OnceLock<T,> {
+    /// An empty cell that is left empty when an initialiser panics, like `std::sync::OnceLock`.
+    pub const fn new() -> Self {
+        Self::with_policy(PanicPolicy::Retry,)
+    }
+
+    /// An empty cell. With [`PanicPolicy::Poison`] a panicking initialiser makes every later
+    /// `get_or_init` panic instead.
+    pub const fn with_policy(policy: PanicPolicy,) -> Self {
+        Self { once: Once::with_policy(policy,), value: UnsafeCell::new(MaybeUninit::uninit(),), }
+    }
+
+    pub fn get(&self,) -> Option<&T,> {
+        // SAFETY: complete, so the value is written and never written again.
+        self.once.is_completed().then(|| unsafe { (*self.value.read_ptr()).assume_init_ref() },)
+    }
+
+    pub fn get_mut(&mut self,) -> Option<&mut T,> {
+        if !self.once.is_completed() {
+            return None;
+        }
+        // SAFETY: complete, so the value is written, and the `&mut` rules out everyone else.
+        Some(unsafe { self.value.get_mut().assume_init_mut() },)
+    }
+
+    /// Sets the value if there is none yet, or hands `value` back.
+    pub fn set(&self, value: T,) -> Result<(), T,> {
+        let mut value = Some(value,);
+        self.get_or_init(|| value.take().unwrap_or_else(|| unreachable!(),),);
+        value.map_or(Ok((),), Err,)
+    }
+
+    /// The value, made by `f` if there is none yet. If another thread is making it, waits for
+    /// that one instead.
+    ///
+    /// # Panics
+    ///
+    /// If `f` panics, and with [`PanicPolicy::Poison`] if an earlier initialiser did.
+    pub fn get_or_init(&self, f: impl FnOnce() -> T,) -> &T {
+        match self.get_or_try_init(|| Ok::<T, core::convert::Infallible,>(f(),),) {
+            Ok(value,) => value,
+        }
+    }
+
+    /// Like `get_or_init`, but `f` may fail. Then nothing is stored, and the next caller tries
+    /// again.
+    ///
+    /// # Errors
+    ///
+    /// Whatever `f` returned.
+    pub fn get_or_try_init<E,>(&self, f: impl FnOnce() -> Result<T, E,>,) -> Result<&T, E,> {
+        if let Some(value,) = self.get() {
+            return Ok(value,);
+        }
+        let mut f = Some(f,);
+        let mut error = None;
+        self.once.run(false, &mut |_| {
+            let Some(f,) = f.take() else { return false };
+            match f() {
+                Ok(value,) => {
+                    // SAFETY: we're the one running the `Once`, nobody reads the value until
+                    // it's complete.
+                    unsafe { (*self.value.write_ptr()).write(value,) };
+                    true
+                }
+                Err(e,) => {
+                    error = Some(e,);
+                    false
+                }
+            }
+        },);
+        match error {
+            Some(e,) => Err(e,),
+            // Ours or someone else's, it's there now.
+            None => Ok(self.get().unwrap_or_else(|| unreachable!("the Once completed"),),),
+        }
+    }
+
+    pub fn into_inner(mut self,) -> Option<T,> {
+        self.take()
+    }
+
+    /// Takes the value out, leaving the cell empty.
+    pub fn take(&mut self,) -> Option<T,> {
+        if !self.once.is_completed() {
+            return None;
+        }
+        self.once = Once::with_policy(self.once.policy,);
+        // SAFETY: it was complete, and with the `Once` reset nothing will read the value again.
+        Some(unsafe { self.value.get_mut().assume_init_read() },)
+    }
+}
+
+impl<T,> From<T,> for OnceLock<T,> {
+    fn from(value: T,) -> Self {
+        let cell = Self::new();
+        let _ = cell.set(value,);
+        cell
+    }
+}
+
+impl<T: fmt::Debug,> fmt::Debug for OnceLock<T,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        let mut d = f.debug_tuple("OnceLock",);
+        match self.get() {
+            Some(value,) => d.field(value,),
+            None => d.field(&format_args!("<uninit>"),),
+        };
+        d.finish()
+    }
+}
+
+impl<T,> Drop for OnceLock<T,> {
+    fn drop(&mut self,) {
+        drop(self.take(),);
+    }
+}
+
+/// A value made by `F` the first time it's used.
+///
+/// It poisons: if `F` panics, every later use panics as well, since `F` is gone.
+pub struct LazyLock<T, F = fn() -> T,> {
+    cell: OnceLock<T,>,
+    init: UnsafeCell<Option<F,>,>,
+}
+
+// SAFETY: `init` is only touched by the one thread that runs the `Once`, and it may be a
+// different thread than the one that made the lock. The value is shared like a `OnceLock`'s.
+unsafe impl<T: Send + Sync, F: Send,> Sync for LazyLock<T, F,> {}
+
+impl<T, F: FnOnce() -> T,> LazyLock<T, F,> {
+    pub const fn new(init: F,) -> Self {
+        Self {
+            cell: OnceLock::with_policy(PanicPolicy::Poison,),
+            init: UnsafeCell::new(Some(init,),),
+        }
+    }
+
+    /// The value, made now if it hasn't been yet.
+    ///
+    /// # Panics
+    ///
+    /// If the initialiser panics, now or on an earlier call.
+    pub fn force(this: &Self,) -> &T {
+        this.cell.get_or_init(|| {
+            // SAFETY: we're running the `Once`, nobody else touches `init`.
+            let init = unsafe { (*this.init.write_ptr()).take() };
+            match init {
+                Some(init,) => init(),
+                None => unreachable!("a LazyLock is only initialised once"),
+            }
+        },)
+    }
+
+    /// The value if it was made, the initialiser if not.
+    ///
+    /// # Panics
+    ///
+    /// If the initialiser panicked.
+    pub fn into_inner(this: Self,) -> Result<T, F,> {
+        let LazyLock { cell, init, } = this;
+        match (cell.into_inner(), init.into_inner(),) {
+            (Some(value,), _,) => Ok(value,),
+            (None, Some(init,),) => Err(init,),
+            (None, None,) => panic!("LazyLock instance has previously been poisoned"),
+        }
+    }
+}
+
+impl<T, F: FnOnce() -> T,> Deref for LazyLock<T, F,> {
+    type Target = T;
+    fn deref(&self,) -> &T {
+        LazyLock::force(self,)
+    }
+}
+
+impl<T: Default,> Default for LazyLock<T,> {
+    fn default() -> Self {
+        Self::new(T::default,)
+    }
+}
+
+impl<T: fmt::Debug, F,> fmt::Debug for LazyLock<T, F,> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+        let mut d = f.debug_tuple("LazyLock",);
+        match self.cell.get() {
+            Some(value,) => d.field(value,),
+            None => d.field(&format_args!("<uninit>"),),
+        };
+        d.finish()
+    }
+}
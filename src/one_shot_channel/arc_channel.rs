@@ -0,0 +1,74 @@
+//! The one-shot channel with the shared state in an [`Arc`], so the `Sender` and `Receiver` own
+//! it and can be sent anywhere. The allocation comes from the allocator passed to
+//! [`channel_in`], or the global one.
+
+use crate::allocator::{Allocator, Global};
+use crate::arc::Arc;
+use crate::sync::atomic::AtomicBool;
+use crate::sync::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+struct Channel<T,> {
+    message: UnsafeCell<MaybeUninit<T,>,>,
+    ready: AtomicBool,
+}
+
+// SAFETY: if T is Send, we have to ensure the Channel<T> is Sync
+unsafe impl<T: Send,> Sync for Channel<T,> {}
+
+impl<T,> Drop for Channel<T,> {
+    fn drop(&mut self,) {
+        if *self.ready.get_mut() {
+            // SAFETY: if self.ready, a message was sent and never received.
+            unsafe { self.message.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+pub struct Sender<T, A: Allocator = Global,> {
+    channel: Arc<Channel<T,>, A,>,
+}
+
+pub struct Receiver<T, A: Allocator = Global,> {
+    channel: Arc<Channel<T,>, A,>,
+}
+
+pub fn channel<T,>() -> (Sender<T,>, Receiver<T,>,) {
+    channel_in(Global,)
+}
+
+/// Like [`channel`], with the shared state allocated from `alloc`.
+pub fn channel_in<T, A: Allocator,>(alloc: A,) -> (Sender<T, A,>, Receiver<T, A,>,) {
+    let channel = Arc::new_in(
+        Channel {
+            message: UnsafeCell::new(MaybeUninit::uninit(),),
+            ready: AtomicBool::new(false,),
+        },
+        alloc,
+    );
+    (Sender { channel: channel.clone(), }, Receiver { channel, },)
+}
+
+impl<T, A: Allocator,> Sender<T, A,> {
+    pub fn send(self, message: T,) {
+        // SAFETY: send takes the only Sender by value, so nothing else writes the message, and
+        // the receiver only reads it after the Release store below.
+        unsafe { (*self.channel.message.write_ptr()).write(message,) };
+        self.channel.ready.store(true, Release,);
+    }
+}
+
+impl<T, A: Allocator,> Receiver<T, A,> {
+    pub fn is_ready(&self,) -> bool {
+        self.channel.ready.load(Relaxed,)
+    }
+
+    pub fn receive(self,) -> T {
+        if !self.channel.ready.swap(false, Acquire,) {
+            panic!("no message available!");
+        }
+        // SAFETY: We've just checked (and reset) the ready flag.
+        unsafe { (*self.channel.message.read_ptr()).assume_init_read() }
+    }
+}
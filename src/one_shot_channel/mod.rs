@@ -1,2 +1,4 @@
+pub mod arc_channel;
+#[cfg(feature = "std")]
 pub mod typed_channel;
 pub mod unsafe_channel;
@@ -1,6 +1,9 @@
+use crate::sync::atomic::AtomicBool;
+use crate::sync::cell::UnsafeCell;
+use crate::sync::thread::{self, Thread};
 use negative_impl::negative_impl;
+use std::mem::MaybeUninit;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::{cell::UnsafeCell, mem::MaybeUninit, sync::atomic::AtomicBool, thread, thread::Thread};
 
 pub struct Channel<T,> {
     message: UnsafeCell<MaybeUninit<T,>,>,
@@ -48,7 +51,7 @@ impl<T,> Sender<'_, T,> {
         // SAFETY: we are accessing a UnsafeCell and writing to it. the reciever is parked so there
         // is nothing accessing it. this is a Typed channel guarenteeing the send() can only be
         // called once + the happens-before pattern is being used with 'Release' on the AtomicBool
-        unsafe { (*self.channel.message.get()).write(message,) };
+        unsafe { (*self.channel.message.write_ptr()).write(message,) };
         self.channel.ready.store(true, Release,);
         self.receiving_thread.unpark();
     }
@@ -71,6 +74,6 @@ impl<T,> Receiver<'_, T,> {
             thread::park();
         }
         // SAFETY: We've just checked (and reset) the ready flag.
-        unsafe { (*self.channel.message.get()).assume_init_read() }
+        unsafe { (*self.channel.message.read_ptr()).assume_init_read() }
     }
 }
@@ -1,5 +1,7 @@
-use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::{cell::UnsafeCell, mem::MaybeUninit, sync::atomic::AtomicU8};
+use crate::sync::atomic::AtomicU8;
+use crate::sync::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 
 const EMPTY: u8 = 0;
 const WRITING: u8 = 1;
@@ -30,7 +32,7 @@ impl<T,> Channel<T,> {
         }
         // SAFETY: we're accessing an UnsafeCell and writing to it. It is guarenteed to be safe to
         // write to givent the self.state = EMPTY
-        unsafe { (*self.message.get()).write(message,) };
+        unsafe { (*self.message.write_ptr()).write(message,) };
         self.state.store(READY, Release,);
     }
 
@@ -44,7 +46,7 @@ impl<T,> Channel<T,> {
         }
         // SAFETY: we're accessing a UnsafeCell and reading it (there is something there if
         // status == READY)
-        unsafe { (*self.message.get()).assume_init_read() }
+        unsafe { (*self.message.read_ptr()).assume_init_read() }
     }
 }
 
@@ -1,58 +1,141 @@
-use std::ops::{Deref, DerefMut};
-use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::{cell::UnsafeCell, sync::atomic::AtomicU32};
+use crate::lock_api::{self, RawRwLock, RawRwLockUpgrade};
+use crate::mutex::lock_contended;
+use crate::stats::{Contention, Counters, WaitTimer};
+use crate::sync::atomic::AtomicU32;
+use crate::wait::{DefaultBackend, WaitBackend};
+use core::marker::PhantomData;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 
-use atomic_wait::{wait, wake_all, wake_one};
+/// A reader-writer lock that sleeps on a futex when it has to wait. For another
+/// [`WaitBackend`], use `lock_api::RwLock<RawFutexRwLock<W>, T>`.
+pub type RwLock<T,> = lock_api::RwLock<RawFutexRwLock, T,>;
+pub type ReadGuard<'a, T,> = lock_api::RwLockReadGuard<'a, RawFutexRwLock, T,>;
+pub type WriteGuard<'a, T,> = lock_api::RwLockWriteGuard<'a, RawFutexRwLock, T,>;
+pub type UpgradableReadGuard<'a, T,> = lock_api::RwLockUpgradableReadGuard<'a, RawFutexRwLock, T,>;
 
-pub struct RwLock<T,> {
+// NOTE: readers may only push the state up to here. Even a waiting writer's +1 on top leaves it
+// below u32::MAX, the write-locked state, and `+ 2` can't overflow.
+const READER_LIMIT: u32 = u32::MAX - 3;
+const _: () = assert!(READER_LIMIT.is_multiple_of(2,) && READER_LIMIT + 1 < u32::MAX);
+
+/// The locking protocol of [`RwLock`], sleeping through `W` when it has to wait, see
+/// [`crate::wait`].
+pub struct RawFutexRwLock<W = DefaultBackend,> {
     // NOTE: to prevent writer starvation:
     //          - The number of read locks increments by 2
     //          - The number of write locks (just one write lock at a time) increments by 1
     //          - therefore, if the state is odd, there is a writer waiting
     state: AtomicU32,
-    value: UnsafeCell<T,>,
     writer_wake_count: AtomicU32,
+    // NOTE: a futex mutex (see `mutex::lock_contended`) held by the one upgradable reader, which
+    // also holds a read lock in `state`. Upgrading waits until that read lock is the only one.
+    upgrader: AtomicU32,
+    stats: Counters,
+    backend: PhantomData<fn() -> W,>,
 }
 
-impl<T: Default,> Default for RwLock<T,> {
-    fn default() -> Self {
-        Self::new(T::default(),)
-    }
-}
-
-// SAFETY: we require Send if T implements Sync
-unsafe impl<T,> Sync for RwLock<T,> where T: Send + Sync {}
-
-impl<T,> RwLock<T,> {
-    pub const fn new(value: T,) -> Self {
-        Self {
-            state: AtomicU32::new(0,),
-            value: UnsafeCell::new(value,),
-            writer_wake_count: AtomicU32::new(0,),
+impl<W: WaitBackend,> RawFutexRwLock<W,> {
+    fn acquired(&self, timer: Option<WaitTimer,>, contention: Contention,) {
+        if let Some(timer,) = timer {
+            self.stats.record_contended(timer, contention,);
         }
+        self.stats.record_acquire();
     }
-    pub fn read(&self,) -> ReadGuard<'_, T,> {
+
+    fn read(&self, mut timer: Option<WaitTimer,>, mut contention: Contention,) {
         let mut s = self.state.load(Relaxed,);
         loop {
             if s.is_multiple_of(2,) {
-                assert!(s != u32::MAX - 1, "too many readers.");
-                match self.state.compare_exchange_weak(s, s + 1, Acquire, Relaxed,) {
-                    Ok(_,) => return ReadGuard { rwlock: self, },
+                assert!(s < READER_LIMIT, "too many readers.");
+                match self.state.compare_exchange_weak(s, s + 2, Acquire, Relaxed,) {
+                    Ok(_,) => {
+                        self.acquired(timer, contention,);
+                        if s == 0 {
+                            self.stats.record_read_start();
+                        }
+                        return;
+                    }
                     Err(e,) => s = e,
                 }
             }
             if s % 2 == 1 {
-                wait(&self.state, s,);
+                timer.get_or_insert_with(WaitTimer::start,);
+                contention.futex_waits += 1;
+                W::wait(&self.state, s,);
                 s = self.state.load(Relaxed,);
             }
         }
     }
-    pub fn write(&self,) -> WriteGuard<'_, T,> {
+}
+
+// SAFETY: readers only get in while the state is even, a writer only while it's 0 or 1 (no
+// readers), and then sets it to u32::MAX so nobody else gets in. Locks Acquire, unlocks Release.
+unsafe impl<W: WaitBackend,> RawRwLock for RawFutexRwLock<W,> {
+    #[allow(
+        clippy::declare_interior_mutable_const,
+        reason = "`INIT` is copied into every new lock, it is never shared"
+    )]
+    const INIT: Self = RawFutexRwLock {
+        state: AtomicU32::new(0,),
+        writer_wake_count: AtomicU32::new(0,),
+        upgrader: AtomicU32::new(0,),
+        stats: Counters::new(),
+        backend: PhantomData,
+    };
+
+    fn lock_shared(&self,) {
+        self.read(None, Contention::default(),);
+    }
+
+    fn try_lock_shared(&self,) -> bool {
+        let mut s = self.state.load(Relaxed,);
+        while s.is_multiple_of(2,) {
+            assert!(s < READER_LIMIT, "too many readers.");
+            match self.state.compare_exchange_weak(s, s + 2, Acquire, Relaxed,) {
+                Ok(_,) => {
+                    self.stats.record_acquire();
+                    if s == 0 {
+                        self.stats.record_read_start();
+                    }
+                    return true;
+                }
+                Err(e,) => s = e,
+            }
+        }
+        false
+    }
+
+    unsafe fn unlock_shared(&self,) {
+        match self.state.fetch_sub(2, Release,) {
+            2 => self.stats.record_read_end(),
+            // The last reader, a writer is waiting.
+            3 => {
+                self.stats.record_read_end();
+                self.writer_wake_count.fetch_add(1, Release,);
+                W::wake(&self.writer_wake_count, 1,);
+            }
+            // Down to one reader, which may be an upgrader waiting for the others to leave.
+            // A writer may be sleeping there as well, so wake both.
+            5 => {
+                self.writer_wake_count.fetch_add(1, Release,);
+                W::wake(&self.writer_wake_count, u32::MAX,);
+            }
+            _ => {}
+        }
+    }
+
+    fn lock_exclusive(&self,) {
         let mut s = self.state.load(Relaxed,);
+        let mut timer = None;
+        let mut contention = Contention::default();
         loop {
             if s <= 1 {
                 match self.state.compare_exchange(s, u32::MAX, Acquire, Relaxed,) {
-                    Ok(_,) => return WriteGuard { rwlock: self, },
+                    Ok(_,) => {
+                        self.acquired(timer, contention,);
+                        self.stats.record_hold_start();
+                        return;
+                    }
                     Err(e,) => {
                         s = e;
                         continue;
@@ -70,59 +153,151 @@ impl<T,> RwLock<T,> {
             }
             let w = self.writer_wake_count.load(Acquire,);
             s = self.state.load(Relaxed,);
-            if s <= 2 {
-                wait(&self.writer_wake_count, w,);
+            // Only sleep while the state is odd. If the previous writer left and readers got in
+            // before we set the waiting bit, no reader would ever wake us, so go around and set it.
+            if s >= 2 && !s.is_multiple_of(2,) {
+                timer.get_or_insert_with(WaitTimer::start,);
+                contention.futex_waits += 1;
+                W::wait(&self.writer_wake_count, w,);
                 s = self.state.load(Relaxed,);
+            } else {
+                timer.get_or_insert_with(WaitTimer::start,);
+                contention.spins += 1;
             }
         }
     }
-}
 
-pub struct ReadGuard<'a, T,> {
-    rwlock: &'a RwLock<T,>,
-}
+    fn try_lock_exclusive(&self,) -> bool {
+        let s = self.state.load(Relaxed,);
+        let locked = s <= 1 && self.state.compare_exchange(s, u32::MAX, Acquire, Relaxed,).is_ok();
+        if locked {
+            self.stats.record_acquire();
+            self.stats.record_hold_start();
+        }
+        locked
+    }
 
-impl<T,> Deref for ReadGuard<'_, T,> {
-    type Target = T;
-    fn deref(&self,) -> &T {
-        // SAFETY: accessing an UnsafeCell, it is save, given the Guard
-        unsafe { &*self.rwlock.value.get() }
+    unsafe fn unlock_exclusive(&self,) {
+        self.stats.record_hold_end();
+        self.state.store(0, Release,);
+        self.writer_wake_count.fetch_add(1, Release,);
+        W::wake(&self.writer_wake_count, 1,);
+        W::wake(&self.state, u32::MAX,);
     }
 }
 
-impl<T,> Drop for ReadGuard<'_, T,> {
-    fn drop(&mut self,) {
-        if self.rwlock.state.fetch_sub(2, Release,) == 3 {
-            self.rwlock.writer_wake_count.fetch_add(1, Release,);
-            wake_one(&self.rwlock.writer_wake_count,);
+// SAFETY: the `upgrader` mutex admits one upgradable reader at a time. It holds a read lock, so
+// writers stay out, and upgrading only swaps that read lock for the write lock once it's the last.
+unsafe impl<W: WaitBackend,> RawRwLockUpgrade for RawFutexRwLock<W,> {
+    fn lock_upgradable(&self,) {
+        let mut timer = None;
+        let mut contention = Contention::default();
+        if self.upgrader.compare_exchange(0, 1, Acquire, Relaxed,).is_err() {
+            timer = Some(WaitTimer::start(),);
+            contention = lock_contended::<W,>(&self.upgrader,);
         }
+        self.read(timer, contention,);
+    }
+
+    fn try_lock_upgradable(&self,) -> bool {
+        if self.upgrader.compare_exchange(0, 1, Acquire, Relaxed,).is_err() {
+            return false;
+        }
+        if self.try_lock_shared() {
+            return true;
+        }
+        self.unlock_upgrader();
+        false
     }
-}
 
-pub struct WriteGuard<'a, T,> {
-    rwlock: &'a RwLock<T,>,
+    unsafe fn unlock_upgradable(&self,) {
+        // SAFETY: the upgradable lock comes with a read lock.
+        unsafe { self.unlock_shared() };
+        self.unlock_upgrader();
+    }
+
+    unsafe fn upgrade(&self,) {
+        let mut s = self.state.load(Relaxed,);
+        loop {
+            // Only our read lock left, perhaps with a writer waiting behind it.
+            if s == 2 || s == 3 {
+                match self.state.compare_exchange(s, u32::MAX, Acquire, Relaxed,) {
+                    Ok(_,) => break,
+                    Err(e,) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+            // Set the waiting bit, so no new readers get in.
+            if s.is_multiple_of(2,) {
+                match self.state.compare_exchange(s, s + 1, Relaxed, Relaxed,) {
+                    Ok(_,) => {}
+                    Err(e,) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+            let w = self.writer_wake_count.load(Acquire,);
+            s = self.state.load(Relaxed,);
+            // Readers leaving wake everyone on `writer_wake_count` once we're the last one left.
+            if s > 3 && !s.is_multiple_of(2,) {
+                W::wait(&self.writer_wake_count, w,);
+                s = self.state.load(Relaxed,);
+            }
+        }
+        self.stats.record_read_end();
+        self.stats.record_hold_start();
+        self.unlock_upgrader();
+    }
+
+    unsafe fn try_upgrade(&self,) -> bool {
+        let s = self.state.load(Relaxed,);
+        if !(s == 2 || s == 3)
+            || self.state.compare_exchange(s, u32::MAX, Acquire, Relaxed,).is_err()
+        {
+            return false;
+        }
+        self.stats.record_read_end();
+        self.stats.record_hold_start();
+        self.unlock_upgrader();
+        true
+    }
 }
 
-impl<T,> Deref for WriteGuard<'_, T,> {
-    type Target = T;
-    fn deref(&self,) -> &T {
-        // SAFETY: accessing an UnsafeCell, it is save, given the Guard
-        unsafe { &*self.rwlock.value.get() }
+impl<W: WaitBackend,> RawFutexRwLock<W,> {
+    fn unlock_upgrader(&self,) {
+        if self.upgrader.swap(0, Release,) == 2 {
+            W::wake(&self.upgrader, 1,);
+        }
     }
 }
 
-impl<T,> DerefMut for WriteGuard<'_, T,> {
-    fn deref_mut(&mut self,) -> &mut T {
-        // SAFETY: see safety comment for Deref impl
-        unsafe { &mut *self.rwlock.value.get() }
+#[cfg(feature = "stats")]
+impl<W,> crate::stats::StatsSource for RawFutexRwLock<W,> {
+    fn stats(&self,) -> crate::stats::LockStats {
+        self.stats.snapshot()
+    }
+
+    fn reset_stats(&self,) {
+        self.stats.reset();
     }
 }
 
-impl<T,> Drop for WriteGuard<'_, T,> {
-    fn drop(&mut self,) {
-        self.rwlock.state.store(0, Release,);
-        self.rwlock.writer_wake_count.fetch_add(1, Release,);
-        wake_one(&self.rwlock.writer_wake_count,);
-        wake_all(&self.rwlock.state,);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn too_many_readers_panics_before_the_state_overflows() {
+        let lock = RawFutexRwLock::<DefaultBackend,>::INIT;
+        lock.state.store(READER_LIMIT - 2, Relaxed,);
+        assert!(lock.try_lock_shared());
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| lock.try_lock_shared()),).is_err());
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| lock.lock_shared()),).is_err());
+        assert_eq!(lock.state.load(Relaxed,), READER_LIMIT);
+        assert!(!lock.try_lock_exclusive());
     }
 }
@@ -1,61 +1,63 @@
-use std::ops::{Deref, DerefMut};
-use std::sync::atomic::Ordering::{Acquire, Release};
-use std::{cell::UnsafeCell, sync::atomic::AtomicBool};
-
-pub struct SpinLock<T,> {
+use crate::lock_api::{self, RawMutex};
+use crate::stats::{Contention, Counters, WaitTimer};
+use crate::sync::atomic::AtomicBool;
+use crate::sync::spin_loop;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+/// A lock that spins until it's free, never sleeps.
+pub type SpinLock<T,> = lock_api::Mutex<RawSpinLock, T,>;
+pub type Guard<'a, T,> = lock_api::MutexGuard<'a, RawSpinLock, T,>;
+
+/// The locking protocol of [`SpinLock`]: a flag, swapped until we're the one who set it.
+pub struct RawSpinLock {
     locked: AtomicBool,
-    value: UnsafeCell<T,>,
+    stats: Counters,
 }
 
-// SAFETY: if the spinlock is Send, we have to make sure it is sync
-unsafe impl<T,> Sync for SpinLock<T,> where T: Send {}
-
-impl<T,> SpinLock<T,> {
-    // NOTE: pub functions are protected by the Guard
-
-    pub const fn new(value: T,) -> Self {
-        Self { locked: AtomicBool::new(false,), value: UnsafeCell::new(value,), }
+// SAFETY: the swap sets the flag for exactly one thread, Acquire pairs with the Release in unlock.
+unsafe impl RawMutex for RawSpinLock {
+    #[allow(
+        clippy::declare_interior_mutable_const,
+        reason = "`INIT` is copied into every new lock, it is never shared"
+    )]
+    const INIT: Self = RawSpinLock { locked: AtomicBool::new(false,), stats: Counters::new(), };
+
+    fn lock(&self,) {
+        if self.locked.swap(true, Acquire,) {
+            let timer = WaitTimer::start();
+            let mut contention = Contention::default();
+            while self.locked.swap(true, Acquire,) {
+                contention.spins += 1;
+                spin_loop();
+            }
+            self.stats.record_contended(timer, contention,);
+        }
+        self.stats.record_acquire();
+        self.stats.record_hold_start();
     }
 
-    pub fn lock(&self,) -> Guard<'_, T,> {
-        while self.locked.swap(true, Acquire,) {
-            std::hint::spin_loop();
+    fn try_lock(&self,) -> bool {
+        let locked = self.locked.compare_exchange(false, true, Acquire, Relaxed,).is_ok();
+        if locked {
+            self.stats.record_acquire();
+            self.stats.record_hold_start();
         }
-        Guard::new(self,)
+        locked
     }
 
-    pub fn unlock(&self,) {
+    unsafe fn unlock(&self,) {
+        self.stats.record_hold_end();
         self.locked.store(false, Release,);
     }
 }
 
-pub struct Guard<'a, T,> {
-    lock: &'a SpinLock<T,>,
-}
-
-impl<'a, T,> Guard<'a, T,> {
-    pub const fn new(lock: &'a SpinLock<T,>,) -> Self {
-        Guard { lock, }
+#[cfg(feature = "stats")]
+impl crate::stats::StatsSource for RawSpinLock {
+    fn stats(&self,) -> crate::stats::LockStats {
+        self.stats.snapshot()
     }
-}
-
-impl<T,> Deref for Guard<'_, T,> {
-    type Target = T;
-    fn deref(&self,) -> &T {
-        // SAFETY: the very existence of the guard guarentees the lock is exclusively locked.
-        unsafe { &*self.lock.value.get() }
-    }
-}
-
-impl<T,> DerefMut for Guard<'_, T,> {
-    fn deref_mut(&mut self,) -> &mut T {
-        // SAFETY: the very existence of the guard guarentees the lock is exclusively locked.
-        unsafe { &mut *self.lock.value.get() }
-    }
-}
 
-impl<T,> Drop for Guard<'_, T,> {
-    fn drop(&mut self,) {
-        self.lock.unlock();
+    fn reset_stats(&self,) {
+        self.stats.reset();
     }
 }
@@ -0,0 +1,314 @@
+//! Contention statistics for the lock types.
+//!
+//! With the `stats` feature enabled every `Mutex`, `RwLock`, `SpinLock` and `CondVar` keeps a set
+//! of counters that can be read with `stats()` and cleared with `reset_stats()`. Locks can also be
+//! registered under a name in a global registry, which is what [`report`] and
+//! [`contention_report`] walk.
+//!
+//! Without the feature the counters are zero-sized and every recording call compiles to nothing.
+
+#[cfg(feature = "stats")]
+pub use enabled::*;
+
+#[cfg(not(feature = "stats"))]
+pub(crate) use disabled::*;
+
+/// How much work a contended acquisition had to do before it got the lock.
+#[derive(Clone, Copy, Debug, Default,)]
+pub(crate) struct Contention {
+    pub(crate) spins: u64,
+    pub(crate) futex_waits: u64,
+}
+
+#[cfg(feature = "stats")]
+mod enabled {
+    use super::Contention;
+    use crate::mutex::Mutex;
+    use std::fmt;
+    use std::sync::OnceLock;
+    use std::sync::atomic::Ordering::{Acquire, Relaxed};
+    use std::sync::atomic::{AtomicU64, fence};
+    use std::time::{Duration, Instant};
+
+    /// A snapshot of the counters of a single lock.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq,)]
+    pub struct LockStats {
+        /// Number of times the lock was acquired (or waited on, for a `CondVar`).
+        pub acquisitions: u64,
+        /// Number of acquisitions that could not take the fast path.
+        pub contended: u64,
+        /// Number of spin-loop iterations spent waiting for the lock.
+        pub spin_iterations: u64,
+        /// Number of times a thread went to sleep on the futex.
+        pub futex_waits: u64,
+        /// Total time spent waiting in contended acquisitions.
+        pub total_wait: Duration,
+        /// Longest time the lock was held by a single guard. Overlapping read locks can't be told
+        /// apart, so for an `RwLock` they count as one hold, from the first reader in to the last
+        /// one out.
+        pub max_hold: Duration,
+    }
+
+    impl fmt::Display for LockStats {
+        fn fmt(&self, f: &mut fmt::Formatter<'_,>,) -> fmt::Result {
+            write!(
+                f,
+                "acquisitions={} contended={} spins={} futex_waits={} total_wait={:?} max_hold={:?}",
+                self.acquisitions,
+                self.contended,
+                self.spin_iterations,
+                self.futex_waits,
+                self.total_wait,
+                self.max_hold,
+            )
+        }
+    }
+
+    /// Anything that can report `LockStats`. Implemented by every lock type so it can be stored
+    /// in the global registry.
+    pub trait StatsSource: Sync {
+        /// The lock's counters since it was made or last reset. Each counter is read on its own
+        /// while the lock stays in use, so the snapshot isn't atomic as a whole: `contended` may
+        /// already count an acquisition that `acquisitions` doesn't yet, say.
+        fn stats(&self,) -> LockStats;
+
+        /// Sets every counter back to zero, one after the other. It doesn't stop the lock: an
+        /// acquisition racing with the reset may be counted partly or not at all, and a guard
+        /// held across it can still set `max_hold` when it's dropped.
+        fn reset_stats(&self,);
+    }
+
+    pub(crate) struct Counters {
+        acquisitions: AtomicU64,
+        contended: AtomicU64,
+        spin_iterations: AtomicU64,
+        futex_waits: AtomicU64,
+        wait_nanos: AtomicU64,
+        max_hold_nanos: AtomicU64,
+        // NOTE: when the current holder got the lock, in nanoseconds since `START`.
+        held_since: AtomicU64,
+        // NOTE: the same for the first of the current readers of an RwLock.
+        read_since: AtomicU64,
+    }
+
+    static START: OnceLock<Instant,> = OnceLock::new();
+
+    fn now() -> u64 {
+        nanos(START.get_or_init(Instant::now,).elapsed(),)
+    }
+
+    impl Counters {
+        pub(crate) const fn new() -> Self {
+            Self {
+                acquisitions: AtomicU64::new(0,),
+                contended: AtomicU64::new(0,),
+                spin_iterations: AtomicU64::new(0,),
+                futex_waits: AtomicU64::new(0,),
+                wait_nanos: AtomicU64::new(0,),
+                max_hold_nanos: AtomicU64::new(0,),
+                held_since: AtomicU64::new(0,),
+                read_since: AtomicU64::new(0,),
+            }
+        }
+
+        #[inline]
+        pub(crate) fn record_acquire(&self,) {
+            self.acquisitions.fetch_add(1, Relaxed,);
+        }
+
+        pub(crate) fn record_contended(&self, timer: WaitTimer, contention: Contention,) {
+            self.contended.fetch_add(1, Relaxed,);
+            self.spin_iterations.fetch_add(contention.spins, Relaxed,);
+            self.futex_waits.fetch_add(contention.futex_waits, Relaxed,);
+            self.wait_nanos.fetch_add(nanos(timer.0.elapsed(),), Relaxed,);
+        }
+
+        /// Called by an exclusive lock right after it was acquired. Relaxed is enough: the
+        /// lock itself orders this before the holder's `record_hold_end`.
+        #[inline]
+        pub(crate) fn record_hold_start(&self,) {
+            self.held_since.store(now(), Relaxed,);
+        }
+
+        /// Called by an exclusive lock right before it is released.
+        #[inline]
+        pub(crate) fn record_hold_end(&self,) {
+            let held = now().saturating_sub(self.held_since.load(Relaxed,),);
+            self.max_hold_nanos.fetch_max(held, Relaxed,);
+        }
+
+        /// Called by the first reader of an `RwLock`, right after it got in.
+        #[inline]
+        pub(crate) fn record_read_start(&self,) {
+            self.read_since.store(now(), Relaxed,);
+        }
+
+        /// Called by the last reader of an `RwLock`, right after it left (or upgraded). The lock
+        /// only orders the readers' unlocks with Release, so this takes the fence: every reader
+        /// before us released, including the first one after its `record_read_start`.
+        #[inline]
+        pub(crate) fn record_read_end(&self,) {
+            fence(Acquire,);
+            let held = now().saturating_sub(self.read_since.load(Relaxed,),);
+            self.max_hold_nanos.fetch_max(held, Relaxed,);
+        }
+
+        pub(crate) fn snapshot(&self,) -> LockStats {
+            LockStats {
+                acquisitions: self.acquisitions.load(Relaxed,),
+                contended: self.contended.load(Relaxed,),
+                spin_iterations: self.spin_iterations.load(Relaxed,),
+                futex_waits: self.futex_waits.load(Relaxed,),
+                total_wait: Duration::from_nanos(self.wait_nanos.load(Relaxed,),),
+                max_hold: Duration::from_nanos(self.max_hold_nanos.load(Relaxed,),),
+            }
+        }
+
+        pub(crate) fn reset(&self,) {
+            self.acquisitions.store(0, Relaxed,);
+            self.contended.store(0, Relaxed,);
+            self.spin_iterations.store(0, Relaxed,);
+            self.futex_waits.store(0, Relaxed,);
+            self.wait_nanos.store(0, Relaxed,);
+            self.max_hold_nanos.store(0, Relaxed,);
+        }
+    }
+
+    fn nanos(d: Duration,) -> u64 {
+        u64::try_from(d.as_nanos(),).unwrap_or(u64::MAX,)
+    }
+
+    /// Started when an acquisition leaves the fast path.
+    pub(crate) struct WaitTimer(Instant,);
+
+    impl WaitTimer {
+        #[inline]
+        pub(crate) fn start() -> Self {
+            Self(Instant::now(),)
+        }
+    }
+
+    enum Source {
+        Static(&'static dyn StatsSource,),
+        Shared(std::sync::Weak<dyn StatsSource + Send,>,),
+    }
+
+    struct Entry {
+        name: String,
+        source: Source,
+    }
+
+    static REGISTRY: Mutex<Vec<Entry,>,> = Mutex::new(Vec::new(),);
+
+    /// Registers a lock that lives for the rest of the program under `name`.
+    pub fn register<L: StatsSource,>(name: impl Into<String,>, lock: &'static L,) {
+        REGISTRY.lock().push(Entry { name: name.into(), source: Source::Static(lock,), },);
+    }
+
+    /// Registers a shared lock under `name`. The registry only keeps a weak reference, the entry
+    /// disappears once the last `Arc` is dropped.
+    pub fn register_shared<L: StatsSource + Send + 'static,>(
+        name: impl Into<String,>,
+        lock: &std::sync::Arc<L,>,
+    ) {
+        let lock: std::sync::Arc<dyn StatsSource + Send,> = lock.clone();
+        REGISTRY.lock().push(Entry {
+            name: name.into(),
+            source: Source::Shared(std::sync::Arc::downgrade(&lock,),),
+        },);
+    }
+
+    /// Returns the name and current stats of every registered lock that is still alive.
+    pub fn report() -> Vec<(String, LockStats,),> {
+        let mut registry = REGISTRY.lock();
+        let mut out = Vec::with_capacity(registry.len(),);
+        registry.retain(|entry| match &entry.source {
+            Source::Static(lock,) => {
+                out.push((entry.name.clone(), lock.stats(),),);
+                true
+            }
+            Source::Shared(weak,) => match weak.upgrade() {
+                Some(lock,) => {
+                    out.push((entry.name.clone(), lock.stats(),),);
+                    true
+                }
+                None => false,
+            },
+        },);
+        out
+    }
+
+    /// Resets the stats of every registered lock.
+    pub fn reset_all() {
+        for entry in REGISTRY.lock().iter() {
+            match &entry.source {
+                Source::Static(lock,) => lock.reset_stats(),
+                Source::Shared(weak,) => {
+                    if let Some(lock,) = weak.upgrade() {
+                        lock.reset_stats();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Formats [`report`] as a table, most contended locks first.
+    pub fn contention_report() -> String {
+        let mut rows = report();
+        rows.sort_by(|a, b| {
+            b.1.contended.cmp(&a.1.contended,).then(b.1.total_wait.cmp(&a.1.total_wait,),)
+        },);
+        let width = rows.iter().map(|(name, _,)| name.len(),).max().unwrap_or(0,).max(4,);
+        let mut out = format!(
+            "{:<width$} {:>12} {:>12} {:>12} {:>12} {:>14} {:>14}\n",
+            "lock", "acquisitions", "contended", "spins", "futex_waits", "total_wait", "max_hold",
+        );
+        for (name, s,) in rows {
+            out.push_str(&format!(
+                "{:<width$} {:>12} {:>12} {:>12} {:>12} {:>14} {:>14}\n",
+                name,
+                s.acquisitions,
+                s.contended,
+                s.spin_iterations,
+                s.futex_waits,
+                format!("{:?}", s.total_wait),
+                format!("{:?}", s.max_hold),
+            ),);
+        }
+        out
+    }
+}
+
+#[cfg(not(feature = "stats"))]
+mod disabled {
+    use super::Contention;
+
+    pub(crate) struct Counters;
+
+    impl Counters {
+        pub(crate) const fn new() -> Self {
+            Self
+        }
+        #[inline(always)]
+        pub(crate) fn record_acquire(&self,) {}
+        #[inline(always)]
+        pub(crate) fn record_contended(&self, _timer: WaitTimer, _contention: Contention,) {}
+        #[inline(always)]
+        pub(crate) fn record_hold_start(&self,) {}
+        #[inline(always)]
+        pub(crate) fn record_hold_end(&self,) {}
+        #[inline(always)]
+        pub(crate) fn record_read_start(&self,) {}
+        #[inline(always)]
+        pub(crate) fn record_read_end(&self,) {}
+    }
+
+    pub(crate) struct WaitTimer;
+
+    impl WaitTimer {
+        #[inline(always)]
+        pub(crate) fn start() -> Self {
+            Self
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! The atomics, cells and thread parking the primitives are built from (futex calls live in
+//! [`crate::futex`]).
+//!
+//! Every module goes through here instead of `std`, so a `cfg(model_check)` build can swap them
+//! for the shims in [`crate::model`] and have the model checker drive every interleaving.
+
+#[cfg(not(model_check))]
+pub(crate) mod atomic {
+    #[cfg(feature = "std")]
+    pub(crate) use core::sync::atomic::AtomicPtr;
+    pub(crate) use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, AtomicUsize, fence};
+}
+
+#[cfg(model_check)]
+pub(crate) mod atomic {
+    pub(crate) use crate::model::atomic::{
+        AtomicBool, AtomicPtr, AtomicU8, AtomicU32, AtomicUsize, fence,
+    };
+}
+
+#[cfg(not(model_check))]
+pub(crate) use core::hint::spin_loop;
+
+#[cfg(model_check)]
+pub(crate) use crate::model::hint::spin_loop;
+
+#[cfg(all(feature = "std", not(model_check)))]
+pub(crate) mod thread {
+    pub(crate) use std::thread::{Thread, current, park};
+}
+
+#[cfg(model_check)]
+pub(crate) mod thread {
+    pub(crate) use crate::model::thread::{Thread, current, park};
+}
+
+#[cfg(not(model_check))]
+pub(crate) mod cell {
+    use core::alloc::Layout;
+
+    /// `core::cell::UnsafeCell` with the access split into reads and writes, which is what the
+    /// model checker needs to find data races. Here it compiles down to `get()`.
+    #[repr(transparent)]
+    pub(crate) struct UnsafeCell<T: ?Sized,>(core::cell::UnsafeCell<T,>,);
+
+    impl<T,> UnsafeCell<T,> {
+        pub(crate) const fn new(value: T,) -> Self {
+            Self(core::cell::UnsafeCell::new(value,),)
+        }
+
+        pub(crate) fn into_inner(self,) -> T {
+            self.0.into_inner()
+        }
+    }
+
+    impl<T: ?Sized,> UnsafeCell<T,> {
+        #[inline(always)]
+        pub(crate) fn read_ptr(&self,) -> *const T {
+            self.0.get()
+        }
+
+        #[inline(always)]
+        pub(crate) fn write_ptr(&self,) -> *mut T {
+            self.0.get()
+        }
+
+        #[inline(always)]
+        pub(crate) fn get_mut(&mut self,) -> &mut T {
+            self.0.get_mut()
+        }
+
+        /// Pointer to the value of a cell that may not be initialised yet. Not an access.
+        ///
+        /// # Safety
+        ///
+        /// `this` must point into an allocation big enough for the cell.
+        #[inline(always)]
+        pub(crate) const unsafe fn raw_get(this: *const Self,) -> *mut T {
+            core::cell::UnsafeCell::raw_get(this as *const core::cell::UnsafeCell<T,>,)
+        }
+
+        /// The layout of a cell holding a value with layout `value`, and the offset of the value.
+        pub(crate) fn layout_for(value: Layout,) -> (Layout, usize,) {
+            (value, 0,)
+        }
+
+        /// Initialises everything in the cell at `this` but the value itself, for cells built in
+        /// place. Nothing to do here.
+        ///
+        /// # Safety
+        ///
+        /// `this` must be valid for writes.
+        #[inline(always)]
+        pub(crate) unsafe fn init_header(this: *mut Self,) {
+            let _ = this;
+        }
+    }
+}
+
+#[cfg(model_check)]
+pub(crate) mod cell {
+    pub(crate) use crate::model::cell::UnsafeCell;
+}
+
+/// Aborts the process, for reference counts about to overflow.
+#[cfg(feature = "std")]
+pub(crate) fn abort() -> ! {
+    std::process::abort()
+}
+
+/// Without std there is no `abort`, but a panic while unwinding from another one aborts too (and
+/// with `panic = "abort"` the first one already does).
+#[cfg(not(feature = "std"))]
+pub(crate) fn abort() -> ! {
+    struct PanicOnDrop;
+
+    impl Drop for PanicOnDrop {
+        fn drop(&mut self,) {
+            panic!("aborting");
+        }
+    }
+
+    let _bomb = PanicOnDrop;
+    panic!("aborting");
+}
@@ -0,0 +1,89 @@
+//! How the blocking primitives put a thread to sleep and wake it up again.
+//!
+//! [`RawFutexMutex`](crate::mutex::RawFutexMutex) and
+//! [`RawFutexRwLock`](crate::rwlock::RawFutexRwLock) only ever wait for an `AtomicU32` to change,
+//! so all they need from the platform is a futex-like [`WaitBackend`].
+//! With the `std` feature the default is [`Futex`], the OS futex (or its emulation). Without it
+//! the default is [`Spin`], and embedded or kernel code can plug in its own park/unpark:
+//!
+//! ```
+//! use atomics_locks::lock_api::Mutex;
+//! use atomics_locks::mutex::RawFutexMutex;
+//! use atomics_locks::wait::WaitBackend;
+//! use std::sync::atomic::AtomicU32;
+//! use std::sync::atomic::Ordering::Relaxed;
+//!
+//! /// Stands in for a platform wait queue: yields until the value changes.
+//! struct Yield;
+//!
+//! impl WaitBackend for Yield {
+//!     fn wait(atomic: &AtomicU32, expected: u32) {
+//!         while atomic.load(Relaxed) == expected {
+//!             std::thread::yield_now();
+//!         }
+//!     }
+//!
+//!     fn wake(_: &AtomicU32, _: u32) {}
+//! }
+//!
+//! let m: Mutex<RawFutexMutex<Yield>, u32> = Mutex::new(0);
+//! *m.lock() += 1;
+//! assert_eq!(*m.lock(), 1);
+//! ```
+
+use crate::sync::atomic::AtomicU32;
+
+/// Blocks threads on an atomic until another thread wakes them, like a futex.
+///
+/// The functions take no `self`: a backend is a type parameter of the primitives, so it has to
+/// keep any state it needs (wait queues, say) outside them, keyed by the atomic's address.
+pub trait WaitBackend {
+    /// Blocks until woken, if `atomic` holds `expected`. The check and going to sleep must be
+    /// atomic with respect to [`wake`](Self::wake), or a wake could be missed. Returning early,
+    /// spuriously, is fine: callers check their condition again.
+    fn wait(atomic: &AtomicU32, expected: u32,);
+
+    /// Wakes up to `n` threads waiting on `atomic`. Called after `atomic` was changed.
+    fn wake(atomic: &AtomicU32, n: u32,);
+}
+
+/// The OS futex on Linux, the crate's emulation of one everywhere else.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default,)]
+pub struct Futex;
+
+#[cfg(feature = "std")]
+impl WaitBackend for Futex {
+    #[inline]
+    fn wait(atomic: &AtomicU32, expected: u32,) {
+        crate::futex::wait(atomic, expected, None,);
+    }
+
+    #[inline]
+    fn wake(atomic: &AtomicU32, n: u32,) {
+        crate::futex::wake(atomic, n,);
+    }
+}
+
+/// Never sleeps: a wait is a spin hint, and waking is a no-op. Works anywhere, but burns the CPU
+/// for as long as a lock is held by someone else.
+#[derive(Clone, Copy, Debug, Default,)]
+pub struct Spin;
+
+impl WaitBackend for Spin {
+    #[inline]
+    fn wait(_: &AtomicU32, _: u32,) {
+        crate::sync::spin_loop();
+    }
+
+    #[inline]
+    fn wake(_: &AtomicU32, _: u32,) {}
+}
+
+/// The backend `RawFutexMutex` and `RawFutexRwLock` use unless told otherwise.
+#[cfg(feature = "std")]
+pub type DefaultBackend = Futex;
+
+/// The backend `RawFutexMutex` and `RawFutexRwLock` use unless told otherwise.
+#[cfg(not(feature = "std"))]
+pub type DefaultBackend = Spin;
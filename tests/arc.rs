@@ -1,7 +1,13 @@
+pub mod common;
 pub mod must;
+use atomics_locks::allocator::Allocator;
 use atomics_locks::arc::{Arc, Weak};
 use atomics_locks::spinlock::SpinLock;
+use atomics_locks::unsize_arc;
+use common::Counting;
 use must::Must;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Display;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::Relaxed;
 use std::thread;
@@ -163,3 +169,260 @@ fn arc_spinlock() {
     assert!(carter_weak.upgrade().is_none());
     assert!(madison_weak.upgrade().is_none());
 }
+
+#[test]
+fn arc_str_and_slices() {
+    let s: Arc<str,> = Arc::from("hello",);
+    let t = s.clone();
+    thread::spawn(move || assert_eq!(&*t, "hello"),).join().must();
+    assert_eq!(&*s, "hello");
+    assert_eq!(&*Arc::<str,>::from(String::from("owned",),), "owned");
+    assert_eq!(&*Arc::<str,>::from("",), "");
+
+    let v: Arc<[String],> = Arc::from(vec![String::from("a",), String::from("b",)],);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[1], "b");
+    let squares: Arc<[u64],> = (1..=4).map(|i| i * i,).collect();
+    assert_eq!(&*squares, &[1, 4, 9, 16]);
+    let units: Arc<[()],> = Arc::from(&[(), (),][..],);
+    assert_eq!(units.len(), 2);
+    let arr: Arc<[u8],> = unsize_arc!(Arc::new([1u8, 2, 3],), [u8]);
+    assert_eq!(&*arr, &[1, 2, 3]);
+}
+
+#[test]
+fn arc_dyn() {
+    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0,);
+
+    struct Loud(u16,);
+
+    impl Display for Loud {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_,>,) -> std::fmt::Result {
+            write!(f, "loud {}", self.0)
+        }
+    }
+
+    impl Drop for Loud {
+        fn drop(&mut self,) {
+            NUM_DROPS.fetch_add(1, Relaxed,);
+        }
+    }
+
+    let boxed: Box<dyn Display + Send + Sync,> = Box::new(Loud(1,),);
+    let a: Arc<dyn Display + Send + Sync,> = Arc::from(boxed,);
+    let b: Arc<dyn Display + Send + Sync,> =
+        unsize_arc!(Arc::new(Loud(2,),), dyn Display + Send + Sync);
+    let weak = Arc::downgrade(&b,);
+    thread::scope(|s| {
+        s.spawn(|| assert_eq!(a.to_string(), "loud 1"),);
+        s.spawn(|| assert_eq!(weak.upgrade().must().to_string(), "loud 2"),);
+    },);
+    assert_eq!(NUM_DROPS.load(Relaxed), 0);
+    drop((a, b,),);
+    assert_eq!(NUM_DROPS.load(Relaxed), 2);
+    assert!(weak.upgrade().is_none());
+
+    let mut c: Arc<[u32],> = Arc::from(vec![1, 2],);
+    Arc::get_mut(&mut c,).must()[0] = 5;
+    assert_eq!(&*c, &[5, 2]);
+}
+
+#[test]
+fn arc_counts_and_raw() {
+    let a = Arc::new(5,);
+    let b = a.clone();
+    let weak = Arc::downgrade(&a,);
+    assert_eq!((Arc::strong_count(&a), Arc::weak_count(&a)), (2, 1));
+    assert_eq!((weak.strong_count(), weak.weak_count()), (2, 1));
+    assert!(Arc::ptr_eq(&a, &b));
+    assert!(!Arc::ptr_eq(&a, &Arc::new(5)));
+    assert_eq!(Arc::as_ptr(&a), &*b as *const i32);
+
+    let raw = Arc::into_raw(b,);
+    assert_eq!(Arc::strong_count(&a), 2);
+    // SAFETY: `raw` comes from into_raw, and is only turned back once.
+    let b = unsafe { Arc::from_raw(raw,) };
+    assert!(Arc::ptr_eq(&a, &b));
+    drop((a, b,),);
+    assert_eq!((weak.strong_count(), weak.weak_count()), (0, 0));
+
+    let s: Arc<str,> = Arc::from("unsized",);
+    let raw = Arc::into_raw(s,);
+    // SAFETY: as above.
+    let s = unsafe { Arc::from_raw(raw,) };
+    assert_eq!(&*s, "unsized");
+    let d: Arc<dyn Display,> = unsize_arc!(Arc::new(7u64,), dyn Display);
+    // SAFETY: as above.
+    let d = unsafe { Arc::from_raw(Arc::into_raw(d,),) };
+    assert_eq!(d.to_string(), "7");
+
+    let empty = Weak::<String,>::new();
+    assert!(empty.upgrade().is_none());
+    assert_eq!((empty.strong_count(), empty.weak_count()), (0, 0));
+    assert!(empty.ptr_eq(&empty.clone()));
+    assert!(Weak::<String,>::default().upgrade().is_none());
+}
+
+#[test]
+fn arc_into_inner_race() {
+    for _ in 0..100 {
+        let a = Arc::new(String::from("once",),);
+        let clones: Vec<_,> = (0..4).map(|_| a.clone(),).collect();
+        drop(a,);
+        let got = AtomicUsize::new(0,);
+        thread::scope(|s| {
+            for c in clones {
+                s.spawn(|| {
+                    if let Some(v,) = Arc::into_inner(c,) {
+                        assert_eq!(v, "once");
+                        got.fetch_add(1, Relaxed,);
+                    }
+                },);
+            }
+        },);
+        assert_eq!(got.into_inner(), 1);
+    }
+}
+
+#[test]
+fn arc_try_unwrap_and_make_mut() {
+    let a = Arc::new(String::from("a",),);
+    let b = a.clone();
+    let Err(a,) = Arc::try_unwrap(a,) else { panic!("unwrapped a shared Arc") };
+    drop(b,);
+    let weak = Arc::downgrade(&a,);
+    assert_eq!(Arc::try_unwrap(a,).must(), "a");
+    assert!(weak.upgrade().is_none());
+    assert_eq!(Arc::unwrap_or_clone(Arc::new(3,)), 3);
+    let shared = Arc::new(4,);
+    assert_eq!(Arc::unwrap_or_clone(shared.clone()), 4);
+
+    // Shared: the value is cloned, the other Arc keeps the old one.
+    let mut a = Arc::new(vec![1],);
+    let b = a.clone();
+    Arc::make_mut(&mut a,).push(2,);
+    assert_eq!((&**a, &**b), (&[1, 2][..], &[1][..]));
+    // Unique: changed in place.
+    let before = Arc::as_ptr(&a,);
+    Arc::make_mut(&mut a,).push(3,);
+    assert_eq!(Arc::as_ptr(&a), before);
+    // Only Weaks left: the value moves, the Weaks are cut off.
+    let weak = Arc::downgrade(&a,);
+    Arc::make_mut(&mut a,).push(4,);
+    assert!(weak.upgrade().is_none());
+    assert_eq!(*a, [1, 2, 3, 4]);
+    assert_eq!(Arc::weak_count(&a), 0);
+
+    // Other threads cloning from a shared Arc while make_mut runs never see the change.
+    let mut a = Arc::new(0,);
+    thread::scope(|s| {
+        let shared = a.clone();
+        s.spawn(move || {
+            for _ in 0..1000 {
+                assert_eq!(*shared.clone(), 0);
+            }
+        },);
+        *Arc::make_mut(&mut a,) += 1;
+    },);
+    assert_eq!(*a, 1);
+}
+
+#[test]
+fn arc_new_cyclic() {
+    struct Node {
+        me: Weak<Node,>,
+        value: u32,
+    }
+
+    let node = Arc::new_cyclic(|me| {
+        assert!(me.upgrade().is_none());
+        assert_eq!(me.strong_count(), 0);
+        Node { me: me.clone(), value: 9, }
+    },);
+    assert!(Arc::ptr_eq(&node, &node.me.upgrade().must()));
+    assert_eq!((Arc::strong_count(&node), Arc::weak_count(&node)), (1, 1));
+    assert_eq!(node.me.upgrade().must().value, 9);
+    let me = node.me.clone();
+    drop(node,);
+    assert!(me.upgrade().is_none());
+
+    // A Weak sent to another thread during construction only upgrades after it is done.
+    let (tx, rx,) = std::sync::mpsc::channel::<Weak<u32,>,>();
+    let t = thread::spawn(move || {
+        let weak = rx.recv().must();
+        loop {
+            if let Some(a,) = weak.upgrade() {
+                return *a;
+            }
+            thread::yield_now();
+        }
+    },);
+    let a = Arc::new_cyclic(|me| {
+        tx.send(me.clone(),).must();
+        thread::sleep(std::time::Duration::from_millis(10,),);
+        42
+    },);
+    assert_eq!(t.join().must(), 42);
+    drop(a,);
+}
+
+#[test]
+fn arc_traits() {
+    let a: Arc<str,> = Arc::from("b",);
+    let set: BTreeSet<Arc<str,>,> = ["c", "a", "b",].into_iter().map(Arc::from,).collect();
+    assert_eq!(set.iter().map(|s| &**s).collect::<Vec<_,>>(), ["a", "b", "c"]);
+    assert!(set.contains("b"));
+    let mut map = HashMap::new();
+    map.insert(a.clone(), 1,);
+    assert_eq!(map.get("b"), Some(&1));
+    assert_eq!(a, Arc::from("b"));
+    assert!(a < Arc::from("c"));
+    assert_eq!(format!("{a} {a:?}"), "b \"b\"");
+    assert_eq!(*Arc::<Vec<u8,>,>::default(), Vec::<u8,>::new());
+    assert_eq!(*Arc::from(3,), 3);
+    assert_eq!(format!("{:?}", Arc::downgrade(&a)), "(Weak)");
+}
+
+#[test]
+fn arc_new_in() {
+    let alloc = Counting::default();
+    let a = Arc::new_in(String::from("arena",), &alloc,);
+    let weak = Arc::downgrade(&a,);
+    let b = a.clone();
+    assert_eq!(alloc.live.load(Relaxed), 1);
+    thread::scope(|s| {
+        s.spawn(|| assert_eq!(*b.clone(), "arena"),);
+    },);
+    drop((a, b,),);
+    // The Weak keeps the allocation, and frees it through the same allocator.
+    assert!(weak.upgrade().is_none());
+    assert_eq!(alloc.live.load(Relaxed), 1);
+    drop(weak,);
+    assert_eq!(alloc.live.load(Relaxed), 0);
+
+    // make_mut clones into the same allocator, raw pointers find it again.
+    let mut a = Arc::new_in(vec![1], &alloc,);
+    let b = a.clone();
+    Arc::make_mut(&mut a,).push(2,);
+    assert_eq!(alloc.live.load(Relaxed), 2);
+    assert!(std::ptr::eq(*Arc::allocator(&a), &alloc));
+    drop(b,);
+    // SAFETY: from into_raw, turned back once.
+    let a = unsafe { Arc::<Vec<i32,>, &Counting,>::from_raw_in(Arc::into_raw(a,),) };
+    struct Node<'a,> {
+        me: Weak<Node<'a,>, &'a Counting,>,
+        value: u32,
+    }
+    let node = Arc::new_cyclic_in(|me| Node { me: me.clone(), value: 3, }, &alloc,);
+    assert_eq!(node.me.upgrade().must().value, 3);
+    drop((a, node,),);
+    assert_eq!(alloc.live.load(Relaxed), 0);
+
+    let system = Arc::new_in([1u8, 2, 3,], std::alloc::System,);
+    let slice: Arc<[u8], _,> = unsize_arc!(system, [u8]);
+    assert_eq!(&*slice, &[1, 2, 3]);
+    let layout = std::alloc::Layout::new::<(),>();
+    let zst = Counting::default().allocate(layout,).must();
+    // SAFETY: just allocated with this layout.
+    unsafe { Counting::default().deallocate(zst, layout,) };
+}
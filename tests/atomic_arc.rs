@@ -0,0 +1,92 @@
+pub mod must;
+use atomics_locks::arc::Arc;
+use atomics_locks::atomic_arc::AtomicArc;
+use must::Must;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::thread;
+
+/// Swaps per writer, fewer under miri.
+const ROUNDS: usize = if cfg!(miri) { 50 } else { 2000 };
+
+static LIVE: AtomicUsize = AtomicUsize::new(0,);
+
+/// A config whose fields always agree, and which counts how many are alive.
+struct Config {
+    version: usize,
+    double: usize,
+}
+
+impl Config {
+    fn new(version: usize,) -> Arc<Config,> {
+        LIVE.fetch_add(1, Relaxed,);
+        Arc::new(Config { version, double: version * 2, },)
+    }
+}
+
+impl Drop for Config {
+    fn drop(&mut self,) {
+        assert_eq!(self.double, self.version * 2);
+        LIVE.fetch_sub(1, Relaxed,);
+    }
+}
+
+#[test]
+fn atomic_arc_stress() {
+    let config = AtomicArc::new(Config::new(0,),);
+    let stop = AtomicBool::new(false,);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                while !stop.load(Relaxed,) {
+                    let c = config.load();
+                    assert_eq!(c.double, c.version * 2);
+                }
+            },);
+        }
+        let writers: Vec<_,> = (0..2)
+            .map(|w| {
+                let config = &config;
+                s.spawn(move || {
+                    for i in 1..=ROUNDS {
+                        if i % 2 == 0 {
+                            config.store(Config::new(w * 10_000 + i,),);
+                        } else {
+                            let old = config.swap(Config::new(w * 10_000 + i,),);
+                            assert_eq!(old.double, old.version * 2);
+                        }
+                    }
+                },)
+            },)
+            .collect();
+        for w in writers {
+            w.join().must();
+        }
+        stop.store(true, Relaxed,);
+    },);
+    drop(config,);
+    assert_eq!(LIVE.load(Relaxed), 0);
+}
+
+#[test]
+fn atomic_arc_rcu_and_compare_and_swap() {
+    let counter = AtomicArc::new(Arc::new(0,),);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..500 {
+                    counter.rcu(|n| n + 1,);
+                }
+            },);
+        }
+    },);
+    assert_eq!(*counter.load(), 2000);
+
+    let current = counter.load();
+    let stale = Arc::new(2000,);
+    let rejected = counter.compare_and_swap(&stale, Arc::new(-1,),);
+    assert!(matches!(rejected, Err(new) if *new == -1));
+    let old = counter.compare_and_swap(&current, Arc::new(1,),).must();
+    assert!(Arc::ptr_eq(&old, &current));
+    assert_eq!(*counter.into_inner(), 1);
+}
@@ -0,0 +1,97 @@
+pub mod must;
+use atomics_locks::barrier::{Barrier, BarrierError};
+use must::Must;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread;
+use std::time::Duration;
+
+/// Generations per test, fewer under miri.
+const GENERATIONS: u64 = if cfg!(miri) { 10 } else { 1_000 };
+
+#[test]
+fn one_leader_per_generation() {
+    let barrier = Barrier::new(4,);
+    let slots: [AtomicU64; 4] = Default::default();
+    let leaders = AtomicU64::new(0,);
+    thread::scope(|s| {
+        for (t, slot,) in slots.iter().enumerate() {
+            let (barrier, slots, leaders,) = (&barrier, &slots, &leaders,);
+            s.spawn(move || {
+                for generation in 1..=GENERATIONS {
+                    slot.store(generation, Relaxed,);
+                    if barrier.wait().must().is_leader() {
+                        leaders.fetch_add(1, Relaxed,);
+                    }
+                    // Everyone's write from this generation is visible past the barrier.
+                    for other in slots {
+                        assert!(other.load(Relaxed,) >= generation, "thread {t} ran ahead");
+                    }
+                    // And nobody starts the next one before everyone has looked.
+                    barrier.wait().must();
+                }
+            },);
+        }
+    },);
+    assert_eq!(leaders.load(Relaxed,), GENERATIONS);
+    assert!(!barrier.is_broken());
+}
+
+#[test]
+fn single_thread_barrier_always_leads() {
+    for n in [0, 1,] {
+        let barrier = Barrier::new(n,);
+        assert!(barrier.wait().must().is_leader());
+        assert!(barrier.wait_timeout(Duration::ZERO,).must().is_leader());
+    }
+}
+
+#[test]
+fn timeout_breaks_the_barrier() {
+    let barrier = Barrier::new(3,);
+    thread::scope(|s| {
+        let waiter = s.spawn(|| barrier.wait(),);
+        assert_eq!(barrier.wait_timeout(Duration::from_millis(50,),), Err(BarrierError::TimedOut));
+        assert_eq!(waiter.join().must(), Err(BarrierError::Broken));
+    },);
+    assert!(barrier.is_broken());
+    assert_eq!(barrier.wait(), Err(BarrierError::Broken));
+}
+
+#[test]
+fn wait_timeout_opens_like_wait() {
+    let barrier = Barrier::new(2,);
+    thread::scope(|s| {
+        let other = s.spawn(|| barrier.wait_timeout(Duration::from_secs(10,),),);
+        let here = barrier.wait_timeout(Duration::from_secs(10,),).must();
+        let there = other.join().must().must();
+        assert_ne!(here.is_leader(), there.is_leader());
+    },);
+}
+
+#[test]
+fn panicking_participant_breaks_the_barrier() {
+    let barrier = Barrier::new(3,);
+    thread::scope(|s| {
+        let waiters: Vec<_,> = (0..2).map(|_| s.spawn(|| barrier.wait(),),).collect();
+        let panicked = s
+            .spawn(|| {
+                let _guard = barrier.break_on_panic();
+                panic!("participant failed before reaching the barrier");
+            },)
+            .join();
+        assert!(panicked.is_err());
+        for waiter in waiters {
+            assert_eq!(waiter.join().must(), Err(BarrierError::Broken));
+        }
+    },);
+    assert_eq!(barrier.wait(), Err(BarrierError::Broken));
+}
+
+#[test]
+fn guard_dropped_normally_leaves_the_barrier_alone() {
+    let barrier = Barrier::new(1,);
+    drop(barrier.break_on_panic(),);
+    assert!(!barrier.is_broken());
+    assert!(barrier.wait().is_ok());
+}
@@ -0,0 +1,82 @@
+pub mod must;
+use atomics_locks::biased_arc::{BiasedArc, SharedBiasedArc};
+use must::Must;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread;
+
+/// Clones per thread, fewer under miri.
+const CLONES: usize = if cfg!(miri) { 20 } else { 10_000 };
+
+struct DetectDrop<'a,>(&'a AtomicUsize,);
+
+impl Drop for DetectDrop<'_,> {
+    fn drop(&mut self,) {
+        self.0.fetch_add(1, Relaxed,);
+    }
+}
+
+#[test]
+fn biased_arc_owner() {
+    let drops = AtomicUsize::new(0,);
+    let a = BiasedArc::new(DetectDrop(&drops,),);
+    assert!(BiasedArc::is_biased(&a));
+    let clones: Vec<_,> = (0..10).map(|_| a.clone(),).collect();
+    assert!(clones.iter().all(|c| BiasedArc::is_biased(c) && BiasedArc::ptr_eq(c, &a)));
+    drop(clones,);
+    assert_eq!(drops.load(Relaxed), 0);
+    drop(a,);
+    assert_eq!(drops.load(Relaxed), 1);
+}
+
+#[test]
+fn biased_arc_across_threads() {
+    let drops = AtomicUsize::new(0,);
+    let a = BiasedArc::new(DetectDrop(&drops,),);
+    let shared = BiasedArc::to_shared(&a,);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            let shared = shared.clone();
+            s.spawn(move || {
+                let local = SharedBiasedArc::into_local(shared,);
+                assert!(!BiasedArc::is_biased(&local));
+                for _ in 0..CLONES {
+                    drop(local.clone(),);
+                }
+            },);
+        }
+        // The owner keeps cloning its own handles meanwhile.
+        for _ in 0..CLONES {
+            drop(a.clone(),);
+        }
+    },);
+    drop(shared,);
+    assert_eq!(drops.load(Relaxed), 0);
+    drop(a,);
+    assert_eq!(drops.load(Relaxed), 1);
+}
+
+#[test]
+fn biased_arc_outlives_owner_handles() {
+    let drops = AtomicUsize::new(0,);
+    let a = BiasedArc::new(DetectDrop(&drops,),);
+    let shared = SharedBiasedArc::from(a,);
+    // The owner has no handle left, the other thread frees the data.
+    thread::scope(|s| {
+        s.spawn(move || drop(SharedBiasedArc::into_local(shared,),),).join().must();
+    },);
+    assert_eq!(drops.load(Relaxed), 1);
+
+    // Back on the owner thread, a shared handle becomes a biased one again, even after the
+    // owner's own handles are all gone.
+    let a = BiasedArc::new(DetectDrop(&drops,),);
+    let shared = BiasedArc::to_shared(&a,);
+    drop(a,);
+    let a = SharedBiasedArc::into_local(shared,);
+    assert!(BiasedArc::is_biased(&a));
+    let b = a.clone();
+    drop(a,);
+    assert_eq!(drops.load(Relaxed), 1);
+    drop(b,);
+    assert_eq!(drops.load(Relaxed), 2);
+}
@@ -1,6 +1,11 @@
+pub mod common;
+pub mod must;
+use std::sync::atomic::Ordering::Relaxed;
 use std::thread;
 
-use atomics_locks::one_shot_channel::{typed_channel, unsafe_channel};
+use atomics_locks::one_shot_channel::{arc_channel, typed_channel, unsafe_channel};
+use common::Counting;
+use must::Must;
 
 #[test]
 fn unsafe_channel() {
@@ -68,3 +73,37 @@ fn typed_channel() {
         assert_eq!(receiver.receive(), "hello world");
     },)
 }
+
+#[test]
+fn arc_channel() {
+    let (tx, rx,) = arc_channel::channel();
+    let t = thread::spawn(move || tx.send(String::from("hello world",),),);
+    t.join().must();
+    assert!(rx.is_ready());
+    assert_eq!(rx.receive(), "hello world");
+
+    let alloc = Counting::default();
+    let (tx, rx,) = arc_channel::channel_in(&alloc,);
+    assert_eq!(alloc.live.load(Relaxed), 1);
+    thread::scope(|s| {
+        s.spawn(move || tx.send(5,),);
+        while !rx.is_ready() {
+            thread::yield_now();
+        }
+        assert_eq!(rx.receive(), 5);
+    },);
+    assert_eq!(alloc.live.load(Relaxed), 0);
+
+    // An unreceived message is dropped with the channel.
+    let (tx, rx,) = arc_channel::channel_in(&alloc,);
+    tx.send(vec![1],);
+    drop(rx,);
+    assert_eq!(alloc.live.load(Relaxed), 0);
+}
+
+#[test]
+#[should_panic(expected = "no message available")]
+fn arc_channel_receive_before_send_panics() {
+    let (_tx, rx,) = arc_channel::channel::<u8,>();
+    rx.receive();
+}
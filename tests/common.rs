@@ -1 +1,25 @@
+use atomics_locks::allocator::{AllocError, Allocator, Global};
+use std::alloc::Layout;
+use std::ptr::NonNull;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
 
+/// An allocator that counts the blocks it has out, on top of the global one.
+#[derive(Debug, Default,)]
+pub struct Counting {
+    pub live: AtomicUsize,
+}
+
+// SAFETY: forwards to Global.
+unsafe impl Allocator for Counting {
+    fn allocate(&self, layout: Layout,) -> Result<NonNull<u8,>, AllocError,> {
+        self.live.fetch_add(1, Relaxed,);
+        Global.allocate(layout,)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8,>, layout: Layout,) {
+        self.live.fetch_sub(1, Relaxed,);
+        // SAFETY: allocated by Global with this layout.
+        unsafe { Global.deallocate(ptr, layout,) }
+    }
+}
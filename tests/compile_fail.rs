@@ -0,0 +1,7 @@
+// The misuse the lock APIs rule out. Each file in `tests/ui` must fail to compile with the error
+// next to it.
+#[test]
+#[cfg_attr(miri, ignore = "runs rustc")]
+fn misuse_does_not_compile() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs",);
+}
@@ -0,0 +1,55 @@
+use std::thread;
+use std::time::Duration;
+
+use atomics_locks::condvar::CondVar;
+use atomics_locks::mutex::Mutex;
+
+#[test]
+#[should_panic(expected = "different mutex")]
+fn condvar_with_two_mutexes_panics() {
+    let c = CondVar::new();
+    let a = Mutex::new((),);
+    let b = Mutex::new((),);
+    let (guard, _,) = c.wait_timeout(a.lock(), Duration::from_millis(1,),);
+    drop(guard,);
+    let _ = c.wait_timeout(b.lock(), Duration::from_millis(1,),);
+}
+
+#[test]
+fn condvar_reused_with_same_mutex() {
+    let c = CondVar::new();
+    let m = Mutex::new(0,);
+    for _ in 0..3 {
+        let (mut guard, result,) = c.wait_timeout(m.lock(), Duration::from_millis(1,),);
+        assert!(result.timed_out());
+        *guard += 1;
+    }
+    assert_eq!(*m.lock(), 3);
+}
+
+#[test]
+#[should_panic(expected = "different mutex")]
+fn bound_guard_of_another_bound_condvar_panics() {
+    let a = Mutex::new((),);
+    let b = Mutex::new((),);
+    let (ca, cb,) = (CondVar::for_mutex(&a,), CondVar::for_mutex(&b,),);
+    let _ = cb.wait_timeout(ca.lock(), Duration::from_millis(1,),);
+}
+
+#[test]
+fn bound_condvar() {
+    let m = Mutex::new(false,);
+    let c = CondVar::for_mutex(&m,);
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(50,),);
+            *c.lock() = true;
+            c.notify_all();
+        },);
+        let mut ready = c.lock();
+        while !*ready {
+            ready = c.wait(ready,);
+        }
+    },);
+    assert!(std::ptr::eq(c.mutex(), &m));
+}
@@ -0,0 +1,145 @@
+use atomics_locks::epoch::{self, Atomic, Owned, Shared};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+use std::sync::mpsc;
+use std::thread;
+
+/// Operations per thread, fewer under miri.
+const OPS: usize = if cfg!(miri) { 100 } else { 20_000 };
+
+static LIVE: AtomicUsize = AtomicUsize::new(0,);
+
+/// Overwritten when dropped, so reading a destroyed node shows.
+struct Node {
+    value: usize,
+    check: usize,
+    next: Atomic<Node,>,
+}
+
+impl Drop for Node {
+    fn drop(&mut self,) {
+        self.check = 0;
+        LIVE.fetch_sub(1, Relaxed,);
+    }
+}
+
+struct Stack {
+    head: Atomic<Node,>,
+}
+
+impl Stack {
+    fn push(&self, value: usize,) {
+        LIVE.fetch_add(1, Relaxed,);
+        let mut node = Owned::new(Node { value, check: !value, next: Atomic::null(), },);
+        let guard = epoch::pin();
+        loop {
+            let head = self.head.load(Relaxed, &guard,);
+            node.next.store(head, Relaxed,);
+            match self.head.compare_exchange(head, node, Release, Relaxed, &guard,) {
+                Ok(_,) => return,
+                Err(e,) => node = e.new,
+            }
+        }
+    }
+
+    fn pop(&self,) -> Option<usize,> {
+        let guard = epoch::pin();
+        loop {
+            let head = self.head.load(Acquire, &guard,);
+            // SAFETY: nodes are only destroyed through `defer_destroy`.
+            let node = unsafe { head.as_ref() }?;
+            thread::yield_now();
+            assert_eq!(node.check, !node.value, "read a destroyed node");
+            let next = node.next.load(Relaxed, &guard,);
+            if self.head.compare_exchange(head, next, Acquire, Relaxed, &guard,).is_ok() {
+                // SAFETY: unlinked by us, and from an `Owned`.
+                unsafe { guard.defer_destroy(head,) };
+                return Some(node.value,);
+            }
+        }
+    }
+}
+
+/// Flushes until every node is gone, the epoch needs a couple of steps and other tests may be
+/// pinned meanwhile.
+fn flush_until_none_live() {
+    for _ in 0..10_000 {
+        epoch::pin().flush();
+        if LIVE.load(Relaxed,) == 0 {
+            return;
+        }
+        thread::yield_now();
+    }
+}
+
+#[test]
+fn epoch_treiber_stack() {
+    let stack = Stack { head: Atomic::null(), };
+    let popped = AtomicUsize::new(0,);
+    thread::scope(|s| {
+        for t in 0..4 {
+            let (stack, popped,) = (&stack, &popped,);
+            s.spawn(move || {
+                for i in 0..OPS {
+                    stack.push(t * OPS + i,);
+                    if stack.pop().is_some() {
+                        popped.fetch_add(1, Relaxed,);
+                    }
+                }
+            },);
+        }
+    },);
+    while stack.pop().is_some() {
+        popped.fetch_add(1, Relaxed,);
+    }
+    assert_eq!(popped.into_inner(), 4 * OPS);
+    // What the exited threads couldn't destroy was handed over, and gets collected here.
+    flush_until_none_live();
+    assert_eq!(LIVE.load(Relaxed), 0);
+}
+
+#[test]
+fn epoch_pinned_thread_holds_back_destruction() {
+    static DROPS: AtomicUsize = AtomicUsize::new(0,);
+
+    struct CountDrop;
+
+    impl Drop for CountDrop {
+        fn drop(&mut self,) {
+            DROPS.fetch_add(1, Relaxed,);
+        }
+    }
+
+    let slot = Atomic::new(CountDrop,);
+    let (pinned_tx, pinned_rx,) = mpsc::channel();
+    let (done_tx, done_rx,) = mpsc::channel::<(),>();
+    thread::scope(|s| {
+        let slot = &slot;
+        s.spawn(move || {
+            let guard = epoch::pin();
+            let _held = slot.load(Acquire, &guard,);
+            pinned_tx.send((),).ok();
+            done_rx.recv().ok();
+        },);
+        pinned_rx.recv().ok();
+
+        let guard = epoch::pin();
+        let old = slot.swap(Shared::null(), AcqRel, &guard,);
+        // SAFETY: unlinked above, and from an `Owned`.
+        unsafe { guard.defer_destroy(old,) };
+        drop(guard,);
+        for _ in 0..100 {
+            epoch::pin().flush();
+        }
+        assert_eq!(DROPS.load(Relaxed), 0);
+        done_tx.send((),).ok();
+    },);
+    for _ in 0..10_000 {
+        epoch::pin().flush();
+        if DROPS.load(Relaxed,) == 1 {
+            break;
+        }
+        thread::yield_now();
+    }
+    assert_eq!(DROPS.load(Relaxed), 1);
+}
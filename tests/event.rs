@@ -0,0 +1,151 @@
+pub mod must;
+use atomics_locks::event::{AutoResetEvent, ManualResetEvent};
+use must::Must;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::thread;
+use std::time::Duration;
+
+/// Rounds of the race tests, fewer under miri.
+const ROUNDS: u32 = if cfg!(miri) { 20 } else { 2_000 };
+
+#[test]
+fn manual_reset_lets_everyone_through() {
+    let event = ManualResetEvent::new(false,);
+    thread::scope(|s| {
+        let waiters: Vec<_,> = (0..8).map(|_| s.spawn(|| event.wait(),),).collect();
+        event.set();
+        for waiter in waiters {
+            waiter.join().must();
+        }
+    },);
+    // It stays set until reset.
+    assert!(event.is_set());
+    event.wait();
+    assert!(event.wait_timeout(Duration::ZERO,));
+    event.reset();
+    assert!(!event.wait_timeout(Duration::from_millis(10,),));
+    assert_eq!(format!("{event:?}"), "ManualResetEvent { set: false }");
+}
+
+#[test]
+fn manual_reset_set_and_reset_race() {
+    // Every round the waiter either sees the set or is woken by it: no round hangs.
+    let event = ManualResetEvent::default();
+    let (woken, reset,) = (AtomicU32::new(0,), AtomicU32::new(0,),);
+    thread::scope(|s| {
+        s.spawn(|| {
+            for r in 1..=ROUNDS {
+                event.wait();
+                woken.store(r, Relaxed,);
+                while reset.load(Relaxed,) < r {
+                    thread::yield_now();
+                }
+            }
+        },);
+        for r in 1..=ROUNDS {
+            event.set();
+            while woken.load(Relaxed,) < r {
+                thread::yield_now();
+            }
+            event.reset();
+            reset.store(r, Relaxed,);
+        }
+    },);
+}
+
+#[test]
+fn manual_reset_wait_timeout_sees_a_late_set() {
+    let event = ManualResetEvent::new(false,);
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(10,),);
+            event.set();
+        },);
+        assert!(event.wait_timeout(Duration::from_secs(10,),));
+    },);
+}
+
+#[test]
+fn auto_reset_lets_one_through_per_set() {
+    let event = AutoResetEvent::new(false,);
+    let through = AtomicU32::new(0,);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                event.wait();
+                // Release: the set taken in `wait` is visible to whoever sees this.
+                through.fetch_add(1, Release,);
+            },);
+        }
+        for n in 1..=4 {
+            event.set();
+            while through.load(Acquire,) < n {
+                thread::yield_now();
+            }
+            // The set was taken, and nobody else got through on it.
+            thread::sleep(Duration::from_millis(if cfg!(miri) { 1 } else { 10 },),);
+            assert_eq!(through.load(Relaxed,), n);
+            assert!(!event.is_set());
+        }
+    },);
+}
+
+#[test]
+fn auto_reset_stays_set_until_taken() {
+    let event = AutoResetEvent::new(true,);
+    // Setting it again doesn't count twice.
+    event.set();
+    assert_eq!(format!("{event:?}"), "AutoResetEvent { set: true }");
+    assert!(event.wait_timeout(Duration::ZERO,));
+    assert!(!event.is_set());
+    assert!(!event.wait_timeout(Duration::from_millis(10,),));
+    event.set();
+    event.reset();
+    assert!(!event.wait_timeout(Duration::ZERO,));
+}
+
+#[test]
+fn auto_reset_no_lost_wakeups() {
+    // Ping-pong: each side sets the other's event and waits on its own, so a single lost wakeup
+    // hangs the test.
+    let (ping, pong,) = (AutoResetEvent::default(), AutoResetEvent::default(),);
+    thread::scope(|s| {
+        s.spawn(|| {
+            for _ in 0..ROUNDS {
+                ping.wait();
+                pong.set();
+            }
+        },);
+        for _ in 0..ROUNDS {
+            ping.set();
+            pong.wait();
+        }
+    },);
+    assert!(!ping.is_set() && !pong.is_set());
+}
+
+#[test]
+fn auto_reset_timed_out_waiters_dont_swallow_sets() {
+    // Waiters keep timing out while sets come in: every set is still taken by someone.
+    let event = AutoResetEvent::new(false,);
+    let taken = AtomicU32::new(0,);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                while taken.load(Relaxed,) < ROUNDS {
+                    if event.wait_timeout(Duration::from_micros(50,),) {
+                        taken.fetch_add(1, Relaxed,);
+                    }
+                }
+            },);
+        }
+        for n in 1..=ROUNDS {
+            event.set();
+            while taken.load(Relaxed,) < n {
+                thread::yield_now();
+            }
+        }
+    },);
+    assert_eq!(taken.load(Relaxed,), ROUNDS);
+}
@@ -0,0 +1,184 @@
+pub mod must;
+use atomics_locks::epoch;
+use atomics_locks::hash_map::{ConcurrentHashMap, Entry, LockFreeHashMap};
+use must::Must;
+use std::sync::Arc;
+use std::thread;
+
+/// Keys per thread, fewer under miri.
+const KEYS: usize = if cfg!(miri) { 50 } else { 5_000 };
+
+#[test]
+fn sharded_map_basics() {
+    let map = ConcurrentHashMap::with_shards(3,);
+    assert_eq!(map.shards().count(), 4);
+    assert!(map.is_empty());
+    assert_eq!(map.insert("a", 1,), None);
+    assert_eq!(map.insert("b", 2,), None);
+    assert_eq!(map.insert("a", 3,), Some(1));
+    assert_eq!(*map.get("a",).must(), 3);
+    assert_eq!(*map.get("a",).must().key(), "a");
+    *map.get_mut("b",).must() += 10;
+    assert_eq!(*map.get("b",).must(), 12);
+    assert!(map.get("c",).is_none() && !map.contains_key("c",));
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.remove("a",), Some(3));
+    assert_eq!(map.remove("a",), None);
+    map.retain(|_, v| *v != 12,);
+    assert!(map.is_empty());
+}
+
+#[test]
+fn sharded_map_entries() {
+    let map = ConcurrentHashMap::new();
+    *map.entry("a",).or_insert(1,) += 1;
+    *map.entry("a",).or_insert(10,) += 1;
+    map.entry("b",).and_modify(|v| *v = 0,).or_default();
+    assert_eq!(*map.get("a",).must(), 3);
+    assert_eq!(*map.get("b",).must(), 0);
+    match map.entry("a",) {
+        Entry::Occupied(mut e,) => {
+            assert_eq!(e.insert(5,), 3);
+            assert_eq!(e.remove(), 5);
+        }
+        Entry::Vacant(_,) => panic!("a is in the map"),
+    }
+    match map.entry("a",) {
+        Entry::Occupied(_,) => panic!("a was removed"),
+        Entry::Vacant(e,) => assert_eq!(e.into_key(), "a"),
+    }
+    // Every entry shows up in exactly one shard.
+    let mut keys: Vec<_,> =
+        map.shards().flat_map(|s| s.keys().copied().collect::<Vec<_,>>(),).collect();
+    keys.sort_unstable();
+    assert_eq!(keys, ["b"]);
+    for mut shard in map.shards_mut() {
+        shard.clear();
+    }
+    assert!(map.is_empty());
+}
+
+#[test]
+fn sharded_map_counts_from_many_threads() {
+    let map = ConcurrentHashMap::with_shards(8,);
+    thread::scope(|s| {
+        for t in 0..4 {
+            let map = &map;
+            s.spawn(move || {
+                for i in 0..KEYS {
+                    // Shared keys, counted by everyone, and keys of our own.
+                    *map.entry(i % 16,).or_insert(0,) += 1;
+                    map.insert(1_000 + t * KEYS + i, t,);
+                    assert_eq!(map.get(&(1_000 + t * KEYS + i),).as_deref(), Some(&t));
+                }
+            },);
+        }
+    },);
+    let counted: usize = (0..16).map(|k| *map.get(&k,).must(),).sum();
+    assert_eq!(counted, 4 * KEYS);
+    assert_eq!(map.len(), 16 + 4 * KEYS);
+}
+
+#[test]
+fn lock_free_map_basics() {
+    let map = LockFreeHashMap::new();
+    assert!(map.is_empty());
+    assert_eq!(map.insert("a".to_string(), 1,), None);
+    assert_eq!(map.insert("a".to_string(), 2,), Some(1));
+    assert_eq!(*map.get("a",).must(), 2);
+    assert_eq!(map.get("a",).must().key(), "a");
+    assert!(map.get("b",).is_none());
+    assert_eq!(map.remove("a",), Some(2));
+    assert!(map.remove("a",).is_none() && !map.contains_key("a",));
+    // Back in the slot it had.
+    assert_eq!(map.insert("a".to_string(), 3,), None);
+    assert_eq!(map.len(), 1);
+}
+
+#[test]
+fn lock_free_map_grows_and_drops_removed_keys() {
+    let map = LockFreeHashMap::with_capacity(4,);
+    for i in 0..1_000 {
+        assert_eq!(map.insert(i, i * 2,), None);
+        if i % 2 == 1 {
+            assert_eq!(map.remove(&(i - 1),), Some((i - 1) * 2));
+        }
+    }
+    assert_eq!(map.len(), 500);
+    for i in 0..1_000 {
+        assert_eq!(map.get(&i,).map(|v| *v,), (i % 2 == 1).then_some(i * 2,));
+    }
+}
+
+#[test]
+fn lock_free_map_from_many_threads() {
+    let map = LockFreeHashMap::new();
+    thread::scope(|s| {
+        for t in 0..4 {
+            let map = &map;
+            s.spawn(move || {
+                for i in 0..KEYS {
+                    let key = t * KEYS + i;
+                    assert_eq!(map.insert(key, key * 2,), None);
+                    // Someone else's key, in whatever state it is.
+                    let other = ((t + 1) % 4) * KEYS + i;
+                    if let Some(v,) = map.get(&other,) {
+                        assert_eq!(*v, other * 2);
+                    }
+                    if i % 3 == 0 {
+                        assert_eq!(map.remove(&key,), Some(key * 2));
+                    }
+                }
+            },);
+        }
+    },);
+    let expected = (0..4 * KEYS).filter(|k| k % KEYS % 3 != 0,).count();
+    assert_eq!(map.len(), expected);
+    for key in 0..4 * KEYS {
+        assert_eq!(map.get(&key,).map(|v| *v,), (key % KEYS % 3 != 0).then_some(key * 2,));
+    }
+}
+
+#[test]
+fn lock_free_map_writes_survive_resizes() {
+    // Few live keys and many fresh ones, so the table is replaced every dozen inserts or so.
+    let map = LockFreeHashMap::new();
+    thread::scope(|s| {
+        for t in 0..4 {
+            let map = &map;
+            s.spawn(move || {
+                for i in 0..KEYS {
+                    let key = t * KEYS + i;
+                    assert_eq!(map.insert(key, i,), None);
+                    assert_eq!(map.get(&key,).as_deref(), Some(&i));
+                    assert_eq!(map.remove(&key,), Some(i), "lost {key}");
+                }
+            },);
+        }
+    },);
+    assert!(map.is_empty());
+}
+
+#[test]
+fn lock_free_map_frees_every_value() {
+    // Keys and values both hold a count, keys dropped on resize included.
+    let value = Arc::new((),);
+    let map = LockFreeHashMap::new();
+    for i in 0..100 {
+        map.insert((i % 40, value.clone(),), value.clone(),);
+        if i % 3 == 0 {
+            map.remove(&(i % 40, value.clone(),),);
+        }
+    }
+    drop(map,);
+    // Replaced and removed ones go through the epoch, which takes a couple of steps, and other
+    // tests may be pinned meanwhile.
+    for _ in 0..10_000 {
+        epoch::pin().flush();
+        if Arc::strong_count(&value,) == 1 {
+            break;
+        }
+        thread::yield_now();
+    }
+    assert_eq!(Arc::strong_count(&value), 1);
+}
@@ -0,0 +1,141 @@
+use atomics_locks::hazard::{self, HazardPointer};
+use std::mem::ManuallyDrop;
+use std::ptr;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicPtr, AtomicUsize};
+use std::thread;
+
+/// Operations per thread, fewer under miri.
+const OPS: usize = if cfg!(miri) { 100 } else { 20_000 };
+
+/// Written over a node's fields when it's freed, reading it back means a use after free.
+const FREED: usize = 0xDEAD;
+
+struct Node {
+    value: ManuallyDrop<usize,>,
+    check: usize,
+    next: *mut Node,
+}
+
+/// Freed nodes are overwritten first, and counted.
+unsafe fn free_node(node: *mut Node,) {
+    static_counters().fetch_sub(1, Relaxed,);
+    // SAFETY: retired nodes come from `Box::into_raw` and are freed once.
+    let mut node = unsafe { Box::from_raw(node,) };
+    node.value = ManuallyDrop::new(FREED,);
+    node.check = FREED;
+}
+
+fn static_counters() -> &'static AtomicUsize {
+    static LIVE: AtomicUsize = AtomicUsize::new(0,);
+    &LIVE
+}
+
+struct Stack {
+    head: AtomicPtr<Node,>,
+}
+
+impl Stack {
+    fn push(&self, value: usize,) {
+        static_counters().fetch_add(1, Relaxed,);
+        let node = Box::into_raw(Box::new(Node {
+            value: ManuallyDrop::new(value,),
+            check: !value,
+            next: ptr::null_mut(),
+        },),);
+        let mut head = self.head.load(Relaxed,);
+        loop {
+            // SAFETY: not published yet.
+            unsafe { (*node).next = head };
+            match self.head.compare_exchange_weak(head, node, Release, Relaxed,) {
+                Ok(_,) => return,
+                Err(now,) => head = now,
+            }
+        }
+    }
+
+    fn pop(&self, hazard: &mut HazardPointer,) -> Option<usize,> {
+        loop {
+            let head = hazard.protect(&self.head,);
+            if head.is_null() {
+                return None;
+            }
+            // Give the other threads a chance to pop and retire it while we hold it.
+            thread::yield_now();
+            // SAFETY: protected.
+            let (next, value, check,) = unsafe { ((*head).next, *(*head).value, (*head).check,) };
+            assert_eq!(check, !value, "read a freed node");
+            if self.head.compare_exchange(head, next, Acquire, Relaxed,).is_ok() {
+                // SAFETY: unlinked by us, and from a `Box`.
+                unsafe { hazard::retire(head, free_node,) };
+                hazard.reset();
+                return Some(value,);
+            }
+        }
+    }
+}
+
+#[test]
+fn hazard_treiber_stack() {
+    let stack = Stack { head: AtomicPtr::new(ptr::null_mut(),), };
+    let popped = AtomicUsize::new(0,);
+    thread::scope(|s| {
+        for t in 0..4 {
+            let (stack, popped,) = (&stack, &popped,);
+            s.spawn(move || {
+                let mut hazard = HazardPointer::new();
+                for i in 0..OPS {
+                    stack.push(t * OPS + i,);
+                    if stack.pop(&mut hazard,).is_some() {
+                        popped.fetch_add(1, Relaxed,);
+                    }
+                }
+            },);
+        }
+    },);
+    let mut hazard = HazardPointer::new();
+    while stack.pop(&mut hazard,).is_some() {
+        popped.fetch_add(1, Relaxed,);
+    }
+    drop(hazard,);
+    assert_eq!(popped.into_inner(), 4 * OPS);
+    // The other threads are gone, what they couldn't free is orphaned, and a scan picks it up.
+    // Other tests scan too, and might be holding some of it for a moment.
+    for _ in 0..1000 {
+        hazard::scan();
+        if static_counters().load(Relaxed,) == 0 {
+            break;
+        }
+        thread::yield_now();
+    }
+    assert_eq!(static_counters().load(Relaxed), 0);
+}
+
+#[test]
+fn hazard_protects_until_reset() {
+    static DROPS: AtomicUsize = AtomicUsize::new(0,);
+
+    unsafe fn count_drop(ptr: *mut u64,) {
+        DROPS.fetch_add(1, Relaxed,);
+        // SAFETY: from `Box::into_raw` below.
+        unsafe { hazard::drop_box(ptr,) };
+    }
+
+    let slot = AtomicPtr::new(Box::into_raw(Box::new(7u64,),),);
+    let mut hazard = HazardPointer::new();
+    let ptr = hazard.protect(&slot,);
+    slot.store(ptr::null_mut(), Release,);
+    // SAFETY: unlinked above, and from a `Box`.
+    unsafe { hazard::retire(ptr, count_drop,) };
+
+    // Another thread scanning can't free it either.
+    thread::spawn(hazard::scan,).join().ok();
+    hazard::scan();
+    assert_eq!(DROPS.load(Relaxed), 0);
+    // SAFETY: still protected.
+    assert_eq!(unsafe { *ptr }, 7);
+
+    hazard.reset();
+    hazard::scan();
+    assert_eq!(DROPS.load(Relaxed), 1);
+}
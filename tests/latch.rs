@@ -0,0 +1,108 @@
+pub mod must;
+use atomics_locks::latch::{Latch, WaitGroup};
+use must::Must;
+use std::panic;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn latch_opens_after_every_count_down() {
+    let latch = Latch::new(4,);
+    let slots: [AtomicU32; 4] = Default::default();
+    thread::scope(|s| {
+        for (t, slot,) in slots.iter().enumerate() {
+            let latch = &latch;
+            s.spawn(move || {
+                slot.store(t as u32 + 1, Relaxed,);
+                latch.count_down();
+            },);
+        }
+        latch.wait();
+        // Everything before the count downs is visible past the wait.
+        for (t, slot,) in slots.iter().enumerate() {
+            assert_eq!(slot.load(Relaxed,), t as u32 + 1);
+        }
+    },);
+    assert_eq!(latch.count(), 0);
+    latch.count_down();
+    assert_eq!(latch.count(), 0);
+    latch.wait();
+}
+
+#[test]
+fn latch_wakes_every_waiter() {
+    let latch = Latch::new(1,);
+    thread::scope(|s| {
+        let waiters: Vec<_,> = (0..8).map(|_| s.spawn(|| latch.wait(),),).collect();
+        thread::sleep(Duration::from_millis(10,),);
+        latch.count_down();
+        for waiter in waiters {
+            waiter.join().must();
+        }
+    },);
+}
+
+#[test]
+fn latch_wait_timeout() {
+    assert!(Latch::new(0,).wait_timeout(Duration::ZERO,));
+    let latch = Latch::new(2,);
+    latch.count_down();
+    assert!(!latch.wait_timeout(Duration::from_millis(10,),));
+    assert_eq!(latch.count(), 1);
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(10,),);
+            latch.count_down();
+        },);
+        assert!(latch.wait_timeout(Duration::from_secs(10,),));
+    },);
+    assert_eq!(format!("{latch:?}"), "Latch { count: 0 }");
+}
+
+#[test]
+fn wait_group_waits_for_every_handle() {
+    let group = WaitGroup::new();
+    let finished = AtomicU32::new(0,);
+    thread::scope(|s| {
+        let handle = group.handle();
+        for _ in 0..8 {
+            let (handle, finished,) = (handle.clone(), &finished,);
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(5,),);
+                finished.fetch_add(1, Relaxed,);
+                drop(handle,);
+            },);
+        }
+        handle.done();
+        group.wait();
+        assert_eq!(finished.load(Relaxed,), 8);
+    },);
+    assert_eq!(group.count(), 0);
+}
+
+#[test]
+fn wait_group_is_reusable() {
+    let group = WaitGroup::default();
+    group.wait();
+    for round in 0..3 {
+        group.add(2,);
+        assert_eq!(format!("{group:?}"), "WaitGroup { count: 2 }");
+        thread::scope(|s| {
+            s.spawn(|| group.done(),);
+            s.spawn(|| group.done(),);
+            group.wait();
+        },);
+        assert_eq!(group.count(), 0, "round {round}");
+    }
+}
+
+#[test]
+fn wait_group_counts_must_balance() {
+    let group = WaitGroup::new();
+    assert!(panic::catch_unwind(|| group.done()).is_err());
+    group.add(u32::MAX,);
+    assert!(panic::catch_unwind(|| group.add(1,)).is_err());
+    assert_eq!(group.count(), u32::MAX);
+}
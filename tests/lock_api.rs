@@ -0,0 +1,176 @@
+pub mod must;
+use atomics_locks::lock_api::{self, RawMutex, RwLockUpgradableReadGuard};
+use atomics_locks::mutex::Mutex;
+use atomics_locks::rwlock::RwLock;
+use atomics_locks::spinlock::SpinLock;
+use must::Must;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+use std::sync::atomic::{AtomicBool, AtomicU32};
+use std::thread;
+use std::time::Duration;
+
+/// Increments per thread, fewer under miri.
+const OPS: u32 = if cfg!(miri) { 100 } else { 100_000 };
+
+/// A test-and-set lock that counts its unlocks, built outside the crate.
+struct RawTas {
+    locked: AtomicBool,
+    unlocks: AtomicU32,
+}
+
+// SAFETY: the CAS sets the flag for one thread at a time, Acquire pairs with the Release store.
+unsafe impl RawMutex for RawTas {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = RawTas { locked: AtomicBool::new(false,), unlocks: AtomicU32::new(0,), };
+
+    fn lock(&self,) {
+        while !self.try_lock() {
+            thread::yield_now();
+        }
+    }
+
+    fn try_lock(&self,) -> bool {
+        self.locked.compare_exchange(false, true, Acquire, Relaxed,).is_ok()
+    }
+
+    unsafe fn unlock(&self,) {
+        self.unlocks.fetch_add(1, Relaxed,);
+        self.locked.store(false, Release,);
+    }
+}
+
+type TasMutex<T,> = lock_api::Mutex<RawTas, T,>;
+
+#[test]
+fn user_raw_mutex_gets_the_guards() {
+    let m = TasMutex::new(0,);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..OPS {
+                    *m.lock() += 1;
+                }
+            },);
+        }
+    },);
+    assert_eq!(*m.lock(), 4 * OPS);
+    let guard = m.lock();
+    assert!(m.try_lock().is_none());
+    drop(guard,);
+    assert!(m.try_lock().is_some());
+    assert_eq!(m.raw().unlocks.load(Relaxed,), 4 * OPS + 3);
+}
+
+#[test]
+fn upgradable_read_shares_with_readers_only() {
+    let l = RwLock::new(1,);
+    let up = l.upgradable_read();
+    assert_eq!(*l.read(), 1);
+    assert!(l.try_upgradable_read().is_none());
+    assert!(l.try_write().is_none());
+    let reader = l.read();
+    let up = RwLockUpgradableReadGuard::try_upgrade(up,).err().must();
+    drop(reader,);
+    let mut w = RwLockUpgradableReadGuard::try_upgrade(up,).ok().must();
+    *w += 1;
+    assert!(l.try_read().is_none() && l.try_upgradable_read().is_none());
+    drop(w,);
+    assert_eq!(*l.upgradable_read(), 2);
+}
+
+#[test]
+fn upgrade_waits_for_readers() {
+    let l = RwLock::new(0,);
+    let upgraded = AtomicBool::new(false,);
+    thread::scope(|s| {
+        let reader = l.read();
+        let up = l.upgradable_read();
+        s.spawn(|| {
+            let mut w = RwLockUpgradableReadGuard::upgrade(up,);
+            upgraded.store(true, SeqCst,);
+            *w += 1;
+        },);
+        thread::sleep(Duration::from_millis(if cfg!(miri) { 1 } else { 50 },),);
+        assert!(!upgraded.load(SeqCst,));
+        drop(reader,);
+    },);
+    assert_eq!(*l.read(), 1);
+}
+
+#[test]
+fn upgraders_and_writers_from_many_threads() {
+    let l = RwLock::new(0,);
+    thread::scope(|s| {
+        for t in 0..4 {
+            let l = &l;
+            s.spawn(move || {
+                for i in 0..OPS / 10 {
+                    match (t + i) % 3 {
+                        0 => *l.write() += 1,
+                        1 => {
+                            let up = l.upgradable_read();
+                            let seen = *up;
+                            let mut w = RwLockUpgradableReadGuard::upgrade(up,);
+                            // Nobody wrote in between.
+                            assert_eq!(*w, seen);
+                            *w += 1;
+                        }
+                        _ => assert!(*l.read() <= 4 * OPS),
+                    }
+                }
+            },);
+        }
+    },);
+    let writes =
+        (0..4).map(|t| (0..OPS / 10).filter(|i| (t + i) % 3 != 2,).count(),).sum::<usize>();
+    assert_eq!(*l.read() as usize, writes);
+}
+
+#[test]
+fn debug_shows_the_data_or_locked() {
+    let spin = SpinLock::new(1,);
+    let m = Mutex::new(vec![2],);
+    let l = RwLock::new("three",);
+    assert_eq!(format!("{spin:?}"), "Mutex { data: 1, .. }");
+    assert_eq!(format!("{m:?}"), "Mutex { data: [2], .. }");
+    assert_eq!(format!("{l:?}"), r#"RwLock { data: "three", .. }"#);
+    assert_eq!(format!("{:?}", m.lock()), "[2]");
+    // Readers don't keep it from being printed.
+    let read = l.read();
+    assert_eq!(format!("{l:?}"), r#"RwLock { data: "three", .. }"#);
+    drop(read,);
+}
+
+#[test]
+fn debug_never_blocks() {
+    let spin = SpinLock::new(1,);
+    let m = Mutex::new(2,);
+    let l = RwLock::new(3,);
+    // Every one held by this thread: if printing waited for the lock, it would never return.
+    let _spin = spin.lock();
+    let _m = m.lock();
+    let _l = l.write();
+    assert_eq!(format!("{spin:?}"), "Mutex { data: <locked>, .. }");
+    assert_eq!(format!("{m:?}"), "Mutex { data: <locked>, .. }");
+    assert_eq!(format!("{l:?}"), "RwLock { data: <locked>, .. }");
+}
+
+#[test]
+fn default_from_into_inner_and_get_mut() {
+    let mut spin: SpinLock<Vec<u32,>,> = SpinLock::default();
+    let mut m: Mutex<Vec<u32,>,> = Mutex::default();
+    let mut l: RwLock<Vec<u32,>,> = RwLock::default();
+    spin.get_mut().push(1,);
+    m.get_mut().push(2,);
+    l.get_mut().push(3,);
+    assert_eq!(spin.into_inner(), [1]);
+    assert_eq!(m.into_inner(), [2]);
+    assert_eq!(l.into_inner(), [3]);
+
+    let m = Mutex::from(String::from("m",),);
+    let l: RwLock<_,> = 4.into();
+    assert_eq!(*m.lock(), "m");
+    assert_eq!(l.into_inner(), 4);
+    // Moving the data out leaves nothing behind to drop twice.
+    assert_eq!(m.into_inner(), "m");
+}
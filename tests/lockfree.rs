@@ -0,0 +1,211 @@
+pub mod must;
+use atomics_locks::lockfree::{Queue, Stack};
+use must::Must;
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::SeqCst;
+use std::thread;
+
+/// Histories to check, fewer under miri.
+const ROUNDS: usize = if cfg!(miri) { 5 } else { 500 };
+const THREADS: usize = 3;
+const OPS_PER_THREAD: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash,)]
+enum Op {
+    Add(u32,),
+    Remove(Option<u32,>,),
+}
+
+/// An operation with the clock ticks it was called and returned at. Ticks come from one SeqCst
+/// counter, so if one operation returned before another was called, it also happened before it.
+#[derive(Clone, Copy, Debug,)]
+struct Event {
+    op: Op,
+    call: u64,
+    ret: u64,
+}
+
+/// The sequential specification: applies an operation, and says whether its result fits.
+trait Model: Clone + Eq + std::hash::Hash {
+    fn apply(&mut self, op: Op,) -> bool;
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Hash,)]
+struct StackModel(Vec<u32,>,);
+
+impl Model for StackModel {
+    fn apply(&mut self, op: Op,) -> bool {
+        match op {
+            Op::Add(v,) => {
+                self.0.push(v,);
+                true
+            }
+            Op::Remove(r,) => self.0.pop() == r,
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Hash,)]
+struct QueueModel(VecDeque<u32,>,);
+
+impl Model for QueueModel {
+    fn apply(&mut self, op: Op,) -> bool {
+        match op {
+            Op::Add(v,) => {
+                self.0.push_back(v,);
+                true
+            }
+            Op::Remove(r,) => self.0.pop_front() == r,
+        }
+    }
+}
+
+/// Whether the history has a linearization: an order of the operations that respects real time
+/// (nothing goes before an operation that returned before it was called) and that the model
+/// accepts. A depth-first search over which operation takes effect next, remembering dead ends.
+fn linearizable<M: Model,>(history: &[Event], model: M,) -> bool {
+    fn search<M: Model,>(
+        history: &[Event],
+        done: u64,
+        model: M,
+        dead: &mut HashSet<(u64, M,),>,
+    ) -> bool {
+        if done.count_ones() as usize == history.len() {
+            return true;
+        }
+        if dead.contains(&(done, model.clone(),),) {
+            return false;
+        }
+        let pending = || (0..history.len()).filter(|i| done & (1 << i) == 0,);
+        // Anything called after the earliest pending return can't go first.
+        let first_ret = pending().map(|i| history[i].ret,).min().unwrap_or(u64::MAX,);
+        for i in pending().filter(|&i| history[i].call < first_ret,) {
+            let mut next = model.clone();
+            if next.apply(history[i].op,) && search(history, done | (1 << i), next, dead,) {
+                return true;
+            }
+        }
+        dead.insert((done, model,),);
+        false
+    }
+    search(history, 0, model, &mut HashSet::new(),)
+}
+
+/// Runs a few threads doing random adds and removes on `target`, and returns what happened.
+fn record<S: Sync,>(
+    target: &S,
+    add: impl Fn(&S, u32,) + Sync,
+    remove: impl Fn(&S,) -> Option<u32,> + Sync,
+    seed: u64,
+) -> Vec<Event,> {
+    let clock = AtomicU64::new(0,);
+    thread::scope(|s| {
+        let handles: Vec<_,> = (0..THREADS)
+            .map(|t| {
+                let (clock, add, remove,) = (&clock, &add, &remove,);
+                s.spawn(move || {
+                    let mut rng = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15,) ^ (t as u64 + 1);
+                    let mut events = Vec::new();
+                    for i in 0..OPS_PER_THREAD {
+                        rng ^= rng << 13;
+                        rng ^= rng >> 7;
+                        rng ^= rng << 17;
+                        let call = clock.fetch_add(1, SeqCst,);
+                        let op = if rng % 2 == 0 {
+                            let v = (t * OPS_PER_THREAD + i) as u32;
+                            add(target, v,);
+                            Op::Add(v,)
+                        } else {
+                            Op::Remove(remove(target,),)
+                        };
+                        let ret = clock.fetch_add(1, SeqCst,);
+                        events.push(Event { op, call, ret, },);
+                    }
+                    events
+                },)
+            },)
+            .collect();
+        handles.into_iter().flat_map(|h| h.join().must(),).collect()
+    },)
+}
+
+#[test]
+fn checker_rejects_bad_histories() {
+    let at = |op, call, ret| Event { op, call, ret, };
+    // A pop that returned before the push was even called.
+    let history = [at(Op::Remove(Some(1,),), 0, 1,), at(Op::Add(1,), 2, 3,),];
+    assert!(!linearizable(&history, StackModel::default()));
+    // Overlapping, the push can take effect first.
+    let history = [at(Op::Remove(Some(1,),), 0, 3,), at(Op::Add(1,), 1, 2,),];
+    assert!(linearizable(&history, StackModel::default()));
+    // Two values pushed one after the other come out in the wrong order for a queue.
+    let history =
+        [at(Op::Add(1,), 0, 1,), at(Op::Add(2,), 2, 3,), at(Op::Remove(Some(2,),), 4, 5,),];
+    assert!(!linearizable(&history, QueueModel::default()));
+    assert!(linearizable(&history, StackModel::default()));
+}
+
+#[test]
+fn stack_is_linearizable() {
+    for round in 0..ROUNDS {
+        let stack = Stack::new();
+        let history = record(&stack, Stack::push, Stack::pop, round as u64,);
+        assert!(linearizable(&history, StackModel::default()), "{history:#?}");
+    }
+}
+
+#[test]
+fn queue_is_linearizable() {
+    for round in 0..ROUNDS {
+        let queue = Queue::new();
+        let history = record(&queue, Queue::enqueue, Queue::dequeue, round as u64,);
+        assert!(linearizable(&history, QueueModel::default()), "{history:#?}");
+    }
+}
+
+#[test]
+fn stack_and_queue_under_load() {
+    let per_thread = if cfg!(miri) { 50 } else { 20_000 };
+    let stack = Stack::new();
+    let queue = Queue::new();
+    let popped: Vec<Vec<usize,>,> = thread::scope(|s| {
+        let handles: Vec<_,> = (0..4)
+            .map(|t| {
+                let (stack, queue,) = (&stack, &queue,);
+                s.spawn(move || {
+                    let mut got = Vec::new();
+                    for i in 0..per_thread {
+                        stack.push(t * per_thread + i,);
+                        queue.enqueue(t * per_thread + i,);
+                        got.extend(stack.pop(),);
+                        got.extend(queue.dequeue(),);
+                    }
+                    got
+                },)
+            },)
+            .collect();
+        handles.into_iter().map(|h| h.join().must(),).collect()
+    },);
+    assert!(stack.is_empty() && queue.is_empty());
+    // Everything pushed came out exactly once, from each of them.
+    let mut all: Vec<usize,> = popped.into_iter().flatten().collect();
+    all.sort_unstable();
+    let expected: Vec<usize,> = (0..4 * per_thread).flat_map(|v| [v, v,],).collect();
+    assert_eq!(all, expected);
+}
+
+#[test]
+fn drop_frees_what_is_left() {
+    let stack = Stack::new();
+    let queue = Queue::new();
+    let value = std::sync::Arc::new((),);
+    for _ in 0..10 {
+        stack.push(value.clone(),);
+        queue.enqueue(value.clone(),);
+    }
+    drop(stack.pop(),);
+    drop(queue.dequeue(),);
+    drop((stack, queue,),);
+    assert_eq!(std::sync::Arc::strong_count(&value), 1);
+}
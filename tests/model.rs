@@ -0,0 +1,518 @@
+//! Exhaustive checks of the primitives under the model checker. Run with:
+//!
+//! ```bash
+//! RUSTFLAGS="--cfg model_check" cargo test --release --test model
+//! ```
+#![cfg(model_check)]
+
+use atomics_locks::arc::Arc;
+use atomics_locks::atomic_arc::AtomicArc;
+use atomics_locks::barrier::{Barrier, BarrierError};
+use atomics_locks::biased_arc::{BiasedArc, SharedBiasedArc};
+use atomics_locks::condvar::CondVar;
+use atomics_locks::event::{AutoResetEvent, ManualResetEvent};
+use atomics_locks::latch::{Latch, WaitGroup};
+use atomics_locks::model::{self, thread};
+use atomics_locks::mutex::Mutex;
+use atomics_locks::once::{OnceLock, PanicPolicy};
+use atomics_locks::one_shot_channel::{typed_channel, unsafe_channel};
+use atomics_locks::rwlock::RwLock;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::time::Duration;
+
+#[test]
+fn mutex_counter() {
+    let report = model::check(|| {
+        let m = std::sync::Arc::new(Mutex::new(0,),);
+        let m2 = m.clone();
+        let t = thread::spawn(move || *m2.lock() += 1,);
+        *m.lock() += 1;
+        t.join();
+        assert_eq!(*m.lock(), 2);
+    },);
+    assert!(report.executions > 1);
+}
+
+#[test]
+fn arc_get_mut() {
+    model::check(|| {
+        let mut a = Arc::new(0,);
+        let b = a.clone();
+        let t = thread::spawn(move || {
+            let weak = Arc::downgrade(&b,);
+            let value = *b;
+            drop(b,);
+            (value, weak.upgrade().map(|x| *x,),)
+        },);
+        if let Some(v,) = Arc::get_mut(&mut a,) {
+            *v += 1;
+        }
+        let (seen, upgraded,) = t.join();
+        assert_eq!(seen, 0);
+        assert!(upgraded.is_none_or(|v| v <= 1));
+        assert!(Arc::get_mut(&mut a).is_some());
+    },);
+}
+
+#[test]
+fn arc_drop_from_two_threads() {
+    model::check(|| {
+        let a = Arc::new(String::from("shared",),);
+        let b = a.clone();
+        let t = thread::spawn(move || assert_eq!(b.as_str(), "shared"),);
+        drop(a,);
+        t.join();
+    },);
+}
+
+#[test]
+fn arc_slice_get_mut() {
+    model::check(|| {
+        let mut a: Arc<[u8],> = Arc::from(vec![1, 2],);
+        let b = a.clone();
+        let t = thread::spawn(move || b.iter().sum::<u8>(),);
+        if let Some(v,) = Arc::get_mut(&mut a,) {
+            v[0] = 0;
+        }
+        assert!(matches!(t.join(), 2 | 3));
+    },);
+}
+
+#[test]
+fn arc_into_inner_race() {
+    model::check(|| {
+        let a = Arc::new(String::from("once",),);
+        let b = a.clone();
+        let t = thread::spawn(move || Arc::into_inner(b,),);
+        let mine = Arc::into_inner(a,);
+        let theirs = t.join();
+        assert!(mine.is_some() != theirs.is_some());
+    },);
+}
+
+#[test]
+fn arc_new_cyclic_upgrade() {
+    model::check(|| {
+        let mut reader = None;
+        let a = Arc::new_cyclic(|me| {
+            let me = me.clone();
+            reader = Some(thread::spawn(move || me.upgrade().map(|a| *a,),),);
+            7
+        },);
+        if let Some(reader,) = reader {
+            assert!(reader.join().is_none_or(|v| v == 7));
+        }
+        drop(a,);
+    },);
+}
+
+#[test]
+fn arc_make_mut_with_weak() {
+    model::check(|| {
+        let mut a = Arc::new(1,);
+        let weak = Arc::downgrade(&a,);
+        let t = thread::spawn(move || weak.upgrade().map(|a| *a,),);
+        *Arc::make_mut(&mut a,) += 1;
+        assert_eq!(*a, 2);
+        assert!(t.join().is_none_or(|v| v == 1));
+    },);
+}
+
+#[test]
+fn atomic_arc_load_during_swap() {
+    model::check(|| {
+        let a = std::sync::Arc::new(AtomicArc::new(Arc::new(String::from("old",),),),);
+        let a2 = a.clone();
+        let reader = thread::spawn(move || a2.load().len(),);
+        drop(a.swap(Arc::new(String::from("newer",),),),);
+        assert!(matches!(reader.join(), 3 | 5));
+        assert_eq!(*a.load(), "newer");
+    },);
+}
+
+#[test]
+fn atomic_arc_load_during_two_swaps() {
+    // The second swap may reuse the first one's allocation, and pay a debt owed on it.
+    model::check(|| {
+        let a = std::sync::Arc::new(AtomicArc::new(Arc::new(1,),),);
+        let a2 = a.clone();
+        let reader = thread::spawn(move || *a2.load(),);
+        a.store(Arc::new(2,),);
+        a.store(Arc::new(3,),);
+        assert!(matches!(reader.join(), 1..=3));
+        assert_eq!(*a.load(), 3);
+    },);
+}
+
+#[test]
+fn atomic_arc_rcu() {
+    model::check(|| {
+        let a = std::sync::Arc::new(AtomicArc::new(Arc::new(0,),),);
+        let a2 = a.clone();
+        let t = thread::spawn(move || drop(a2.rcu(|n| n + 1,),),);
+        a.rcu(|n| n + 1,);
+        t.join();
+        assert_eq!(*a.load(), 2);
+    },);
+}
+
+#[test]
+fn biased_arc_owner_and_other_thread() {
+    model::check(|| {
+        let a = BiasedArc::new(String::from("biased",),);
+        let shared = BiasedArc::to_shared(&a,);
+        let t = thread::spawn(move || {
+            let local = SharedBiasedArc::into_local(shared,);
+            local.clone().len()
+        },);
+        let b = a.clone();
+        drop(a,);
+        assert_eq!(b.len(), 6);
+        drop(b,);
+        assert_eq!(t.join(), 6);
+    },);
+}
+
+#[test]
+fn rwlock_write() {
+    model::check(|| {
+        let l = std::sync::Arc::new(RwLock::new((0, 0,),),);
+        let writer = {
+            let l = l.clone();
+            thread::spawn(move || {
+                let mut g = l.write();
+                g.0 += 1;
+                g.1 += 1;
+            },)
+        };
+        let reader = {
+            let l = l.clone();
+            thread::spawn(move || {
+                let g = l.read();
+                assert_eq!(g.0, g.1);
+            },)
+        };
+        {
+            let mut g = l.write();
+            g.0 += 1;
+            g.1 += 1;
+        }
+        writer.join();
+        reader.join();
+        assert_eq!(*l.read(), (2, 2));
+    },);
+}
+
+#[test]
+fn condvar_wait() {
+    model::check(|| {
+        let pair = std::sync::Arc::new((Mutex::new(false,), CondVar::new(),),);
+        let p = pair.clone();
+        let t = thread::spawn(move || {
+            *p.0.lock() = true;
+            p.1.notify_one();
+        },);
+        let mut ready = pair.0.lock();
+        while !*ready {
+            ready = pair.1.wait(ready,);
+        }
+        drop(ready,);
+        t.join();
+    },);
+}
+
+#[test]
+fn condvar_notify_all() {
+    // notify_all wakes one waiter and requeues the other onto the mutex, both have to get out.
+    let report = model::check(|| {
+        let pair = std::sync::Arc::new((Mutex::new(false,), CondVar::new(),),);
+        let waiters: Vec<_,> = (0..2)
+            .map(|_| {
+                let p = pair.clone();
+                thread::spawn(move || {
+                    let mut ready = p.0.lock();
+                    while !*ready {
+                        ready = p.1.wait(ready,);
+                    }
+                },)
+            },)
+            .collect();
+        *pair.0.lock() = true;
+        pair.1.notify_all();
+        for t in waiters {
+            t.join();
+        }
+    },);
+    assert!(report.executions > 1);
+}
+
+#[test]
+fn condvar_wait_timeout() {
+    // Without a notify the only way out is the timeout, which the model takes once nothing else
+    // can run.
+    model::check(|| {
+        let pair = std::sync::Arc::new((Mutex::new(0,), CondVar::new(),),);
+        let p = pair.clone();
+        let t = thread::spawn(move || *p.0.lock() += 1,);
+        let (guard, result,) = pair.1.wait_timeout(pair.0.lock(), Duration::from_secs(1,),);
+        assert!(result.timed_out());
+        drop(guard,);
+        t.join();
+        assert_eq!(*pair.0.lock(), 1);
+    },);
+}
+
+#[test]
+fn barrier_generations() {
+    // Two generations back to back: the second wait must not slip through the first one's wake.
+    let report = model::check(|| {
+        let barrier = std::sync::Arc::new(Barrier::new(2,),);
+        let b = barrier.clone();
+        let leads =
+            |b: &Barrier| (0..2).filter(|_| b.wait().is_ok_and(|r| r.is_leader(),),).count();
+        let t = thread::spawn(move || leads(&b,),);
+        let here = leads(&barrier,);
+        assert_eq!(here + t.join(), 2);
+    },);
+    assert!(report.executions > 1);
+}
+
+#[test]
+fn barrier_timeout_breaks() {
+    // A third participant never comes, so the model takes the timeout once both are asleep, and
+    // the thread still waiting has to be let out.
+    model::check(|| {
+        let barrier = std::sync::Arc::new(Barrier::new(3,),);
+        let b = barrier.clone();
+        let t = thread::spawn(move || b.wait(),);
+        assert_eq!(barrier.wait_timeout(Duration::from_secs(1,),), Err(BarrierError::TimedOut));
+        assert_eq!(t.join(), Err(BarrierError::Broken));
+    },);
+}
+
+#[test]
+fn manual_reset_event_set_reset_set() {
+    // The waiter may look before the first set, between the set and the reset, or after it: any
+    // of them has to let it through by the second set at the latest.
+    let report = model::check(|| {
+        let event = std::sync::Arc::new(ManualResetEvent::new(false,),);
+        let e = event.clone();
+        let t = thread::spawn(move || e.wait(),);
+        event.set();
+        event.reset();
+        event.set();
+        t.join();
+    },);
+    assert!(report.executions > 1);
+}
+
+#[test]
+fn auto_reset_event_one_per_set() {
+    // Two waiters, two sets, each taken before the next: both get through whichever one each set
+    // wakes, and see what was written before their set.
+    use atomics_locks::model::atomic::AtomicU32;
+
+    let report = model::check(|| {
+        let event = std::sync::Arc::new(AutoResetEvent::new(false,),);
+        let data = std::sync::Arc::new(AtomicU32::new(0,),);
+        let threads: Vec<_,> = (0..2)
+            .map(|_| {
+                let (e, d,) = (event.clone(), data.clone(),);
+                thread::spawn(move || {
+                    e.wait();
+                    assert!(d.load(Relaxed,) > 0);
+                },)
+            },)
+            .collect();
+        for n in 1..=2 {
+            data.store(n, Relaxed,);
+            event.set();
+            while event.is_set() {
+                thread::yield_now();
+            }
+        }
+        for t in threads {
+            t.join();
+        }
+    },);
+    assert!(report.executions > 1);
+}
+
+#[test]
+fn latch_count_down_wakes_the_waiter() {
+    // The last count down may land between the waiter's check and its sleep, and what came
+    // before it has to be visible after the wait.
+    use atomics_locks::model::atomic::AtomicU32;
+
+    let report = model::check(|| {
+        let latch = std::sync::Arc::new(Latch::new(2,),);
+        let data = std::sync::Arc::new(AtomicU32::new(0,),);
+        let (l, d,) = (latch.clone(), data.clone(),);
+        let t = thread::spawn(move || {
+            d.store(1, Relaxed,);
+            l.count_down();
+            l.count_down();
+        },);
+        latch.wait();
+        assert_eq!(data.load(Relaxed,), 1);
+        t.join();
+    },);
+    assert!(report.executions > 1);
+}
+
+#[test]
+fn wait_group_handles() {
+    model::check(|| {
+        let group = std::sync::Arc::new(WaitGroup::new(),);
+        group.add(2,);
+        let g = group.clone();
+        let t = thread::spawn(move || g.done(),);
+        group.done();
+        group.wait();
+        assert_eq!(group.count(), 0);
+        t.join();
+    },);
+}
+
+#[test]
+fn once_lock_concurrent_init() {
+    // Both race to initialise: one value wins, and the loser sees it once it's out of the wait.
+    let report = model::check(|| {
+        let cell = std::sync::Arc::new(OnceLock::new(),);
+        let c = cell.clone();
+        let t = thread::spawn(move || *c.get_or_init(|| 1,),);
+        let here = *cell.get_or_init(|| 2,);
+        assert_eq!(here, t.join());
+    },);
+    assert!(report.executions > 1);
+}
+
+#[test]
+fn once_lock_retries_after_a_failed_init() {
+    // The first initialiser fails while the other may be waiting on it: that one has to wake up
+    // and run its own.
+    model::check(|| {
+        let cell = std::sync::Arc::new(OnceLock::with_policy(PanicPolicy::Retry,),);
+        let c = cell.clone();
+        let t = thread::spawn(move || c.get_or_try_init(|| Err::<u32, _,>((),),).is_err(),);
+        assert_eq!(*cell.get_or_init(|| 2,), 2);
+        // Either it failed first, or it found our value.
+        let _ = t.join();
+        assert_eq!(cell.get(), Some(&2));
+    },);
+}
+
+#[test]
+fn unsafe_channel() {
+    model::check(|| {
+        let channel = std::sync::Arc::new(unsafe_channel::Channel::new(),);
+        let c = channel.clone();
+        let t = thread::spawn(move || c.send(String::from("hello world",),),);
+        while !channel.is_ready() {
+            thread::yield_now();
+        }
+        assert_eq!(channel.receive(), "hello world");
+        t.join();
+    },);
+}
+
+#[test]
+fn typed_channel() {
+    model::check(|| {
+        let channel: &'static mut typed_channel::Channel<String,> =
+            Box::leak(Box::new(typed_channel::Channel::new(),),);
+        let (sender, receiver,) = channel.split();
+        let t = thread::spawn(move || sender.send(String::from("hello world",),),);
+        assert_eq!(receiver.receive(), "hello world");
+        t.join();
+    },);
+}
+
+#[test]
+#[should_panic(expected = "data race")]
+fn finds_missing_acquire() {
+    use atomics_locks::model::atomic::AtomicBool;
+    use atomics_locks::model::cell::UnsafeCell;
+
+    struct Flagged {
+        ready: AtomicBool,
+        data: UnsafeCell<u32,>,
+    }
+    // SAFETY: this is the broken type the test expects the model to catch.
+    unsafe impl Sync for Flagged {}
+
+    model::check(|| {
+        let f = std::sync::Arc::new(Flagged {
+            ready: AtomicBool::new(false,),
+            data: UnsafeCell::new(0,),
+        },);
+        let f2 = f.clone();
+        let t = thread::spawn(move || {
+            // SAFETY: only this thread writes, before publishing.
+            unsafe { *f2.data.write_ptr() = 42 };
+            f2.ready.store(true, Release,);
+        },);
+        // Relaxed instead of Acquire: the write above does not happen-before the read below.
+        if f.ready.load(Relaxed,) {
+            // SAFETY: not actually safe, which is the point of the test.
+            assert_eq!(unsafe { *f.data.read_ptr() }, 42);
+        }
+        t.join();
+    },);
+}
+
+#[test]
+fn acquire_release_is_not_a_race() {
+    use atomics_locks::model::atomic::AtomicBool;
+    use atomics_locks::model::cell::UnsafeCell;
+
+    struct Flagged {
+        ready: AtomicBool,
+        data: UnsafeCell<u32,>,
+    }
+    // SAFETY: `data` is only read after `ready` was observed with Acquire.
+    unsafe impl Sync for Flagged {}
+
+    model::check(|| {
+        let f = std::sync::Arc::new(Flagged {
+            ready: AtomicBool::new(false,),
+            data: UnsafeCell::new(0,),
+        },);
+        let f2 = f.clone();
+        let t = thread::spawn(move || {
+            // SAFETY: only this thread writes, before publishing.
+            unsafe { *f2.data.write_ptr() = 42 };
+            f2.ready.store(true, Release,);
+        },);
+        if f.ready.load(Acquire,) {
+            // SAFETY: the Acquire load synchronised with the Release store.
+            assert_eq!(unsafe { *f.data.read_ptr() }, 42);
+        }
+        t.join();
+    },);
+}
+
+#[test]
+#[should_panic(expected = "deadlock")]
+fn finds_deadlock() {
+    model::check(|| {
+        let m = Mutex::new((),);
+        let _a = m.lock();
+        let _b = m.lock();
+    },);
+}
+
+#[test]
+fn exhaustive_without_bound() {
+    let report = model::Builder::new().preemption_bound(None,).check(|| {
+        let m = std::sync::Arc::new(Mutex::new(Vec::new(),),);
+        let m2 = m.clone();
+        let t = thread::spawn(move || m2.lock().push(1,),);
+        m.lock().push(2,);
+        t.join();
+        let v = m.lock().clone();
+        assert!(v == [1, 2] || v == [2, 1]);
+    },);
+    assert!(!report.truncated);
+    assert!(report.executions > 1);
+}
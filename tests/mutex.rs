@@ -1,6 +1,6 @@
-use std::{thread, time::Instant};
+use std::{panic, thread, time::Instant};
 
-use atomics_locks::mutex::Mutex;
+use atomics_locks::mutex::{Mutex, MutexGuard};
 
 #[test]
 fn mutex_attack() {
@@ -28,3 +28,38 @@ fn mutex_attack() {
     let duration = start.elapsed();
     println!("[threaded] locked {} times in {:?}", *m.lock(), duration);
 }
+
+#[test]
+fn guard_knows_its_mutex() {
+    let m = Mutex::new(0,);
+    let g = m.lock();
+    assert!(std::ptr::eq(MutexGuard::mutex(&g,), &m));
+}
+
+#[test]
+fn unlocked_lets_others_in() {
+    let m = Mutex::new(0,);
+    let mut g = m.lock();
+    *g += 1;
+    MutexGuard::unlocked(&mut g, || {
+        thread::scope(|s| {
+            s.spawn(|| *m.lock() += 10,);
+        },);
+    },);
+    assert_eq!(*g, 11);
+    assert!(m.try_lock().is_none());
+}
+
+#[test]
+fn unlocked_locks_again_after_a_panic() {
+    let m = Mutex::new(0,);
+    let mut g = m.lock();
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        MutexGuard::unlocked(&mut g, || panic!("in the closure"),)
+    },),);
+    assert!(result.is_err());
+    assert!(m.try_lock().is_none());
+    *g += 1;
+    drop(g,);
+    assert_eq!(*m.lock(), 1);
+}
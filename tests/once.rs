@@ -0,0 +1,189 @@
+pub mod must;
+use atomics_locks::once::{LazyLock, Once, OnceLock, PanicPolicy};
+use must::Must;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicU32};
+use std::thread;
+use std::time::Duration;
+
+/// Long enough for the other threads to be waiting on the initialiser.
+const SLOW: Duration = Duration::from_millis(if cfg!(miri) { 5 } else { 50 },);
+
+fn panics(f: impl FnOnce(),) -> bool {
+    panic::catch_unwind(AssertUnwindSafe(f,),).is_err()
+}
+
+#[test]
+fn call_once_runs_once() {
+    let once = Once::new();
+    let runs = AtomicU32::new(0,);
+    thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                once.call_once(|| {
+                    thread::sleep(SLOW,);
+                    runs.fetch_add(1, Relaxed,);
+                },);
+                // Nobody gets past before it's done.
+                assert_eq!(runs.load(Relaxed,), 1);
+            },);
+        }
+    },);
+    assert!(once.is_completed());
+}
+
+#[test]
+fn panic_poisons_by_default() {
+    let once = Once::new();
+    assert!(panics(|| once.call_once(|| panic!("init failed"),)));
+    assert!(!once.is_completed());
+    assert!(panics(|| once.call_once(|| {},)));
+    let mut saw_poison = false;
+    once.call_once_force(|state| saw_poison = state.is_poisoned(),);
+    assert!(saw_poison && once.is_completed());
+    once.call_once(|| panic!("already done"),);
+}
+
+#[test]
+fn panic_can_retry_instead() {
+    let once = Once::with_policy(PanicPolicy::Retry,);
+    assert!(panics(|| once.call_once(|| panic!("init failed"),)));
+    let mut ran = false;
+    once.call_once(|| ran = true,);
+    assert!(ran && once.is_completed());
+}
+
+#[test]
+fn concurrent_initialisers_agree() {
+    let cell = OnceLock::new();
+    let runs = AtomicU32::new(0,);
+    thread::scope(|s| {
+        let threads: Vec<_,> = (0..8)
+            .map(|t| {
+                let (cell, runs,) = (&cell, &runs,);
+                s.spawn(move || {
+                    *cell.get_or_init(|| {
+                        runs.fetch_add(1, Relaxed,);
+                        thread::sleep(SLOW,);
+                        t
+                    },)
+                },)
+            },)
+            .collect();
+        let seen: Vec<_,> = threads.into_iter().map(|t| t.join().must(),).collect();
+        assert!(seen.iter().all(|&v| v == seen[0]));
+        assert_eq!(cell.get(), Some(&seen[0]));
+    },);
+    assert_eq!(runs.load(Relaxed,), 1);
+}
+
+#[test]
+fn waiter_takes_over_from_a_panicking_initialiser() {
+    let cell = OnceLock::new();
+    let started = AtomicBool::new(false,);
+    thread::scope(|s| {
+        let first = s.spawn(|| {
+            panics(|| {
+                cell.get_or_init(|| -> u32 {
+                    started.store(true, Relaxed,);
+                    thread::sleep(SLOW,);
+                    panic!("init failed")
+                },);
+            },)
+        },);
+        while !started.load(Relaxed,) {
+            thread::yield_now();
+        }
+        // Waits for the first one, then runs its own.
+        assert_eq!(*cell.get_or_init(|| 2,), 2);
+        assert!(first.join().must());
+    },);
+}
+
+#[test]
+fn waiters_see_the_poison() {
+    let cell = OnceLock::with_policy(PanicPolicy::Poison,);
+    let started = AtomicBool::new(false,);
+    thread::scope(|s| {
+        let first = s.spawn(|| {
+            panics(|| {
+                cell.get_or_init(|| -> u32 {
+                    started.store(true, Relaxed,);
+                    thread::sleep(SLOW,);
+                    panic!("init failed")
+                },);
+            },)
+        },);
+        while !started.load(Relaxed,) {
+            thread::yield_now();
+        }
+        assert!(panics(|| _ = cell.get_or_init(|| 2,)));
+        assert!(first.join().must());
+    },);
+    assert!(cell.get().is_none());
+}
+
+#[test]
+fn once_lock_api() {
+    let mut cell = OnceLock::new();
+    assert_eq!(cell.get_or_try_init(|| Err::<u32, _,>("nope"),), Err("nope"));
+    assert!(cell.get().is_none() && cell.get_mut().is_none());
+    assert_eq!(cell.get_or_try_init(|| Ok::<_, (),>(1,),), Ok(&1));
+    assert_eq!(cell.set(2,), Err(2));
+    *cell.get_mut().must() += 1;
+    assert_eq!(format!("{cell:?}"), "OnceLock(2)");
+    assert_eq!(cell.take(), Some(2));
+    assert_eq!(format!("{cell:?}"), "OnceLock(<uninit>)");
+    assert_eq!(cell.set(3,), Ok(()));
+    assert_eq!(cell.into_inner(), Some(3));
+    assert_eq!(OnceLock::from(4,).into_inner(), Some(4));
+}
+
+#[test]
+fn once_lock_drops_its_value() {
+    let value = Arc::new((),);
+    let cell = OnceLock::new();
+    cell.get_or_init(|| value.clone(),);
+    drop(OnceLock::<Arc<(),>,>::new(),);
+    drop(cell,);
+    assert_eq!(Arc::strong_count(&value), 1);
+}
+
+static RUNS: AtomicU32 = AtomicU32::new(0,);
+static LAZY: LazyLock<Vec<u32,>,> = LazyLock::new(|| {
+    RUNS.fetch_add(1, Relaxed,);
+    vec![1, 2, 3]
+},);
+
+#[test]
+fn lazy_lock_initialises_once() {
+    thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| assert_eq!(LAZY.len(), 3),);
+        }
+    },);
+    assert_eq!(*LazyLock::force(&LAZY,), [1, 2, 3]);
+    assert_eq!(RUNS.load(Relaxed,), 1);
+}
+
+#[test]
+fn lazy_lock_poisons() {
+    let lazy = LazyLock::new(|| -> u32 { panic!("init failed") },);
+    assert!(panics(|| _ = *lazy));
+    assert!(panics(|| _ = *lazy));
+    assert!(panics(|| _ = LazyLock::into_inner(lazy,)));
+}
+
+#[test]
+fn lazy_lock_into_inner() {
+    let lazy = LazyLock::new(|| 5,);
+    assert_eq!(format!("{lazy:?}"), "LazyLock(<uninit>)");
+    let init = LazyLock::into_inner(lazy,).err().must();
+    assert_eq!(init(), 5);
+    let lazy = LazyLock::new(|| 5,);
+    assert_eq!(*lazy, 5);
+    assert_eq!(format!("{lazy:?}"), "LazyLock(5)");
+    assert_eq!(LazyLock::into_inner(lazy,).ok(), Some(5));
+}
@@ -0,0 +1,36 @@
+use std::thread;
+
+use atomics_locks::rwlock::RwLock;
+
+#[test]
+fn rwlock_readers_and_writers() {
+    let l = RwLock::new(0u64,);
+    thread::scope(|s| {
+        for t in 0..4 {
+            let l = &l;
+            s.spawn(move || {
+                for i in 0..2_000 {
+                    if (i + t) % 4 == 0 {
+                        *l.write() += 1;
+                    } else {
+                        let a = *l.read();
+                        let b = *l.read();
+                        assert!(b >= a);
+                    }
+                }
+            },);
+        }
+    },);
+    assert_eq!(*l.read(), 2_000);
+}
+
+#[test]
+fn rwlock_concurrent_readers() {
+    let l = RwLock::new(5,);
+    let r1 = l.read();
+    let r2 = l.read();
+    assert_eq!(*r1 + *r2, 10);
+    drop((r1, r2,),);
+    *l.write() = 6;
+    assert_eq!(*l.read(), 6);
+}
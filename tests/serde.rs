@@ -0,0 +1,130 @@
+#![cfg(feature = "serde")]
+
+pub mod must;
+use atomics_locks::arc::{Arc, shared};
+use atomics_locks::mutex::Mutex;
+use atomics_locks::rwlock::RwLock;
+use atomics_locks::spinlock::SpinLock;
+use must::Must;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize,)]
+struct State {
+    counter: Mutex<u32,>,
+    names: RwLock<Vec<String,>,>,
+    flag: SpinLock<bool,>,
+    config: Arc<String,>,
+}
+
+#[test]
+fn locks_and_arcs_round_trip() {
+    let state = State {
+        counter: Mutex::new(3,),
+        names: RwLock::new(vec!["a".into(), "b".into()],),
+        flag: SpinLock::new(true,),
+        config: Arc::new("on".into(),),
+    };
+    let json = serde_json::to_string(&state,).must();
+    assert_eq!(json, r#"{"counter":3,"names":["a","b"],"flag":true,"config":"on"}"#);
+    let back: State = serde_json::from_str(&json,).must();
+    assert_eq!(*back.counter.lock(), 3);
+    assert_eq!(*back.names.read(), ["a", "b"]);
+    assert!(*back.flag.lock());
+    assert_eq!(*back.config, "on");
+    // Readers don't keep the lock from being serialised.
+    let _read = state.names.read();
+    assert_eq!(serde_json::to_string(&state.names,).must(), r#"["a","b"]"#);
+}
+
+#[test]
+fn unsized_arcs_round_trip() {
+    let s: Arc<str,> = serde_json::from_str(r#""text""#,).must();
+    let slice: Arc<[u8],> = serde_json::from_str("[1,2,3]",).must();
+    assert_eq!(&*s, "text");
+    assert_eq!(&*slice, [1, 2, 3]);
+    assert_eq!(serde_json::to_string(&slice,).must(), "[1,2,3]");
+}
+
+#[derive(Serialize, Deserialize,)]
+struct Graph {
+    #[serde(with = "atomics_locks::arc::shared")]
+    left: Arc<Mutex<u32,>,>,
+    #[serde(with = "atomics_locks::arc::shared")]
+    right: Arc<Mutex<u32,>,>,
+    #[serde(with = "atomics_locks::arc::shared")]
+    other: Arc<Mutex<u32,>,>,
+}
+
+#[test]
+fn shared_arcs_keep_their_identity() {
+    let node = Arc::new(Mutex::new(1,),);
+    let graph = Graph { left: node.clone(), right: node, other: Arc::new(Mutex::new(2,),), };
+    let json = shared::scope(|| serde_json::to_string(&graph,),).must();
+    assert_eq!(json, r#"{"left":{"Def":[0,1]},"right":{"Ref":0},"other":{"Def":[1,2]}}"#);
+
+    let back: Graph = shared::scope(|| serde_json::from_str(&json,),).must();
+    assert!(Arc::ptr_eq(&back.left, &back.right));
+    assert!(!Arc::ptr_eq(&back.left, &back.other));
+    *back.left.lock() += 10;
+    assert_eq!(*back.right.lock(), 11);
+    assert_eq!(*back.other.lock(), 2);
+    // Nothing is kept once the scope is over.
+    assert_eq!(Arc::strong_count(&back.left), 2);
+
+    // Ids start over in every scope.
+    let again = shared::scope(|| serde_json::to_string(&graph,),).must();
+    assert_eq!(again, json);
+}
+
+#[test]
+fn shared_arcs_need_a_scope_and_their_definition() {
+    let node = Arc::new(Mutex::new(1,),);
+    let graph = Graph { left: node.clone(), right: node.clone(), other: node, };
+    assert!(serde_json::to_string(&graph,).is_err());
+    assert!(
+        serde_json::from_str::<Graph,>(
+            r#"{"left":{"Def":[0,1]},"right":{"Ref":0},"other":{"Ref":0}}"#
+        )
+        .is_err()
+    );
+
+    let dangling = r#"{"left":{"Def":[0,1]},"right":{"Ref":1},"other":{"Ref":0}}"#;
+    assert!(shared::scope(|| serde_json::from_str::<Graph,>(dangling,),).is_err());
+    let twice = r#"{"left":{"Def":[0,1]},"right":{"Def":[0,1]},"other":{"Ref":0}}"#;
+    assert!(shared::scope(|| serde_json::from_str::<Graph,>(twice,),).is_err());
+}
+
+#[test]
+fn shared_temporaries_get_their_own_ids() {
+    // Each `Arc` is gone before the next is made, so the allocator may hand out the same address:
+    // the scope must not take the second for the first.
+    let snapshot = |n: u32| Arc::new(Mutex::new(n,),);
+    let (first, second,) = shared::scope(|| {
+        let first = serde_json::to_string(&shared::Ser(&snapshot(1,),),);
+        (first, serde_json::to_string(&shared::Ser(&snapshot(2,),),),)
+    },);
+    assert_eq!(first.must(), r#"{"Def":[0,1]}"#);
+    assert_eq!(second.must(), r#"{"Def":[1,2]}"#);
+}
+
+#[derive(Serialize, Deserialize,)]
+struct Nulls {
+    #[serde(with = "atomics_locks::arc::shared")]
+    none: Arc<Option<u32,>,>,
+    #[serde(with = "atomics_locks::arc::shared")]
+    again: Arc<Option<u32,>,>,
+    #[serde(with = "atomics_locks::arc::shared")]
+    unit: Arc<(),>,
+}
+
+#[test]
+fn shared_values_that_serialise_as_null() {
+    // A definition of `None` or `()` must not read back as a back-reference.
+    let none = Arc::new(None,);
+    let nulls = Nulls { none: none.clone(), again: none, unit: Arc::new((),), };
+    let json = shared::scope(|| serde_json::to_string(&nulls,),).must();
+    assert_eq!(json, r#"{"none":{"Def":[0,null]},"again":{"Ref":0},"unit":{"Def":[1,null]}}"#);
+    let back: Nulls = shared::scope(|| serde_json::from_str(&json,),).must();
+    assert!(Arc::ptr_eq(&back.none, &back.again));
+    assert_eq!(*back.none, None);
+}
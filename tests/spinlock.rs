@@ -1,6 +1,7 @@
 use std::thread;
 
-use atomics_locks::spinlock::SpinLock;
+use atomics_locks::lock_api::RawMutex;
+use atomics_locks::spinlock::{Guard, SpinLock};
 
 #[test]
 fn spinlock() {
@@ -16,3 +17,26 @@ fn spinlock() {
     let g = x.lock();
     assert!(g.as_slice() == [1, 2, 2] || g.as_slice() == [2, 2, 1]);
 }
+
+#[test]
+fn leaked_guard_keeps_the_lock_until_forced() {
+    let x = SpinLock::new(1,);
+    let value = Guard::leak(x.lock(),);
+    *value += 1;
+    assert!(x.try_lock().is_none());
+    // SAFETY: the leaked guard held the lock, and `value` isn't used again.
+    unsafe { x.force_unlock() };
+    assert_eq!(*x.lock(), 2);
+}
+
+#[test]
+fn raw_lock_and_data_ptr() {
+    let x = SpinLock::new(1,);
+    x.raw().lock();
+    // SAFETY: we hold the lock through `raw`, nobody else touches the value.
+    unsafe { *x.data_ptr() += 1 };
+    assert!(x.try_lock().is_none());
+    // SAFETY: taken through `raw` above, and the pointer isn't used again.
+    unsafe { x.force_unlock() };
+    assert_eq!(*x.lock(), 2);
+}
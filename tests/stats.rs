@@ -0,0 +1,96 @@
+#![cfg(feature = "stats")]
+
+pub mod must;
+use must::Must;
+use std::thread;
+use std::time::Duration;
+
+use atomics_locks::mutex::Mutex;
+use atomics_locks::rwlock::RwLock;
+use atomics_locks::spinlock::SpinLock;
+use atomics_locks::stats;
+
+#[test]
+fn mutex_stats() {
+    let m = Mutex::new(0,);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..10_000 {
+                    *m.lock() += 1;
+                }
+            },);
+        }
+    },);
+    let st = m.stats();
+    assert_eq!(st.acquisitions, 40_000);
+    assert!(st.contended <= st.acquisitions);
+
+    m.reset_stats();
+    assert_eq!(m.stats(), stats::LockStats::default());
+}
+
+#[test]
+fn hold_time() {
+    let l = SpinLock::new((),);
+    {
+        let _g = l.lock();
+        thread::sleep(Duration::from_millis(20,),);
+    }
+    assert!(l.stats().max_hold >= Duration::from_millis(20));
+    assert_eq!(l.stats().acquisitions, 1);
+}
+
+#[test]
+fn read_hold_time() {
+    let l = RwLock::new((),);
+    thread::scope(|s| {
+        let first = l.read();
+        s.spawn(|| {
+            let _second = l.read();
+            thread::sleep(Duration::from_millis(20,),);
+        },)
+            .join()
+            .must();
+        // Still read-locked: the hold lasts until the last reader is out.
+        thread::sleep(Duration::from_millis(10,),);
+        drop(first,);
+    },);
+    let st = l.stats();
+    assert_eq!(st.acquisitions, 2);
+    assert!(st.max_hold >= Duration::from_millis(30));
+}
+
+#[test]
+fn contended_wait_is_counted() {
+    let l = RwLock::new(0,);
+    thread::scope(|s| {
+        let g = l.write();
+        s.spawn(|| *l.read(),);
+        thread::sleep(Duration::from_millis(50,),);
+        drop(g,);
+    },);
+    let st = l.stats();
+    assert_eq!(st.acquisitions, 2);
+    assert_eq!(st.contended, 1);
+    assert!(st.total_wait > Duration::ZERO);
+}
+
+#[test]
+fn registry() {
+    static HOT: Mutex<u32,> = Mutex::new(0,);
+    stats::register("hot", &HOT,);
+    let shared = std::sync::Arc::new(SpinLock::new(0,),);
+    stats::register_shared("shared", &shared,);
+
+    *HOT.lock() += 1;
+    *shared.lock() += 1;
+
+    let report = stats::report();
+    assert!(report.iter().any(|(name, s,)| name == "hot" && s.acquisitions >= 1));
+    assert!(report.iter().any(|(name, s,)| name == "shared" && s.acquisitions == 1));
+    assert!(stats::contention_report().contains("hot"));
+
+    drop(shared,);
+    assert!(!stats::report().iter().any(|(name, _,)| name == "shared"));
+}
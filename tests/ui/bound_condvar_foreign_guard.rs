@@ -0,0 +1,10 @@
+// A bound condvar only waits with guards from its own `lock`, not of some other mutex.
+use atomics_locks::condvar::CondVar;
+use atomics_locks::mutex::Mutex;
+
+fn main() {
+    let a = Mutex::new((),);
+    let b = Mutex::new((),);
+    let c = CondVar::for_mutex(&a,);
+    let _ = c.wait(b.lock(),);
+}
@@ -0,0 +1,8 @@
+// Unlocking without a guard is up to the caller to get right.
+use atomics_locks::spinlock::SpinLock;
+
+fn main() {
+    let lock = SpinLock::new(0,);
+    let _guard = lock.lock();
+    lock.force_unlock();
+}
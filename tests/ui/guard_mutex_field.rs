@@ -0,0 +1,8 @@
+// The field is private, the reference can't be copied out of the guard.
+use atomics_locks::mutex::Mutex;
+
+fn main() {
+    let m = Mutex::new(0,);
+    let guard = m.lock();
+    let _mutex = guard.mutex;
+}
@@ -0,0 +1,11 @@
+// The guard's mutex is only lent out for as long as the guard lives.
+use atomics_locks::mutex::{Mutex, MutexGuard};
+
+fn main() {
+    let m = Mutex::new(0,);
+    let mutex = {
+        let guard = m.lock();
+        MutexGuard::mutex(&guard,)
+    };
+    drop(mutex,);
+}
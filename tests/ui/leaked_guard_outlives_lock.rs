@@ -0,0 +1,10 @@
+// A leaked guard's reference borrows the lock, it can't outlive it.
+use atomics_locks::spinlock::{Guard, SpinLock};
+
+fn main() {
+    let value = {
+        let lock = SpinLock::new(0,);
+        Guard::leak(lock.lock(),)
+    };
+    *value += 1;
+}
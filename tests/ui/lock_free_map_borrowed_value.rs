@@ -0,0 +1,9 @@
+// What the map replaces is dropped by the epoch collector later on, so it can't borrow anything.
+use atomics_locks::hash_map::LockFreeHashMap;
+
+fn main() {
+    let s = String::from("borrowed",);
+    let map = LockFreeHashMap::new();
+    map.insert(1, &s,);
+    map.insert(1, &s,);
+}
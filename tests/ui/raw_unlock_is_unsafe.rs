@@ -0,0 +1,9 @@
+// Going through the raw lock doesn't get around it.
+use atomics_locks::lock_api::RawMutex;
+use atomics_locks::spinlock::SpinLock;
+
+fn main() {
+    let lock = SpinLock::new(0,);
+    let _guard = lock.lock();
+    lock.raw().unlock();
+}
@@ -0,0 +1,10 @@
+// A second guard for a lock that's already held would alias the first one's `&mut`.
+use atomics_locks::spinlock::{Guard, SpinLock};
+
+fn main() {
+    let lock = SpinLock::new(0,);
+    let mut a = lock.lock();
+    let mut b = Guard::new(&lock,);
+    *a += 1;
+    *b += 1;
+}
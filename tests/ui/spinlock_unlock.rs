@@ -0,0 +1,9 @@
+// Anyone could release a lock someone else's guard still holds.
+use atomics_locks::spinlock::SpinLock;
+
+fn main() {
+    let lock = SpinLock::new(0,);
+    let guard = lock.lock();
+    lock.unlock();
+    drop(guard,);
+}
@@ -0,0 +1,66 @@
+// Under `cfg(model_check)` backends take the model's `AtomicU32`, and this would need a model run.
+#![cfg(not(model_check))]
+
+use atomics_locks::lock_api::{Mutex, RwLock};
+use atomics_locks::mutex::RawFutexMutex;
+use atomics_locks::rwlock::RawFutexRwLock;
+use atomics_locks::wait::{Spin, WaitBackend};
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+use std::thread;
+use std::time::Duration;
+
+/// Increments per thread, fewer under miri.
+const OPS: u64 = if cfg!(miri) { 100 } else { 100_000 };
+
+static WAITS: AtomicU32 = AtomicU32::new(0,);
+static WAKES: AtomicU32 = AtomicU32::new(0,);
+
+/// Yields until the value changes, and counts what it is asked to do.
+struct Counting;
+
+impl WaitBackend for Counting {
+    fn wait(atomic: &AtomicU32, expected: u32,) {
+        WAITS.fetch_add(1, SeqCst,);
+        while atomic.load(Relaxed,) == expected {
+            thread::yield_now();
+        }
+    }
+
+    fn wake(_: &AtomicU32, _: u32,) {
+        WAKES.fetch_add(1, SeqCst,);
+    }
+}
+
+#[test]
+fn spin_backend_locks() {
+    let m: Mutex<RawFutexMutex<Spin,>, u64,> = Mutex::new(0,);
+    let l: RwLock<RawFutexRwLock<Spin,>, u64,> = RwLock::new(0,);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..OPS {
+                    *m.lock() += 1;
+                    *l.write() += 1;
+                    assert!(*l.read() > 0);
+                }
+            },);
+        }
+    },);
+    assert_eq!(*m.lock(), 4 * OPS);
+    assert_eq!(*l.read(), 4 * OPS);
+}
+
+#[test]
+fn contended_lock_goes_through_the_backend() {
+    let m: Mutex<RawFutexMutex<Counting,>, (),> = Mutex::new((),);
+    thread::scope(|s| {
+        let guard = m.lock();
+        s.spawn(|| drop(m.lock(),),);
+        // Long enough for the other thread to give up spinning and wait.
+        thread::sleep(Duration::from_millis(100,),);
+        drop(guard,);
+    },);
+    assert!(WAITS.load(SeqCst,) > 0);
+    assert!(WAKES.load(SeqCst,) > 0);
+}